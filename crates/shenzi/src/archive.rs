@@ -0,0 +1,270 @@
+// packing a dist into a single archive
+// the dist depends on relative symlinks (symlink farms, `lib/l`, binaries in site-packages pointing into `reals/r`), they are stored as symlinks
+// files hardlinked to each other (`build --dedup`) are stored once, the other names are hard link entries
+// the archive is reproducible: entries are sorted, ownership is root, timestamps are fixed and permissions are normalized
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::Write,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, anyhow};
+use flate2::{Compression, GzBuilder};
+use log::info;
+use tar::{Builder, EntryType, Header};
+use walkdir::WalkDir;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    #[value(name = "tar.gz")]
+    TarGz,
+    #[value(name = "tar.zst")]
+    TarZst,
+}
+
+impl ArchiveFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ArchiveFormat::TarGz => "tar.gz",
+            ArchiveFormat::TarZst => "tar.zst",
+        }
+    }
+}
+
+/// `dist.tar.gz` next to `dist`
+pub fn archive_path(dist: &Path, format: ArchiveFormat) -> PathBuf {
+    let name = dist
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| "dist".to_string());
+    dist.with_file_name(format!("{}.{}", name, format.extension()))
+}
+
+/// write `dist` into an archive at `out`, every entry is inside a top level directory named after `dist`
+pub fn write_archive(dist: &Path, out: &Path, format: ArchiveFormat) -> Result<()> {
+    info!(
+        "writing {} archive of {} to {}",
+        format.extension(),
+        dist.display(),
+        out.display()
+    );
+    let file = fs::File::create(out)
+        .with_context(|| anyhow!("failed in creating archive at {}", out.display()))?;
+    match format {
+        ArchiveFormat::TarGz => {
+            // the gzip header has no timestamp or file name by default
+            let encoder = GzBuilder::new().write(file, Compression::default());
+            append_dist(dist, encoder)?.finish()?;
+        }
+        ArchiveFormat::TarZst => {
+            let encoder = zstd::Encoder::new(file, 0)?;
+            append_dist(dist, encoder)?.finish()?;
+        }
+    };
+    Ok(())
+}
+
+/// write the tar stream of `dist` into `w`, returns `w` so that the caller can finish the compression
+pub fn append_dist<W: Write>(dist: &Path, w: W) -> Result<W> {
+    let top = PathBuf::from(dist.file_name().unwrap_or(std::ffi::OsStr::new("dist")));
+    let mut builder = Builder::new(w);
+    append_tree(&mut builder, dist, &top)?;
+    builder
+        .into_inner()
+        .context("failed in finishing the tar archive")
+}
+
+/// a header owned by root with a fixed timestamp
+pub fn normalized_header(entry_type: EntryType, mode: u32, size: u64) -> Result<Header> {
+    let mut header = Header::new_gnu();
+    header.set_uid(0);
+    header.set_gid(0);
+    header.set_mtime(0);
+    header.set_username("root")?;
+    header.set_groupname("root")?;
+    header.set_entry_type(entry_type);
+    header.set_mode(mode);
+    header.set_size(size);
+    Ok(header)
+}
+
+pub fn append_directory<W: Write>(builder: &mut Builder<W>, name: &Path) -> Result<()> {
+    let mut header = normalized_header(EntryType::Directory, 0o755, 0)?;
+    builder
+        .append_data(&mut header, name, std::io::empty())
+        .with_context(|| anyhow!("failed in archiving directory {}", name.display()))
+}
+
+pub fn append_bytes<W: Write>(
+    builder: &mut Builder<W>,
+    name: &Path,
+    mode: u32,
+    contents: &[u8],
+) -> Result<()> {
+    let mut header = normalized_header(EntryType::Regular, mode, contents.len() as u64)?;
+    builder
+        .append_data(&mut header, name, contents)
+        .with_context(|| anyhow!("failed in archiving {}", name.display()))
+}
+
+/// append every entry of `dir` (and `dir` itself) under `prefix`, sorted by name
+pub fn append_tree<W: Write>(builder: &mut Builder<W>, dir: &Path, prefix: &Path) -> Result<()> {
+    // (dev, ino) of files with several links -> their first name in the archive
+    let mut archived: HashMap<(u64, u64), PathBuf> = HashMap::new();
+    for entry in WalkDir::new(dir).sort_by_file_name() {
+        let entry = entry.with_context(|| anyhow!("failed in walking {}", dir.display()))?;
+        let rel_path = entry
+            .path()
+            .strip_prefix(dir)
+            .expect("fatal: walked path is not inside dir");
+        // joining an empty path would add a trailing slash
+        let name = if rel_path.as_os_str().is_empty() {
+            prefix.to_path_buf()
+        } else {
+            prefix.join(rel_path)
+        };
+
+        let file_type = entry.file_type();
+        if file_type.is_symlink() {
+            let target = fs::read_link(entry.path())
+                .with_context(|| anyhow!("failed in reading symlink {}", entry.path().display()))?;
+            let mut header = normalized_header(EntryType::Symlink, 0o777, 0)?;
+            builder
+                .append_link(&mut header, &name, &target)
+                .with_context(|| {
+                    anyhow!("failed in archiving symlink {}", entry.path().display())
+                })?;
+        } else if file_type.is_dir() {
+            append_directory(builder, &name)?;
+        } else {
+            let meta = entry.metadata().with_context(|| {
+                anyhow!("failed in reading metadata of {}", entry.path().display())
+            })?;
+            if meta.nlink() > 1 {
+                if let Some(first) = archived.get(&(meta.dev(), meta.ino())) {
+                    let mut header = normalized_header(EntryType::Link, normalized_mode(&meta), 0)?;
+                    builder
+                        .append_link(&mut header, &name, first)
+                        .with_context(|| {
+                            anyhow!("failed in archiving hard link {}", entry.path().display())
+                        })?;
+                    continue;
+                }
+                archived.insert((meta.dev(), meta.ino()), name.clone());
+            }
+            let mut header =
+                normalized_header(EntryType::Regular, normalized_mode(&meta), meta.len())?;
+            let file = fs::File::open(entry.path())
+                .with_context(|| anyhow!("failed in opening {}", entry.path().display()))?;
+            builder
+                .append_data(&mut header, &name, file)
+                .with_context(|| anyhow!("failed in archiving {}", entry.path().display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// executables stay executable for everyone, everything else is readable by everyone
+fn normalized_mode(meta: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    if meta.permissions().mode() & 0o111 != 0 {
+        0o755
+    } else {
+        0o644
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_archive_keeps_symlinks() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dist = tmp.path().join("dist");
+        fs::create_dir_all(dist.join("reals").join("r")).unwrap();
+        fs::write(dist.join("reals").join("r").join("abcd_lib.so"), "lib").unwrap();
+        fs::create_dir_all(dist.join("lib").join("l")).unwrap();
+        std::os::unix::fs::symlink(
+            "../../reals/r/abcd_lib.so",
+            dist.join("lib").join("l").join("lib.so"),
+        )
+        .unwrap();
+
+        let out = archive_path(&dist, ArchiveFormat::TarGz);
+        assert_eq!(out, tmp.path().join("dist.tar.gz"));
+        write_archive(&dist, &out, ArchiveFormat::TarGz).unwrap();
+
+        let mut archive =
+            tar::Archive::new(flate2::read::GzDecoder::new(fs::File::open(&out).unwrap()));
+        let mut seen = Vec::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let path = entry.path().unwrap().to_path_buf();
+            assert_eq!(entry.header().uid().unwrap(), 0);
+            assert_eq!(entry.header().mtime().unwrap(), 0);
+            if path.as_path() == Path::new("dist/lib/l/lib.so") {
+                assert_eq!(entry.header().entry_type(), EntryType::Symlink);
+                assert_eq!(
+                    entry.link_name().unwrap().unwrap().to_path_buf(),
+                    PathBuf::from("../../reals/r/abcd_lib.so")
+                );
+            }
+            if path.as_path() == Path::new("dist/reals/r/abcd_lib.so") {
+                let mut contents = String::new();
+                entry.read_to_string(&mut contents).unwrap();
+                assert_eq!(contents, "lib");
+                assert_eq!(entry.header().mode().unwrap(), 0o644);
+            }
+            seen.push(path);
+        }
+        // sorted, parents before children
+        assert_eq!(seen[0], PathBuf::from("dist"));
+        assert!(seen.contains(&PathBuf::from("dist/lib/l/lib.so")));
+        assert!(seen.contains(&PathBuf::from("dist/reals/r/abcd_lib.so")));
+    }
+
+    #[test]
+    fn test_archive_keeps_hard_links() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dist = tmp.path().join("dist");
+        fs::create_dir_all(&dist).unwrap();
+        fs::write(dist.join("a.ttf"), "font").unwrap();
+        fs::hard_link(dist.join("a.ttf"), dist.join("b.ttf")).unwrap();
+
+        let out = archive_path(&dist, ArchiveFormat::TarGz);
+        write_archive(&dist, &out, ArchiveFormat::TarGz).unwrap();
+
+        let mut archive =
+            tar::Archive::new(flate2::read::GzDecoder::new(fs::File::open(&out).unwrap()));
+        let entries: Vec<(PathBuf, EntryType, Option<PathBuf>)> = archive
+            .entries()
+            .unwrap()
+            .map(|e| {
+                let e = e.unwrap();
+                let link = e.link_name().unwrap().map(|l| l.to_path_buf());
+                (
+                    e.path().unwrap().to_path_buf(),
+                    e.header().entry_type(),
+                    link,
+                )
+            })
+            .collect();
+        assert_eq!(
+            entries[1],
+            (PathBuf::from("dist/a.ttf"), EntryType::Regular, None)
+        );
+        assert_eq!(
+            entries[2],
+            (
+                PathBuf::from("dist/b.ttf"),
+                EntryType::Link,
+                Some(PathBuf::from("dist/a.ttf"))
+            )
+        );
+    }
+}
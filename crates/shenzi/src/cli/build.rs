@@ -1,109 +1,534 @@
 use anyhow::{Context, Result, anyhow, bail};
-use log::info;
-use std::{fs, io::Read};
+use log::{info, warn};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+use tempfile::TempDir;
 
 use crate::{
-    gather::build_graph_from_manifest,
-    manifest::{Bin, ShenziManifest},
-    paths::marker_file_path,
-    pkg::{bootstrap::write_bootstrap_script, move_all_nodes, write_warnings},
+    archive::{ArchiveFormat, archive_path, write_archive},
+    cli::common::{Gathered, ManifestOptions, gather},
+    deb::{deb_path, write_deb},
+    node::Node,
+    oci::{oci_path, write_oci},
+    paths::{absolute_path, marker_file_path},
+    pkg::{
+        ExportOptions, Exported,
+        bootstrap::write_bootstrap_script,
+        bytecode::BytecodeMode,
+        copy::CopyMode,
+        dedup::dedup_plain_files,
+        incremental, move_all_nodes, move_nodes,
+        plan::plan_dist,
+        record::{BuildRecord, NodeRecord, build_record_path},
+        staging::StagedDist,
+        strip::StripMode,
+        warnings_file_path, write_warnings,
+        zipimport::zip_site_packages,
+    },
+    report::BuildReport,
+    self_extracting::{self_extracting_path, write_self_extracting},
     warnings::validate_warnings,
-    workspace::InitializedShenziWorkspace,
+    workspace::{InitializedShenziWorkspace, Metadata, workspace_file_path},
 };
 
-pub fn run(manifest: &str, skip_warning_checks: bool) -> Result<()> {
-    let dist = std::env::current_dir().unwrap().join("dist");
-    if dist.exists() {
-        bail!(
-            "the folder `dist` already exists in the current directory, delete it and call `shenzi` again"
-        );
+#[derive(clap::Args, Debug)]
+pub struct BuildArgs {
+    /// manifest file path, use `-` to take input from stdio (or when you are piping)
+    pub manifest: String,
+
+    #[command(flatten)]
+    pub manifest_options: ManifestOptions,
+
+    /// Skip validation of warnings, you should generally not skip this, although warnings validation can take a long time.
+    /// You can skip this if you are running shenzi multiple times and are confident that there were no warnings in the first invocation.
+    #[arg(long, default_value_t = false)]
+    pub skip_warning_checks: bool,
+
+    /// Directory where the distribution is written
+    #[arg(long, default_value = "dist")]
+    pub out: PathBuf,
+
+    /// Replace the output directory if it already exists. shenzi only replaces directories that it created itself.
+    #[arg(long, default_value_t = false)]
+    pub force: bool,
+
+    /// Reuse the distribution in the output directory, only files which changed since the previous build are exported again.
+    /// Falls back to a full build if the output directory does not have a build record.
+    #[arg(long, default_value_t = false)]
+    pub incremental: bool,
+
+    /// Do not write anything, print the planned layout of the distribution as JSON (targets of every file, patch operations and PYTHONPATH)
+    #[arg(long, default_value_t = false)]
+    pub dry_run: bool,
+
+    /// Also pack the distribution into an archive next to the output directory (`dist.tar.gz` for `--out dist`).
+    /// Symlinks are preserved, ownership, timestamps and permissions are normalized.
+    #[arg(long)]
+    pub archive: Option<ArchiveFormat>,
+
+    /// Also write the distribution as a single executable file next to the output directory (`dist.run` for `--out dist`).
+    /// It extracts itself into a cache directory on the first run and then runs `bootstrap.sh`.
+    #[arg(long, default_value_t = false)]
+    pub self_extracting: bool,
+
+    /// What to produce. `dir` is the distribution directory.
+    /// `deb` also writes a debian package next to it which installs the distribution in `/opt/<name>` with a `/usr/bin/<name>` wrapper,
+    /// name, version, maintainer and description are taken from the `[metadata]` table of the shenzi workspace file.
+    /// `oci` also writes an OCI image layout directory next to it (`dist.oci` for `--out dist`), with the distribution in `/app`.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Dir)]
+    pub format: OutputFormat,
+
+    /// OCI image layout used as the base of the image, required for `--format oci`. It has to provide bash and the system libraries.
+    /// Create one with `skopeo copy docker://debian:stable-slim oci:base`
+    #[arg(long)]
+    pub oci_base: Option<PathBuf>,
+
+    /// Strip the copies of shared libraries and executables in the distribution (the original files are never touched).
+    /// `default` removes debug sections, `all` also removes symbols which are not needed for dynamic linking. `--strip` alone is `--strip default`
+    #[arg(long, value_enum, default_value_t = StripMode::None, num_args = 0..=1, default_missing_value = "default")]
+    pub strip: StripMode,
+
+    /// With `--strip`, collect the debug info of stripped ELF files into an archive next to the output directory (`dist.debug.tar.gz` for `--out dist`), for symbolizing crashes
+    #[arg(long, default_value_t = false)]
+    pub debug_archive: bool,
+
+    /// Compile the stdlib and site-packages to bytecode with the packaged interpreter, it has to run on the build machine.
+    /// `compile` writes `__pycache__` next to the sources, `sourceless` writes legacy `.pyc` files and removes the `.py` sources. `--bytecode` alone is `--bytecode compile`
+    #[arg(long, value_enum, default_value_t = BytecodeMode::None, num_args = 0..=1, default_missing_value = "compile")]
+    pub bytecode: BytecodeMode,
+
+    /// Pack the site-packages without shared libraries into one zip per site-packages directory, python imports them from the zip.
+    /// Packages listed in `unzippable` of the shenzi workspace file stay directories. Incremental builds with this flag are full builds, it needs `--bytecode sourceless` to ship bytecode
+    #[arg(long, default_value_t = false)]
+    pub zip_packages: bool,
+
+    /// How files are copied from the environment into dist. `reflink` clones files on copy on write filesystems (btrfs, XFS, APFS),
+    /// `hardlink` links files which are not modified in dist, `auto` tries both. Every mode falls back to a plain copy per file
+    #[arg(long, value_enum, default_value_t = CopyMode::Copy)]
+    pub copy_mode: CopyMode,
+
+    /// Store plain files with the same contents (data files in several site-packages) once, every copy is a hardlink to it.
+    /// `build-report.json` lists the deduplicated files and the bytes saved
+    #[arg(long, default_value_t = false)]
+    pub dedup: bool,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Dir,
+    Deb,
+    Oci,
+}
+
+pub fn run(args: &BuildArgs) -> Result<()> {
+    if args.dry_run {
+        return dry_run(args);
+    }
+    // fail before building anything if the package can't be made
+    let metadata = package_metadata(args)?;
+    if args.format == OutputFormat::Oci {
+        oci_base(args)?;
     }
-    fs::create_dir_all(&dist).context(anyhow!("failed in creating dist at {}", dist.display()))?;
-    let shenzi_marker = marker_file_path(&dist);
-    fs::File::create(&shenzi_marker)
-        .context(anyhow!("failed to create SHENZI marker file in dist"))?;
+    check_flags(args)?;
+    let target = absolute_path(&args.out)?;
+    let previous = if args.incremental {
+        read_previous_record(&target)
+    } else {
+        None
+    };
+    // an incremental build which falls back to a full build replaces the output without --force,
+    // but only if a previous shenzi build is in it (a failed incremental build removes the record, the marker stays)
+    let replace = args.force
+        || (args.incremental && (previous.is_some() || marker_file_path(&target).exists()));
+    // zipped packages are not in dist anymore, an incremental build has nothing to reuse
+    // the debug archive of an incremental build would only have the debug info of the changed nodes
+    if args.incremental && !args.zip_packages && !args.debug_archive {
+        match previous {
+            Some(previous)
+                if previous.bytecode == args.bytecode
+                    && !previous.zip_packages
+                    && previous.strip == args.strip
+                    && previous.debug_archive == args.debug_archive =>
+            {
+                return incremental_build(args, &metadata, &target, previous);
+            }
+            Some(_) => {
+                info!(
+                    "previous build in {} used a different --bytecode, --zip-packages, --strip or --debug-archive, doing a full build",
+                    target.display()
+                );
+            }
+            None => {
+                info!(
+                    "no usable build record found in {}, doing a full build",
+                    target.display()
+                );
+            }
+        }
+    }
+    full_build(args, &metadata, replace)
+}
 
-    let manifest = get_manifest(&manifest)?;
-    let (graph, path_components, mut warnings) =
-        build_graph_from_manifest(&manifest, &manifest.python.cwd)
-            .context("failed in building graph")?;
-    let main_destination = move_all_nodes(&graph, &dist, &manifest.python.main)?;
-    write_bootstrap_script(
+/// `replace`: the output directory may be replaced if it exists
+fn full_build(args: &BuildArgs, metadata: &Option<Metadata>, replace: bool) -> Result<()> {
+    // fail early if the output directory can't be written to, the actual build happens in a staging directory
+    let staged = StagedDist::new(&args.out, replace)?;
+    let dist = staged.path();
+
+    let mut gathered = gather(&args.manifest, &args.manifest_options)?;
+    // named after the output directory, not the staging directory
+    let (options, debug_dir) = export_options(args, &args.out)?;
+    let exported = move_all_nodes(
+        &gathered.graph,
         &dist,
+        &gathered.manifest.python.main,
+        &options,
+        &mut gathered.timings,
+    )?;
+    let record = BuildRecord::from_graph(&gathered.graph, &dist)
+        .context("failed in creating build record")?;
+    let wrote_warnings = finish(args, &dist, gathered, &exported, record)?;
+
+    let target = staged
+        .commit()
+        .context("failed in replacing output directory")?;
+    write_debug_archive(&target, &options)?;
+    drop(debug_dir);
+    write_packed_outputs(args, metadata, &target)?;
+    print_summary(&target, wrote_warnings);
+    Ok(())
+}
+
+fn dry_run(args: &BuildArgs) -> Result<()> {
+    let gathered = gather(&args.manifest, &args.manifest_options)?;
+    let plan = plan_dist(
+        &gathered.graph,
+        &gathered.path_components,
+        &gathered.manifest.python.sys.version,
+        &gathered.manifest.python.main,
+        &gathered.warnings,
+    )
+    .context("failed in planning the distribution")?;
+    println!("{}", serde_json::to_string_pretty(&plan)?);
+    Ok(())
+}
+
+fn incremental_build(
+    args: &BuildArgs,
+    metadata: &Option<Metadata>,
+    dist: &PathBuf,
+    previous: BuildRecord,
+) -> Result<()> {
+    info!("incremental build in {}", dist.display());
+    // the dist is modified in place, without a record a failed build can't be mistaken for a complete one
+    fs::remove_file(build_record_path(dist)).context("failed in removing previous build record")?;
+
+    let mut gathered = gather(&args.manifest, &args.manifest_options)?;
+    let current = BuildRecord::from_graph(&gathered.graph, dist)
+        .context("failed in creating build record")?;
+    let plan = incremental::plan(&previous, &current);
+    info!(
+        "incremental: {} of {} nodes changed",
+        plan.changed.len(),
+        current.nodes.len()
+    );
+
+    incremental::remove_stale(dist, &plan.stale)?;
+    let changed_records: Vec<&NodeRecord> = current
+        .nodes
+        .iter()
+        .filter(|n| plan.changed.contains(&n.path))
+        .collect();
+    incremental::clear_symlink_farms(dist, &changed_records)?;
+    let warnings_file = warnings_file_path(dist);
+    if warnings_file.exists() {
+        fs::remove_file(&warnings_file).context("failed in removing previous warnings file")?;
+    }
+
+    let nodes: Vec<&Node> = gathered
+        .graph
+        .iter_nodes()
+        .filter(|n| plan.changed.contains(&n.path))
+        .collect();
+    let (options, debug_dir) = export_options(args, dist)?;
+    let exported = move_nodes(
+        &gathered.graph,
+        &nodes,
+        dist,
+        &gathered.manifest.python.main,
+        &options,
+        &mut gathered.timings,
+    )?;
+    let wrote_warnings = finish(args, dist, gathered, &exported, current)?;
+    write_debug_archive(dist, &options)?;
+    drop(debug_dir);
+    write_packed_outputs(args, metadata, dist)?;
+    print_summary(dist, wrote_warnings);
+    Ok(())
+}
+
+/// everything after exporting the nodes: bootstrap script, warnings, the build report and the build record
+/// returns whether any warnings were written
+fn finish(
+    args: &BuildArgs,
+    dist: &PathBuf,
+    gathered: Gathered,
+    exported: &Exported,
+    mut record: BuildRecord,
+) -> Result<bool> {
+    let mut timings = gathered.timings;
+    let mut path_components = gathered.path_components;
+    if args.zip_packages {
+        let start = Instant::now();
+        path_components = zip_site_packages(
+            &gathered.graph,
+            &path_components,
+            dist,
+            &unzippable_packages()?,
+        )
+        .context("failed in zipping site-packages")?;
+        timings.record_since("zip site-packages", start);
+    }
+    // after zipping, zipped files are not in dist anymore
+    let mut deduplicated = Vec::new();
+    if args.dedup {
+        let start = Instant::now();
+        deduplicated = dedup_plain_files(&gathered.graph, dist)
+            .context("failed in deduplicating plain files")?;
+        timings.record_since("dedup", start);
+    }
+
+    let start = Instant::now();
+    write_bootstrap_script(
+        dist,
         &path_components,
-        &manifest.python.sys.version,
-        &main_destination,
+        &gathered.manifest.python.sys.version,
+        &exported.main_script,
     )
     .context("failed in writing bootstrap script")?;
+    timings.record_since("bootstrap", start);
 
-    if !skip_warning_checks {
+    let mut warnings = gathered.warnings;
+    if !args.skip_warning_checks {
+        let start = Instant::now();
         println!(
             "shenzi will now validate if any of your warnings are errors, this can take time (it will scan your whole file system). You can skip this by passing --skip-warning-checks, number of warnings: {}",
             warnings.len(),
         );
         warnings = validate_warnings(warnings).context("Warning validation found some errors")?;
         println!("warning validation done: all warnings can be ignored");
+        timings.record_since("warning validation", start);
     }
-    let (warnings_file, wrote_warnings) =
-        write_warnings(warnings, &dist).context("failed in writing warnings")?;
-    if wrote_warnings {
-        println!("warnings written to {}", warnings_file.display());
-        println!(
-            "you would need to test the application to see if any of the warnings have affected the final distribution"
-        );
-    }
+    let (_, wrote_warnings) =
+        write_warnings(&warnings, dist).context("failed in writing warnings")?;
+    BuildReport::new(
+        &record,
+        &timings,
+        &warnings,
+        &exported.stripped,
+        &gathered.excluded,
+        &deduplicated,
+        dist,
+    )
+    .and_then(|report| report.write(dist))
+    .context("failed in writing build report")?;
 
-    Ok(())
+    // always the last step, an incremental build only trusts a dist which has a record
+    record.bytecode = args.bytecode;
+    record.zip_packages = args.zip_packages;
+    record.strip = args.strip;
+    record.debug_archive = args.debug_archive;
+    record.write(dist)?;
+    Ok(wrote_warnings)
 }
 
-fn read_manifest_from_path_or_stdio(manifest: &str) -> Result<String> {
-    let mut contents = String::new();
-    if manifest == "-" {
-        std::io::stdin().read_to_string(&mut contents)?;
+/// the debug info of stripped files is collected in a temporary directory, it is removed when the returned `TempDir` is dropped
+fn export_options(args: &BuildArgs, dist: &Path) -> Result<(ExportOptions, Option<TempDir>)> {
+    let tmp = if args.debug_archive {
+        Some(tempfile::tempdir().context("failed in creating temporary directory for debug info")?)
     } else {
-        contents = std::fs::read_to_string(manifest)?;
+        None
+    };
+    // the directory is named after the archive, it is the top level directory inside it
+    let debug_dir = tmp
+        .as_ref()
+        .map(|tmp| tmp.path().join(debug_archive_name(dist)));
+    Ok((
+        ExportOptions {
+            strip: args.strip,
+            debug_dir,
+            bytecode: args.bytecode,
+            copy_mode: args.copy_mode,
+        },
+        tmp,
+    ))
+}
+
+fn debug_archive_name(dist: &Path) -> String {
+    let name = dist
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| "dist".to_string());
+    format!("{}.debug", name)
+}
+
+fn write_debug_archive(dist: &Path, options: &ExportOptions) -> Result<()> {
+    if let Some(debug_dir) = &options.debug_dir {
+        let out = archive_path(
+            &dist.with_file_name(debug_archive_name(dist)),
+            ArchiveFormat::TarGz,
+        );
+        write_archive(debug_dir, &out, ArchiveFormat::TarGz)
+            .with_context(|| anyhow!("failed in writing debug archive {}", out.display()))?;
+        println!("debug info written to {}", out.display());
     }
-    Ok(contents)
+    Ok(())
 }
 
-fn get_manifest(manifest: &str) -> Result<ShenziManifest> {
-    let shenzi_workspace = InitializedShenziWorkspace::search()?;
-    let manifest = read_manifest_from_path_or_stdio(manifest)
-        .context(anyhow!("failed in reading manifest file at {}", manifest))?;
-    let mut manifest = ShenziManifest::from_str(&manifest)?;
-    if let Some(workspace) = shenzi_workspace {
-        merge_manifest_and_shenzi_workspace_manifest(&mut manifest, &workspace)?;
+/// archives and executables made from the finished dist
+fn write_packed_outputs(args: &BuildArgs, metadata: &Option<Metadata>, dist: &Path) -> Result<()> {
+    if let Some(format) = args.archive {
+        let out = archive_path(dist, format);
+        write_archive(dist, &out, format)
+            .with_context(|| anyhow!("failed in writing archive {}", out.display()))?;
+        println!("archive written to {}", out.display());
     }
-    Ok(manifest)
+    if args.self_extracting {
+        let out = self_extracting_path(dist);
+        write_self_extracting(dist, &out).with_context(|| {
+            anyhow!(
+                "failed in writing self extracting executable {}",
+                out.display()
+            )
+        })?;
+        println!("self extracting executable written to {}", out.display());
+    }
+    match (args.format, metadata) {
+        (OutputFormat::Dir, _) => {}
+        (OutputFormat::Deb, Some(metadata)) => {
+            let out = deb_path(dist, metadata);
+            write_deb(dist, metadata, &out)
+                .with_context(|| anyhow!("failed in writing debian package {}", out.display()))?;
+            println!("debian package written to {}", out.display());
+        }
+        (OutputFormat::Deb, None) => bail!(
+            "--format deb needs package metadata, add a [metadata] table with name and version to {}",
+            workspace_file_path().display()
+        ),
+        (OutputFormat::Oci, metadata) => {
+            let out = oci_path(dist);
+            let tag = metadata
+                .as_ref()
+                .map(|m| m.version.as_str())
+                .unwrap_or("latest");
+            write_oci(dist, &out, tag, oci_base(args)?)
+                .with_context(|| anyhow!("failed in writing OCI image {}", out.display()))?;
+            println!("OCI image written to {} (tag {})", out.display(), tag);
+        }
+    }
+    Ok(())
 }
 
-fn merge_manifest_and_shenzi_workspace_manifest(
-    manifest: &mut ShenziManifest,
-    workspace: &InitializedShenziWorkspace,
-) -> Result<()> {
-    manifest.python.main = workspace.main_path();
-    if !manifest.python.main.exists() {
+/// flags which can't be combined
+fn check_flags(args: &BuildArgs) -> Result<()> {
+    if args.debug_archive && args.strip == StripMode::None {
+        bail!("--debug-archive collects the debug info removed by --strip, pass --strip too");
+    }
+    // `__pycache__` directories are not packed into the zips, python can't write them in a zip either
+    if args.zip_packages && args.bytecode == BytecodeMode::Compile {
         bail!(
-            "main file in shenzi workspace file does not exist, path={}",
-            manifest.python.main.display()
+            "--zip-packages leaves out the __pycache__ directories written by --bytecode compile, pass --bytecode sourceless to put bytecode in the zips"
         );
     }
-    let deps = workspace.get_required_dependencies()?;
-    manifest.python.allowed_packages = Some(deps);
-    let extra_binaries: Vec<Bin> = workspace
-        .workspace
-        .binaries
-        .iter()
-        .map(|b| Bin {
-            path: b.to_string(),
-        })
-        .collect();
-    manifest.bins.extend(extra_binaries);
-    info!(
-        "merging manifest file with workspace, main-file={} required-dependencies={:?} binaries={:?}",
-        manifest.python.main.display(),
-        manifest.python.allowed_packages,
-        manifest.bins,
-    );
     Ok(())
 }
+
+/// the dist runs `bootstrap.sh` with bash and needs the system libraries, an image without a base can't start
+fn oci_base(args: &BuildArgs) -> Result<&Path> {
+    args.oci_base.as_deref().ok_or_else(|| {
+        anyhow!(
+            "--format oci needs --oci-base, an OCI image layout which provides bash and the system libraries (create one with `skopeo copy docker://debian:stable-slim oci:base`)"
+        )
+    })
+}
+
+/// metadata of the package to write from the shenzi workspace, it is required for `--format deb`
+fn package_metadata(args: &BuildArgs) -> Result<Option<Metadata>> {
+    if args.format == OutputFormat::Dir {
+        return Ok(None);
+    }
+    let metadata = InitializedShenziWorkspace::search()?.and_then(|w| w.workspace.metadata);
+    match metadata {
+        Some(metadata) => Ok(Some(metadata)),
+        None if args.format == OutputFormat::Oci => Ok(None),
+        None => bail!(
+            "building a package needs package metadata, add a [metadata] table with name and version (optionally maintainer and description) to {}",
+            workspace_file_path().display()
+        ),
+    }
+}
+
+fn unzippable_packages() -> Result<Vec<String>> {
+    Ok(InitializedShenziWorkspace::search()?
+        .map(|w| w.workspace.unzippable)
+        .unwrap_or_default())
+}
+
+fn print_summary(target: &Path, wrote_warnings: bool) {
+    println!("distribution written to {}", target.display());
+    if wrote_warnings {
+        println!(
+            "warnings written to {}",
+            warnings_file_path(target).display()
+        );
+        println!(
+            "you would need to test the application to see if any of the warnings have affected the final distribution"
+        );
+    }
+}
+
+fn read_previous_record(target: &Path) -> Option<BuildRecord> {
+    if !marker_file_path(target).exists() {
+        return None;
+    }
+    let record_path = build_record_path(target);
+    if !record_path.exists() {
+        return None;
+    }
+    match BuildRecord::read(&record_path) {
+        Ok(record) => Some(record),
+        Err(e) => {
+            warn!("ignoring unreadable build record: {:#}", e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        build: BuildArgs,
+    }
+
+    fn parse(args: &[&str]) -> BuildArgs {
+        Cli::parse_from(["shenzi", "shenzi.json"].iter().chain(args)).build
+    }
+
+    #[test]
+    fn test_check_flags() {
+        assert!(check_flags(&parse(&["--zip-packages", "--bytecode", "sourceless"])).is_ok());
+        assert!(check_flags(&parse(&["--bytecode"])).is_ok());
+        let err = check_flags(&parse(&["--zip-packages", "--bytecode"])).unwrap_err();
+        assert!(err.to_string().contains("--bytecode sourceless"), "{}", err);
+        assert!(check_flags(&parse(&["--debug-archive"])).is_err());
+        assert!(check_flags(&parse(&["--debug-archive", "--strip"])).is_ok());
+    }
+}
@@ -0,0 +1,53 @@
+use anyhow::Result;
+
+use crate::parse::cache::{ParseCache, parse_cache_root};
+
+#[derive(clap::Args, Debug)]
+pub struct CacheArgs {
+    #[command(subcommand)]
+    pub command: CacheCommand,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum CacheCommand {
+    /// Print the location, number of entries and size of the parse cache
+    Stats {
+        /// Print the stats as JSON
+        #[arg(long, default_value_t = false)]
+        json: bool,
+    },
+    /// Remove every entry of the parse cache
+    Clean,
+}
+
+pub fn run(args: &CacheArgs) -> Result<()> {
+    // never creates the cache, it is created by the first build
+    let cache = ParseCache::existing(&parse_cache_root()?);
+    match args.command {
+        CacheCommand::Stats { json } => {
+            let stats = cache.stats()?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&stats)?);
+                return Ok(());
+            }
+            if stats.bytes == 0 {
+                println!("parse cache: {} (empty)", stats.root.display());
+                return Ok(());
+            }
+            println!("parse cache: {}", stats.root.display());
+            println!("  hashed files: {}", stats.file_entries);
+            println!("  parsed files: {}", stats.dynamic_entries);
+            println!("  resolved files: {}", stats.resolved_entries);
+            println!(
+                "  size: {:.1}MB (limit {:.1}MB, set SHENZI_PARSE_CACHE_MAX_MB to change it)",
+                stats.bytes as f64 / (1024.0 * 1024.0),
+                stats.max_bytes as f64 / (1024.0 * 1024.0)
+            );
+        }
+        CacheCommand::Clean => {
+            cache.clean()?;
+            println!("removed parse cache at {}", parse_cache_root()?.display());
+        }
+    }
+    Ok(())
+}
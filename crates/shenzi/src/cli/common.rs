@@ -0,0 +1,201 @@
+// helpers shared between subcommands
+
+use anyhow::{Context, Result, anyhow, bail};
+use log::{info, warn};
+use std::io::Read;
+
+use crate::{
+    gather::{NodeFactory, PythonPathComponent, build_graph_from_manifest, filter::ExcludedFile},
+    graph::FileGraph,
+    manifest::{Bin, ShenziManifest},
+    parse::cache as parse_cache,
+    path_map::{PathMap, PathMapping, remap_manifest, report_unmapped},
+    redact::{EnvAllowlist, log_redaction, redact_env},
+    report::Timings,
+    warnings::Warning,
+    workspace::{FileRules, InitializedShenziWorkspace},
+};
+
+/// options of every subcommand which builds the graph from a manifest
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct ManifestOptions {
+    /// Rewrite absolute paths of the manifest under OLD to the same path under NEW, for environments which moved after discovery.
+    /// Can be repeated, the longest matching OLD wins. Adds to (and overrides) `[path_map]` of the shenzi workspace file
+    #[arg(long = "path-map", value_name = "OLD=NEW")]
+    pub path_map: Vec<PathMapping>,
+
+    /// Keep this environment variable of the manifest, a glob like `CONDA_*`. Can be repeated.
+    /// Only PATH, LD_LIBRARY_PATH, LD_PRELOAD and DYLD_* (used for resolution) and `[env] allow` of the workspace are kept by default
+    #[arg(long = "allow-env", value_name = "NAME")]
+    pub allow_env: Vec<String>,
+}
+
+pub struct Gathered {
+    pub manifest: ShenziManifest,
+    pub graph: FileGraph<NodeFactory>,
+    pub path_components: Vec<PythonPathComponent>,
+    pub warnings: Vec<Warning>,
+    // files left out by the `[files]` rules of the workspace
+    pub excluded: Vec<ExcludedFile>,
+    pub timings: Timings,
+}
+
+/// read the manifest (merged with the workspace if there is one, environment redacted, paths rewritten by `--path-map`) and build the dependency graph
+pub fn gather(manifest: &str, options: &ManifestOptions) -> Result<Gathered> {
+    let (manifest, rules) = get_manifest(manifest, options)?;
+    let mut timings = Timings::new();
+    let (graph, path_components, warnings, excluded) =
+        build_graph_from_manifest(&manifest, &manifest.python.cwd, &rules, &mut timings)
+            .context("failed in building graph")?;
+    trim_parse_cache();
+    Ok(Gathered {
+        manifest,
+        graph,
+        path_components,
+        warnings,
+        excluded,
+        timings,
+    })
+}
+
+/// keep the parse cache under its size limit, a failure only means the cache stays bigger
+fn trim_parse_cache() {
+    if let Some(cache) = parse_cache::global()
+        && let Err(e) = cache.trim(parse_cache::max_bytes())
+    {
+        warn!("failed in trimming parse cache: {:#}", e);
+    }
+}
+
+pub fn read_manifest_from_path_or_stdio(manifest: &str) -> Result<String> {
+    let mut contents = String::new();
+    if manifest == "-" {
+        std::io::stdin().read_to_string(&mut contents)?;
+    } else {
+        contents = std::fs::read_to_string(manifest)?;
+    }
+    Ok(contents)
+}
+
+/// the manifest, and the file rules of the workspace (empty without a workspace)
+fn get_manifest(manifest: &str, options: &ManifestOptions) -> Result<(ShenziManifest, FileRules)> {
+    let shenzi_workspace = InitializedShenziWorkspace::search()?;
+    let manifest = read_manifest_from_path_or_stdio(manifest)
+        .context(anyhow!("failed in reading manifest file at {}", manifest))?;
+    let manifest = ShenziManifest::from_str(&manifest)?;
+    apply_workspace(manifest, shenzi_workspace, options)
+}
+
+/// rewrite the paths of the manifest, then merge the workspace into it
+/// paths taken from the workspace (the main script) are on this machine already, they are never rewritten
+fn apply_workspace(
+    mut manifest: ShenziManifest,
+    shenzi_workspace: Option<InitializedShenziWorkspace>,
+    options: &ManifestOptions,
+) -> Result<(ShenziManifest, FileRules)> {
+    let workspace_path_map = shenzi_workspace
+        .as_ref()
+        .map(|w| w.workspace.path_map.clone())
+        .unwrap_or_default();
+    let path_map = PathMap::new(&workspace_path_map, &options.path_map);
+    if !path_map.is_empty() {
+        let unmapped = remap_manifest(&mut manifest, &path_map);
+        report_unmapped(&unmapped);
+    }
+    let mut rules = FileRules::default();
+    let mut allow_env = options.allow_env.clone();
+    if let Some(workspace) = shenzi_workspace {
+        merge_manifest_and_shenzi_workspace_manifest(&mut manifest, &workspace)?;
+        rules = workspace.workspace.files;
+        allow_env.extend(workspace.workspace.env.allow);
+    }
+    redact_manifest_env(&mut manifest, &allow_env)?;
+    Ok((manifest, rules))
+}
+
+/// keep only the allowed environment variables of the manifest and redact secrets in them
+pub fn redact_manifest_env(manifest: &mut ShenziManifest, allow_env: &[String]) -> Result<()> {
+    let allowlist = EnvAllowlist::new(allow_env)?;
+    let redaction = redact_env(&mut manifest.env, &allowlist);
+    log_redaction(&redaction);
+    Ok(())
+}
+
+fn merge_manifest_and_shenzi_workspace_manifest(
+    manifest: &mut ShenziManifest,
+    workspace: &InitializedShenziWorkspace,
+) -> Result<()> {
+    manifest.python.main = workspace.main_path();
+    if !manifest.python.main.exists() {
+        bail!(
+            "main file in shenzi workspace file does not exist, path={}",
+            manifest.python.main.display()
+        );
+    }
+    let deps = workspace.get_required_dependencies()?;
+    manifest.python.allowed_packages = Some(deps);
+    let extra_binaries: Vec<Bin> = workspace
+        .workspace
+        .binaries
+        .iter()
+        .map(|b| Bin {
+            path: b.to_string(),
+        })
+        .collect();
+    manifest.bins.extend(extra_binaries);
+    info!(
+        "merging manifest file with workspace, main-file={} required-dependencies={:?} binaries={:?}",
+        manifest.python.main.display(),
+        manifest.python.allowed_packages,
+        manifest.bins,
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::manifest::test_manifest;
+    use std::{fs, path::PathBuf};
+
+    #[test]
+    fn test_path_map_does_not_rewrite_workspace_main() {
+        let tmp = tempfile::tempdir().unwrap();
+        let ws = tmp.path();
+        fs::write(ws.join("main.py"), "").unwrap();
+        fs::write(ws.join("poetry.lock"), "package = []\n").unwrap();
+        // the manifest was written in a container where the project was at the same path
+        let workspace_file = ws.join("shenzi_workspace.toml");
+        fs::write(
+            &workspace_file,
+            format!(
+                r#"
+binaries = []
+
+[packaging]
+kind = "poetry"
+config_file = "poetry.lock"
+groups = ["main"]
+
+[execution]
+main = "main.py"
+
+[path_map]
+"{}" = "/build"
+"#,
+                ws.display()
+            ),
+        )
+        .unwrap();
+        let workspace = InitializedShenziWorkspace::from_path(workspace_file).unwrap();
+
+        let mut value = test_manifest();
+        value["python"]["cwd"] = serde_json::json!(ws);
+        let manifest = ShenziManifest::from_str(&value.to_string()).unwrap();
+        let (manifest, _) =
+            apply_workspace(manifest, workspace, &ManifestOptions::default()).unwrap();
+
+        assert_eq!(manifest.python.main, ws.join("main.py"));
+        assert_eq!(manifest.python.cwd, PathBuf::from("/build"));
+    }
+}
@@ -0,0 +1,111 @@
+use anyhow::Result;
+use std::path::PathBuf;
+
+use crate::diff::{Status, Unit, UnitDiff, diff, read_files, total_size};
+
+#[derive(clap::Args, Debug)]
+pub struct DiffArgs {
+    /// The old dist (or its build-record.json)
+    pub before: PathBuf,
+
+    /// The new dist (or its build-record.json)
+    pub after: PathBuf,
+
+    /// Print the changes as JSON
+    #[arg(long, default_value_t = false)]
+    pub json: bool,
+}
+
+pub fn run(args: &DiffArgs) -> Result<()> {
+    let before = read_files(&args.before)?;
+    let after = read_files(&args.after)?;
+    let diffs = diff(&before, &after);
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&diffs)?);
+        return Ok(());
+    }
+
+    for title in ["shared libraries", "python packages", "other"] {
+        let section: Vec<&UnitDiff> = diffs
+            .iter()
+            .filter(|d| section_of(&d.unit) == title)
+            .collect();
+        if section.is_empty() {
+            continue;
+        }
+        println!("{} ({} changed):", title, section.len());
+        for d in section {
+            println!("  {}", describe(d));
+        }
+        println!();
+    }
+    let (size_before, size_after) = (total_size(&before), total_size(&after));
+    println!(
+        "total: {} -> {} ({})",
+        human_size(size_before as i64),
+        human_size(size_after as i64),
+        signed_human_size(size_after as i64 - size_before as i64)
+    );
+    Ok(())
+}
+
+fn section_of(unit: &Unit) -> &'static str {
+    match unit {
+        Unit::SharedLibrary(_) => "shared libraries",
+        Unit::Package(_) => "python packages",
+        Unit::Stdlib | Unit::Other(_) => "other",
+    }
+}
+
+fn describe(d: &UnitDiff) -> String {
+    let name = match &d.unit {
+        Unit::SharedLibrary(name) | Unit::Package(name) | Unit::Other(name) => name.as_str(),
+        Unit::Stdlib => "stdlib",
+    };
+    match d.status {
+        Status::Added => format!("+ {} ({})", name, human_size(d.size_after as i64)),
+        Status::Removed => format!("- {} ({})", name, human_size(d.size_before as i64)),
+        Status::Changed => {
+            let mut files = Vec::new();
+            if let Unit::SharedLibrary(_) = d.unit {
+                files.push("digest changed".to_string());
+            } else {
+                for (n, what) in [
+                    (d.files_added, "added"),
+                    (d.files_removed, "removed"),
+                    (d.files_changed, "changed"),
+                ] {
+                    if n > 0 {
+                        files.push(format!("{} {}", n, what));
+                    }
+                }
+            }
+            format!(
+                "~ {} ({}, {})",
+                name,
+                files.join(", "),
+                signed_human_size(d.size_delta())
+            )
+        }
+    }
+}
+
+fn signed_human_size(bytes: i64) -> String {
+    let sign = if bytes < 0 { "-" } else { "+" };
+    format!("{}{}", sign, human_size(bytes.abs()))
+}
+
+fn human_size(bytes: i64) -> String {
+    let units = ["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, units[0])
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}
@@ -0,0 +1,205 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::{collections::BTreeMap, path::PathBuf};
+
+use crate::{
+    cli::common::{Gathered, ManifestOptions, gather},
+    gather::NodeFactory,
+    graph::FileGraph,
+    node::{Node, deps::Deps},
+    paths::absolute_path,
+    pkg::paths::NodeLayout,
+};
+
+#[derive(clap::ValueEnum, Clone, Debug)]
+pub enum GraphFormat {
+    Dot,
+    Json,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct GraphArgs {
+    /// manifest file path, use `-` to take input from stdio (or when you are piping)
+    pub manifest: String,
+
+    #[command(flatten)]
+    pub manifest_options: ManifestOptions,
+
+    /// Output format, the graph is written to stdout
+    #[arg(long, value_enum, default_value_t = GraphFormat::Dot)]
+    pub format: GraphFormat,
+
+    /// Directory of the distribution, destinations of the nodes are computed relative to it
+    #[arg(long, default_value = "dist")]
+    pub dist: PathBuf,
+}
+
+#[derive(Serialize)]
+struct GraphExport {
+    // sorted by path
+    nodes: Vec<GraphNode>,
+}
+
+#[derive(Serialize)]
+struct GraphNode {
+    path: PathBuf,
+    kind: &'static str,
+    sha: Option<String>,
+    // locations inside dist, relative to dist
+    layout: NodeLayout,
+    // DT_NEEDED names (or load commands) mapped to their resolved paths, empty for plain files
+    needed: BTreeMap<String, PathBuf>,
+    // original paths of the dependencies in the graph, sorted
+    dependencies: Vec<PathBuf>,
+}
+
+pub fn run(args: &GraphArgs) -> Result<()> {
+    let Gathered { graph, .. } = gather(&args.manifest, &args.manifest_options)?;
+    let dist = absolute_path(&args.dist)?;
+    let export = export_graph(&graph, &dist);
+    match args.format {
+        GraphFormat::Json => println!("{}", serde_json::to_string_pretty(&export)?),
+        GraphFormat::Dot => print!("{}", to_dot(&export)),
+    }
+    Ok(())
+}
+
+fn export_graph(graph: &FileGraph<NodeFactory>, dist: &PathBuf) -> GraphExport {
+    let mut nodes: Vec<GraphNode> = graph
+        .iter_nodes()
+        .map(|node| {
+            let mut dependencies: Vec<PathBuf> = graph
+                .get_node_dependencies(node)
+                .into_iter()
+                .map(|n| n.path)
+                .collect();
+            dependencies.sort();
+            GraphNode {
+                path: node.path.clone(),
+                kind: node.pkg.kind(),
+                sha: node.pkg.sha().map(|s| s.to_string()),
+                layout: NodeLayout::relative(node, dist),
+                needed: needed(node),
+                dependencies,
+            }
+        })
+        .collect();
+    nodes.sort_by(|a, b| a.path.cmp(&b.path));
+    GraphExport { nodes }
+}
+
+fn needed(node: &Node) -> BTreeMap<String, PathBuf> {
+    match &node.deps {
+        Deps::Binary(binary) => binary
+            .needed()
+            .iter()
+            .map(|(name, path)| (name.clone(), path.clone()))
+            .collect(),
+        _ => BTreeMap::new(),
+    }
+}
+
+/// edges point from a node to the nodes it needs
+fn to_dot(export: &GraphExport) -> String {
+    let mut out = String::from("digraph shenzi {\n    rankdir=LR;\n    node [shape=box];\n");
+    for node in &export.nodes {
+        let name = node
+            .path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| node.path.display().to_string());
+        let destination = node
+            .layout
+            .destination
+            .as_ref()
+            .or(node.layout.reals.as_ref())
+            .map(|p| p.display().to_string())
+            .unwrap_or_default();
+        out.push_str(&format!(
+            "    {} [label={}, tooltip={}];\n",
+            dot_quote(&node.path.display().to_string()),
+            dot_quote(&format!("{}\n{}", name, node.kind)),
+            dot_quote(&destination),
+        ));
+    }
+    for node in &export.nodes {
+        for dep in &node.dependencies {
+            out.push_str(&format!(
+                "    {} -> {};\n",
+                dot_quote(&node.path.display().to_string()),
+                dot_quote(&dep.display().to_string()),
+            ));
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+fn dot_quote(s: &str) -> String {
+    format!(
+        "\"{}\"",
+        s.replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_dot_quote() {
+        assert_eq!(dot_quote("libz.so"), "\"libz.so\"");
+        assert_eq!(dot_quote("a \"b\""), "\"a \\\"b\\\"\"");
+        assert_eq!(dot_quote("C:\\lib"), "\"C:\\\\lib\"");
+        assert_eq!(dot_quote("name\nkind"), "\"name\\nkind\"");
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let layout = |destination: Option<&str>, reals: Option<&str>| NodeLayout {
+            destination: destination.map(PathBuf::from),
+            reals: reals.map(PathBuf::from),
+            symlink_farm: None,
+        };
+        let export = GraphExport {
+            nodes: vec![
+                GraphNode {
+                    path: PathBuf::from("/env/lib/libz.so.1"),
+                    kind: "binary",
+                    sha: Some("abcd".to_string()),
+                    layout: layout(None, Some("reals/r/abcd_libz.so.1")),
+                    needed: BTreeMap::new(),
+                    dependencies: vec![],
+                },
+                GraphNode {
+                    path: PathBuf::from("/env/site-packages/zlib.so"),
+                    kind: "site_packages_binary",
+                    sha: Some("ef01".to_string()),
+                    layout: layout(
+                        Some("site_packages/sp_0/zlib.so"),
+                        Some("reals/r/ef01_zlib.so"),
+                    ),
+                    needed: BTreeMap::from([(
+                        "libz.so.1".to_string(),
+                        PathBuf::from("/env/lib/libz.so.1"),
+                    )]),
+                    dependencies: vec![PathBuf::from("/env/lib/libz.so.1")],
+                },
+            ],
+        };
+        assert_eq!(
+            to_dot(&export),
+            concat!(
+                "digraph shenzi {\n",
+                "    rankdir=LR;\n",
+                "    node [shape=box];\n",
+                "    \"/env/lib/libz.so.1\" [label=\"libz.so.1\\nbinary\", tooltip=\"reals/r/abcd_libz.so.1\"];\n",
+                "    \"/env/site-packages/zlib.so\" [label=\"zlib.so\\nsite_packages_binary\", tooltip=\"site_packages/sp_0/zlib.so\"];\n",
+                "    \"/env/site-packages/zlib.so\" -> \"/env/lib/libz.so.1\";\n",
+                "}\n",
+            )
+        );
+    }
+}
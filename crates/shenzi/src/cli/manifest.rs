@@ -0,0 +1,127 @@
+use anyhow::{Context, Result, anyhow};
+use std::{fs, path::PathBuf};
+
+use crate::{
+    cli::common::{read_manifest_from_path_or_stdio, redact_manifest_env},
+    manifest::{LoadKind, ShenziManifest, schema},
+    merge::{added_loads, merge_manifests},
+};
+
+#[derive(clap::Args, Debug)]
+pub struct ManifestArgs {
+    #[command(subcommand)]
+    pub command: ManifestCommand,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum ManifestCommand {
+    /// Print the JSON Schema of the current manifest version
+    Schema,
+    /// Validate a manifest and print it upgraded to the current version, without the environment variables shenzi does not use
+    Upgrade {
+        /// Path to the manifest, `-` reads it from stdin
+        manifest: String,
+
+        /// Keep this environment variable of the manifest, a glob like `CONDA_*`. Can be repeated.
+        /// Only PATH, LD_LIBRARY_PATH, LD_PRELOAD and DYLD_* are kept by default, secrets are redacted
+        #[arg(long = "allow-env", value_name = "NAME")]
+        allow_env: Vec<String>,
+    },
+    /// Merge manifests of several discovery runs of the same python environment, loads are kept in the order they were first seen
+    Merge {
+        /// Paths to the manifests, `python.main`, `python.cwd` and `env` are taken from the first one
+        #[arg(required = true, num_args = 1..)]
+        manifests: Vec<String>,
+
+        /// Write the merged manifest to this file instead of stdout
+        #[arg(long, short)]
+        out: Option<PathBuf>,
+
+        /// Keep this environment variable of the manifest, a glob like `CONDA_*`. Can be repeated.
+        /// Only PATH, LD_LIBRARY_PATH, LD_PRELOAD and DYLD_* are kept by default, secrets are redacted
+        #[arg(long = "allow-env", value_name = "NAME")]
+        allow_env: Vec<String>,
+    },
+    /// Show the loads every manifest adds to the manifests before it
+    Diff {
+        /// Paths to the manifests, in the order they would be merged
+        #[arg(required = true, num_args = 1..)]
+        manifests: Vec<String>,
+
+        /// Print the added loads as JSON
+        #[arg(long, default_value_t = false)]
+        json: bool,
+    },
+}
+
+pub fn run(args: &ManifestArgs) -> Result<()> {
+    match &args.command {
+        ManifestCommand::Schema => {
+            println!("{}", serde_json::to_string_pretty(&schema())?);
+        }
+        ManifestCommand::Upgrade {
+            manifest,
+            allow_env,
+        } => {
+            let manifest = read_manifest(manifest, allow_env)?;
+            println!("{}", serde_json::to_string_pretty(&manifest)?);
+        }
+        ManifestCommand::Merge {
+            manifests,
+            out,
+            allow_env,
+        } => {
+            let merged = merge_manifests(read_manifests(manifests, allow_env)?)?;
+            let contents = serde_json::to_string_pretty(&merged)?;
+            match out {
+                Some(out) => fs::write(out, contents).with_context(|| {
+                    anyhow!("failed in writing merged manifest to {}", out.display())
+                })?,
+                None => println!("{}", contents),
+            }
+        }
+        ManifestCommand::Diff { manifests, json } => {
+            let added = added_loads(read_manifests(manifests, &[])?);
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&added)?);
+                return Ok(());
+            }
+            for run in added {
+                println!(
+                    "{} ({} loads, {} new):",
+                    run.manifest,
+                    run.total,
+                    run.added.len()
+                );
+                for load in run.added {
+                    let kind = match load.kind {
+                        LoadKind::Extension => "extension",
+                        LoadKind::Dlopen => "dlopen",
+                    };
+                    println!("  + {} {}", kind, load.path.display());
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// the manifest upgraded to the current version, with its environment redacted
+fn read_manifest(manifest: &str, allow_env: &[String]) -> Result<ShenziManifest> {
+    let contents = read_manifest_from_path_or_stdio(manifest)
+        .context(anyhow!("failed in reading manifest file at {}", manifest))?;
+    let mut parsed = ShenziManifest::from_str(&contents)
+        .with_context(|| anyhow!("failed in parsing manifest {}", manifest))?;
+    redact_manifest_env(&mut parsed, allow_env)?;
+    Ok(parsed)
+}
+
+fn read_manifests(
+    manifests: &[String],
+    allow_env: &[String],
+) -> Result<Vec<(String, ShenziManifest)>> {
+    manifests
+        .iter()
+        .map(|m| read_manifest(m, allow_env).map(|manifest| (m.clone(), manifest)))
+        .collect()
+}
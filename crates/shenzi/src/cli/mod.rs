@@ -1,23 +1,32 @@
-
-use anyhow::{bail, Result};
+use anyhow::{Result, bail};
 use clap::Parser;
 
 mod build;
+mod cache;
+mod common;
+mod diff;
+mod graph;
 mod init;
-
+mod manifest;
+mod verify;
+mod why;
 
 #[derive(clap::Subcommand, Debug)]
 pub enum Commands {
-    Build {
-        /// manifest file path, use `-` to take input from stdio (or when you are piping)
-        manifest: String,
-
-        /// Skip validation of warnings, you should generally not skip this, although warnings validation can take a long time.
-        /// You can skip this if you are running shenzi multiple times and are confident that there were no warnings in the first invocation.
-        #[arg(long, default_value_t = false)]
-        skip_warning_checks: bool,
-    },
+    Build(build::BuildArgs),
     Init {},
+    /// Explain why a file ends up in the distribution
+    Why(why::WhyArgs),
+    /// Export the dependency graph as DOT or JSON
+    Graph(graph::GraphArgs),
+    /// Check that every shared library in the distribution finds its dependencies inside the distribution
+    Verify(verify::VerifyArgs),
+    /// Compare two distributions (or two build records) per shared library and python package
+    Diff(diff::DiffArgs),
+    /// Inspect or clean the cache of parsed shared libraries
+    Cache(cache::CacheArgs),
+    /// Work with shenzi.json: print its JSON Schema, upgrade, merge or compare manifests
+    Manifest(manifest::ManifestArgs),
 }
 
 #[derive(Debug, clap::Parser)]
@@ -27,24 +36,39 @@ pub struct Cli {
     command: Option<Commands>,
 }
 
-
 pub fn run() -> Result<()> {
     let cli = Cli::parse();
 
     match cli.command {
         None => {
             bail!("empty command not allowed");
-        },
-        Some(cmd) => {
-            match cmd {
-                Commands::Build { manifest, skip_warning_checks} => {
-                    build::run(&manifest, skip_warning_checks)?;
-                },
-                Commands::Init {  } => {
-                    init::run()?;
-                }
-            }
         }
+        Some(cmd) => match cmd {
+            Commands::Build(args) => {
+                build::run(&args)?;
+            }
+            Commands::Init {} => {
+                init::run()?;
+            }
+            Commands::Why(args) => {
+                why::run(&args)?;
+            }
+            Commands::Graph(args) => {
+                graph::run(&args)?;
+            }
+            Commands::Verify(args) => {
+                verify::run(&args)?;
+            }
+            Commands::Diff(args) => {
+                diff::run(&args)?;
+            }
+            Commands::Cache(args) => {
+                cache::run(&args)?;
+            }
+            Commands::Manifest(args) => {
+                manifest::run(&args)?;
+            }
+        },
     };
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,44 @@
+use anyhow::{Context, Result, bail};
+use std::path::PathBuf;
+
+use crate::verify::{copy_dist, verify_dist};
+
+#[derive(clap::Args, Debug)]
+pub struct VerifyArgs {
+    /// Directory of the distribution
+    #[arg(default_value = "dist")]
+    pub dist: PathBuf,
+
+    /// Copy the distribution to a temporary directory and verify the copy, this proves that the distribution does not depend on its location
+    #[arg(long, default_value_t = false)]
+    pub relocate: bool,
+}
+
+pub fn run(args: &VerifyArgs) -> Result<()> {
+    if !args.dist.is_dir() {
+        bail!("distribution not found at {}", args.dist.display());
+    }
+    // keep the copy alive until verification is done
+    let (_tmp, dist) = if args.relocate {
+        let tmp = tempfile::tempdir().context("failed in creating temporary directory")?;
+        let dist = tmp.path().join("dist");
+        copy_dist(&args.dist, &dist)?;
+        (Some(tmp), dist)
+    } else {
+        (None, args.dist.clone())
+    };
+
+    let problems = verify_dist(&dist)?;
+    if problems.is_empty() {
+        println!("all dependencies resolve inside {}", args.dist.display());
+        return Ok(());
+    }
+    for p in &problems {
+        println!("{}", p);
+    }
+    bail!(
+        "{} dependencies do not resolve inside {}",
+        problems.len(),
+        args.dist.display()
+    )
+}
@@ -0,0 +1,130 @@
+use anyhow::{Result, bail};
+use std::path::PathBuf;
+
+use crate::{
+    cli::common::{Gathered, ManifestOptions, gather},
+    manifest::{LoadKind, ShenziManifest},
+    node::{Node, Pkg},
+    paths::{absolute_path, normalize_path},
+    pkg::paths::NodeLayout,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct WhyArgs {
+    /// manifest file path, use `-` to take input from stdio (or when you are piping)
+    pub manifest: String,
+
+    #[command(flatten)]
+    pub manifest_options: ManifestOptions,
+
+    /// The file to explain, either its original path or its path inside the distribution
+    pub path: PathBuf,
+
+    /// Directory of the distribution, used to match paths inside the distribution
+    #[arg(long, default_value = "dist")]
+    pub dist: PathBuf,
+
+    /// Maximum number of dependency chains to print
+    #[arg(long, default_value_t = 10)]
+    pub limit: usize,
+}
+
+pub fn run(args: &WhyArgs) -> Result<()> {
+    let Gathered {
+        manifest, graph, ..
+    } = gather(&args.manifest, &args.manifest_options)?;
+    let dist = absolute_path(&args.dist)?;
+    let query = normalize_path(&absolute_path(&args.path)?);
+
+    let node = match graph.get_node_by_path(&query) {
+        Some(node) => node.clone(),
+        None => match graph
+            .iter_nodes()
+            .find(|n| NodeLayout::of(n, &dist).paths().any(|p| *p == query))
+        {
+            Some(node) => node.clone(),
+            None => bail!(
+                "{} is not part of the distribution, it is neither a file found by shenzi nor a path inside {}",
+                args.path.display(),
+                dist.display()
+            ),
+        },
+    };
+
+    println!("{} ({})", node.path.display(), node.pkg.kind());
+    let layout = NodeLayout::relative(&node, &dist);
+    for p in layout.paths() {
+        println!("  in dist: {}", p.display());
+    }
+
+    let chains = graph.chains_to_node(&node, |n| root_reason(n, &manifest).is_some());
+    println!();
+    if chains.is_empty() {
+        println!("no dependency chain found (this should not happen, please report it)");
+    } else {
+        println!(
+            "dependency chains ({} found, showing {}):",
+            chains.len(),
+            chains.len().min(args.limit)
+        );
+        for chain in chains.iter().take(args.limit) {
+            let root = &chain[0];
+            let reason =
+                root_reason(root, &manifest).expect("fatal: chain does not start at a root");
+            println!("  [{}] {}", reason, root.path.display());
+            for n in &chain[1..] {
+                println!("    -> {}", n.path.display());
+            }
+        }
+    }
+
+    let mut dependents: Vec<PathBuf> = graph
+        .get_node_dependents(&node)
+        .into_iter()
+        .map(|n| n.path)
+        .collect();
+    dependents.sort();
+    println!();
+    println!("direct dependents ({}):", dependents.len());
+    for d in dependents {
+        println!("  {}", d.display());
+    }
+    Ok(())
+}
+
+/// why a node is part of the dist by itself, `None` if it is only there because something else needs it
+fn root_reason(node: &Node, manifest: &ShenziManifest) -> Option<String> {
+    if node.path == normalize_path(&manifest.python.sys.executable) {
+        return Some("python executable".to_string());
+    }
+    if let Some(l) = manifest
+        .loads
+        .iter()
+        .find(|l| normalize_path(&l.path) == node.path)
+    {
+        let kind = match l.kind {
+            LoadKind::Dlopen => "dlopen",
+            LoadKind::Extension => "extension",
+        };
+        return Some(format!("manifest load: {}", kind));
+    }
+    if manifest
+        .bins
+        .iter()
+        .any(|b| normalize_path(&PathBuf::from(&b.path)) == node.path)
+    {
+        return Some("binary".to_string());
+    }
+    match &node.pkg {
+        Pkg::MainPyScript => Some("main script".to_string()),
+        Pkg::PlainPyBinaryFile | Pkg::BinaryInPath { .. } => Some("binary".to_string()),
+        Pkg::SitePackagesPlain { alias, .. } | Pkg::SitePackagesBinary { alias, .. } => {
+            Some(format!("site-packages: {}", alias))
+        }
+        Pkg::PrefixPlain(_)
+        | Pkg::PrefixBinary(_)
+        | Pkg::ExecPrefixPlain(_)
+        | Pkg::ExecPrefixBinary(_) => Some("stdlib".to_string()),
+        Pkg::Executable | Pkg::Binary { .. } | Pkg::BinaryInLDPath { .. } => None,
+    }
+}
@@ -0,0 +1,250 @@
+// debian package of a dist, built without dpkg
+// a .deb is an ar archive of `debian-binary`, `control.tar.gz` and `data.tar.gz`
+// the dist is installed at `/opt/<name>`, `/usr/bin/<name>` runs its bootstrap.sh
+
+use std::{
+    collections::HashSet,
+    fs,
+    io::{Read, Seek, SeekFrom, Write},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use flate2::{Compression, GzBuilder, write::GzEncoder};
+use log::info;
+use tar::Builder;
+use tempfile::NamedTempFile;
+use walkdir::WalkDir;
+
+use crate::{
+    archive::{append_bytes, append_directory, append_tree},
+    workspace::Metadata,
+};
+
+/// `<name>_<version>_<arch>.deb` next to `dist`
+pub fn deb_path(dist: &Path, metadata: &Metadata) -> PathBuf {
+    dist.with_file_name(format!(
+        "{}_{}_{}.deb",
+        metadata.name,
+        metadata.version,
+        debian_architecture()
+    ))
+}
+
+pub fn write_deb(dist: &Path, metadata: &Metadata, out: &Path) -> Result<()> {
+    validate_metadata(metadata)?;
+    info!(
+        "writing debian package of {} to {}",
+        dist.display(),
+        out.display()
+    );
+
+    let control = control_file(metadata, installed_size_kib(dist)?);
+    let control_tar = gzipped_tar(Vec::new(), |builder| {
+        append_directory(builder, Path::new("."))?;
+        append_bytes(builder, Path::new("control"), 0o644, control.as_bytes())
+    })?;
+
+    let opt = PathBuf::from("opt").join(&metadata.name);
+    let wrapper = format!(
+        "#!/bin/sh\nexec bash /opt/{}/bootstrap.sh \"$@\"\n",
+        metadata.name
+    );
+    // the data archive is as large as the dist, it is written to a temporary file next to the package instead of memory
+    let parent = out.parent().ok_or_else(|| {
+        anyhow!(
+            "debian package path does not have a parent {}",
+            out.display()
+        )
+    })?;
+    let data_file = NamedTempFile::new_in(parent)
+        .with_context(|| anyhow!("failed in creating temporary file in {}", parent.display()))?;
+    let mut data_tar = gzipped_tar(data_file, |builder| {
+        for dir in [".", "opt", "usr", "usr/bin"] {
+            append_directory(builder, Path::new(dir))?;
+        }
+        append_tree(builder, dist, &opt)?;
+        append_bytes(
+            builder,
+            &PathBuf::from("usr/bin").join(&metadata.name),
+            0o755,
+            wrapper.as_bytes(),
+        )
+    })?;
+
+    let data_size = data_tar.as_file().metadata()?.len();
+    data_tar.seek(SeekFrom::Start(0))?;
+
+    let file = fs::File::create(out)
+        .with_context(|| anyhow!("failed in creating debian package at {}", out.display()))?;
+    let mut ar = ar::Builder::new(file);
+    // the order of the members is fixed by the format
+    append_member(&mut ar, "debian-binary", 4, "2.0\n".as_bytes(), out)?;
+    append_member(
+        &mut ar,
+        "control.tar.gz",
+        control_tar.len() as u64,
+        &control_tar[..],
+        out,
+    )?;
+    append_member(&mut ar, "data.tar.gz", data_size, &mut data_tar, out)?;
+    Ok(())
+}
+
+fn append_member(
+    ar: &mut ar::Builder<fs::File>,
+    name: &str,
+    size: u64,
+    contents: impl Read,
+    out: &Path,
+) -> Result<()> {
+    let mut header = ar::Header::new(name.as_bytes().to_vec(), size);
+    header.set_mode(0o644);
+    ar.append(&header, contents)
+        .with_context(|| anyhow!("failed in writing {} to {}", name, out.display()))
+}
+
+/// a gzipped tar written to `out`, returns `out`
+fn gzipped_tar<W, F>(out: W, fill: F) -> Result<W>
+where
+    W: Write,
+    F: FnOnce(&mut Builder<GzEncoder<W>>) -> Result<()>,
+{
+    let mut builder = Builder::new(GzBuilder::new().write(out, Compression::default()));
+    fill(&mut builder)?;
+    let mut encoder = builder.into_inner()?;
+    encoder.flush()?;
+    Ok(encoder.finish()?)
+}
+
+fn control_file(metadata: &Metadata, installed_size: u64) -> String {
+    let maintainer = metadata.maintainer.as_deref().unwrap_or("unknown");
+    let description = metadata.description.as_deref().unwrap_or(&metadata.name);
+    format!(
+        "Package: {}\nVersion: {}\nArchitecture: {}\nMaintainer: {}\nInstalled-Size: {}\nDepends: bash\nSection: misc\nPriority: optional\nDescription: {}\n",
+        metadata.name,
+        metadata.version,
+        debian_architecture(),
+        maintainer,
+        installed_size,
+        description.replace('\n', " ")
+    )
+}
+
+/// dpkg rejects names with uppercase letters and versions which don't start with a digit
+fn validate_metadata(metadata: &Metadata) -> Result<()> {
+    let name_ok = metadata.name.len() >= 2
+        && metadata
+            .name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "+-.".contains(c))
+        && metadata
+            .name
+            .starts_with(|c: char| c.is_ascii_alphanumeric());
+    if !name_ok {
+        bail!(
+            "invalid package name {:?}, debian package names have at least two characters: lowercase letters, digits, `+`, `-` and `.`, starting with a letter or digit",
+            metadata.name
+        );
+    }
+    if !metadata.version.starts_with(|c: char| c.is_ascii_digit())
+        || metadata.version.contains(char::is_whitespace)
+    {
+        bail!(
+            "invalid package version {:?}, debian versions start with a digit and have no whitespace",
+            metadata.version
+        );
+    }
+    Ok(())
+}
+
+fn debian_architecture() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        "x86" => "i386",
+        "arm" => "armhf",
+        "powerpc64" => "ppc64el",
+        arch => arch,
+    }
+}
+
+/// size of the installed files, hardlinked files (`build --dedup`) are installed as hard links and counted once
+fn installed_size_kib(dist: &Path) -> Result<u64> {
+    let mut total = 0;
+    let mut seen = HashSet::new();
+    for entry in WalkDir::new(dist) {
+        let entry = entry.with_context(|| anyhow!("failed in walking {}", dist.display()))?;
+        if entry.file_type().is_file() {
+            let meta = entry.metadata()?;
+            if seen.insert((meta.dev(), meta.ino())) {
+                total += meta.len();
+            }
+        }
+    }
+    Ok(total.div_ceil(1024))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn metadata(name: &str, version: &str) -> Metadata {
+        Metadata {
+            name: name.to_string(),
+            version: version.to_string(),
+            maintainer: None,
+            description: Some("a test app".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_write_deb() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dist = tmp.path().join("dist");
+        fs::create_dir_all(&dist).unwrap();
+        fs::write(dist.join("bootstrap.sh"), "echo hello\n").unwrap();
+
+        let metadata = metadata("hello", "1.0.0");
+        let out = deb_path(&dist, &metadata);
+        write_deb(&dist, &metadata, &out).unwrap();
+
+        let mut archive = ar::Archive::new(fs::File::open(&out).unwrap());
+        let mut members = Vec::new();
+        while let Some(entry) = archive.next_entry() {
+            let mut entry = entry.unwrap();
+            let name = String::from_utf8(entry.header().identifier().to_vec()).unwrap();
+            let mut contents = Vec::new();
+            entry.read_to_end(&mut contents).unwrap();
+            members.push((name, contents));
+        }
+        let names: Vec<&str> = members.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, ["debian-binary", "control.tar.gz", "data.tar.gz"]);
+
+        let mut data = tar::Archive::new(flate2::read::GzDecoder::new(&members[2].1[..]));
+        let paths: Vec<PathBuf> = data
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().to_path_buf())
+            .collect();
+        assert!(paths.contains(&PathBuf::from("opt/hello/bootstrap.sh")));
+        assert!(paths.contains(&PathBuf::from("usr/bin/hello")));
+    }
+
+    #[test]
+    fn test_installed_size_counts_hard_links_once() {
+        let tmp = tempfile::tempdir().unwrap();
+        fs::write(tmp.path().join("a.ttf"), vec![0u8; 4096]).unwrap();
+        fs::hard_link(tmp.path().join("a.ttf"), tmp.path().join("b.ttf")).unwrap();
+        fs::write(tmp.path().join("c.ttf"), vec![0u8; 2048]).unwrap();
+        assert_eq!(installed_size_kib(tmp.path()).unwrap(), 6);
+    }
+
+    #[test]
+    fn test_validate_metadata() {
+        assert!(validate_metadata(&metadata("hello-app", "1.0.0")).is_ok());
+        assert!(validate_metadata(&metadata("Hello", "1.0.0")).is_err());
+        assert!(validate_metadata(&metadata("hello", "v1")).is_err());
+    }
+}
@@ -0,0 +1,466 @@
+// structural diff between two dists (or two build records)
+// files are grouped into units using the dist layout (see `pkg::paths`):
+// shared libraries in `reals/r` (by their original file name), python packages in `site_packages/<alias>`,
+// the stdlib, and everything else by its top level directory
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{Context, Result, anyhow};
+use lazy_static::lazy_static;
+use log::{info, warn};
+use regex::Regex;
+use serde::Serialize;
+use walkdir::WalkDir;
+
+use crate::{
+    digest::make_digest,
+    pkg::record::{BuildRecord, build_record_path},
+    site_pkgs::normalize_package_name,
+};
+
+lazy_static! {
+    static ref REALS_PREFIX_RE: Regex =
+        Regex::new(r"^[0-9a-f]{4}_").expect("failed to compile regex for reals file names");
+}
+
+/// a single shipped file
+#[derive(Debug, Clone)]
+pub struct DistFile {
+    // relative to dist
+    pub path: PathBuf,
+    pub sha: String,
+    pub size: u64,
+    // original file name of a file in `reals/r`, from the build record, or from the reals file name without its digest prefix
+    // files without an extension are named by their whole digest in reals, they are named by a symlink to them
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(tag = "kind", content = "name", rename_all = "snake_case")]
+pub enum Unit {
+    SharedLibrary(String),
+    Package(String),
+    Stdlib,
+    Other(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Added,
+    Removed,
+    Changed,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UnitDiff {
+    pub unit: Unit,
+    pub status: Status,
+    pub size_before: u64,
+    pub size_after: u64,
+    pub files_added: usize,
+    pub files_removed: usize,
+    pub files_changed: usize,
+}
+
+impl UnitDiff {
+    pub fn size_delta(&self) -> i64 {
+        self.size_after as i64 - self.size_before as i64
+    }
+}
+
+/// read the shipped files of a dist or a build record
+/// a dist with a build record uses the record (digests of the original files), otherwise every file in the dist is hashed
+pub fn read_files(path: &Path) -> Result<Vec<DistFile>> {
+    let record_path = if path.is_dir() {
+        build_record_path(path)
+    } else {
+        path.to_path_buf()
+    };
+    if record_path.is_file() {
+        info!("diff: reading build record {}", record_path.display());
+        return Ok(files_from_record(&BuildRecord::read(&record_path)?));
+    }
+    if !path.is_dir() {
+        return Err(anyhow!(
+            "{} is neither a dist nor a build record",
+            path.display()
+        ));
+    }
+    warn!(
+        "no build record found in {}, hashing every file in it. Digests of patched files differ from a build record, compare two dists of the same kind",
+        path.display()
+    );
+    files_from_dist(path)
+}
+
+fn files_from_record(record: &BuildRecord) -> Vec<DistFile> {
+    let mut by_path: BTreeMap<PathBuf, DistFile> = BTreeMap::new();
+    for node in &record.nodes {
+        // true copies share their reals, keep the first one
+        if let Some(path) = node
+            .layout
+            .reals
+            .as_ref()
+            .or(node.layout.destination.as_ref())
+        {
+            by_path.entry(path.clone()).or_insert_with(|| DistFile {
+                path: path.clone(),
+                sha: node.sha.clone(),
+                size: node.size,
+                name: node
+                    .path
+                    .file_name()
+                    .map(|n| n.to_string_lossy().to_string()),
+            });
+        }
+    }
+    by_path.into_values().collect()
+}
+
+fn files_from_dist(dist: &Path) -> Result<Vec<DistFile>> {
+    let names = reals_names(dist)?;
+    let mut files = Vec::new();
+    for entry in WalkDir::new(dist) {
+        let entry = entry.with_context(|| anyhow!("failed in walking {}", dist.display()))?;
+        // symlinks point to files we already see
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let path = entry.path().to_path_buf();
+        let size = entry
+            .metadata()
+            .with_context(|| anyhow!("failed in reading metadata of {}", path.display()))?
+            .len();
+        let rel_path = path
+            .strip_prefix(dist)
+            .expect("fatal: walked path is not inside dist")
+            .to_path_buf();
+        files.push(DistFile {
+            sha: make_digest(&path)
+                .with_context(|| anyhow!("failed in computing digest of {}", path.display()))?,
+            name: names.get(&rel_path).cloned(),
+            path: rel_path,
+            size,
+        });
+    }
+    Ok(files)
+}
+
+/// file in `reals/r` (relative to dist) -> its original file name, the same name a build record has
+/// `<sha4>_libz.so.1` is `libz.so.1` (see `pkg::paths`), files named by their digest get the name of the first symlink to them (`bin/b/ffmpeg`)
+fn reals_names(dist: &Path) -> Result<HashMap<PathBuf, String>> {
+    let mut names = symlink_names(dist)?;
+    let reals = dist.join("reals").join("r");
+    if reals.is_dir() {
+        for entry in fs::read_dir(&reals)
+            .with_context(|| anyhow!("failed in reading {}", reals.display()))?
+        {
+            let name = entry?.file_name().to_string_lossy().to_string();
+            if REALS_PREFIX_RE.is_match(&name) {
+                names.insert(
+                    Path::new("reals").join("r").join(&name),
+                    REALS_PREFIX_RE.replace(&name, "").to_string(),
+                );
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// file in `reals/r` (relative to dist) -> name of the first symlink to it (`lib/l/libz.so.1`, `bin/b/ffmpeg`, symlink farms)
+fn symlink_names(dist: &Path) -> Result<HashMap<PathBuf, String>> {
+    let dist = dist
+        .canonicalize()
+        .with_context(|| anyhow!("failed in resolving {}", dist.display()))?;
+    let mut names = HashMap::new();
+    for entry in WalkDir::new(&dist).sort_by_file_name() {
+        let entry = entry.with_context(|| anyhow!("failed in walking {}", dist.display()))?;
+        if !entry.path_is_symlink() {
+            continue;
+        }
+        // dangling symlinks are not shipped files
+        let Ok(target) = entry.path().canonicalize() else {
+            continue;
+        };
+        if let Ok(rel_target) = target.strip_prefix(&dist)
+            && rel_target.starts_with("reals/r")
+        {
+            names
+                .entry(rel_target.to_path_buf())
+                .or_insert_with(|| entry.file_name().to_string_lossy().to_string());
+        }
+    }
+    Ok(names)
+}
+
+/// the unit of a file, shared libraries are named by their original file name if it is known
+pub fn unit_of_file(file: &DistFile) -> Unit {
+    match (unit_of(&file.path), &file.name) {
+        (Unit::SharedLibrary(_), Some(name)) => Unit::SharedLibrary(name.clone()),
+        (unit, _) => unit,
+    }
+}
+
+pub fn unit_of(path: &Path) -> Unit {
+    let parts: Vec<String> = path
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(c) => Some(c.to_string_lossy().to_string()),
+            _ => None,
+        })
+        .collect();
+    let part = |i: usize| parts.get(i).map(|s| s.as_str());
+    match (part(0), part(1), part(2)) {
+        (Some("reals"), Some("r"), Some(name)) => {
+            Unit::SharedLibrary(REALS_PREFIX_RE.replace(name, "").to_string())
+        }
+        (Some("site_packages"), Some(_alias), Some(top)) => Unit::Package(package_of(top)),
+        (Some("python"), Some("lib"), Some(_)) => Unit::Stdlib,
+        (Some(first), _, _) => Unit::Other(first.to_string()),
+        (None, _, _) => Unit::Other(String::new()),
+    }
+}
+
+/// the package a top level entry of site-packages belongs to
+/// `numpy`, `numpy.libs`, `numpy-2.0.0.dist-info` and `numpy.cpython-311-x86_64-linux-gnu.so` all belong to `numpy`
+fn package_of(top: &str) -> String {
+    let name = if top.ends_with(".dist-info") || top.ends_with(".egg-info") {
+        top.split('-').next().unwrap_or(top)
+    } else {
+        top.split('.').next().unwrap_or(top)
+    };
+    normalize_package_name(name)
+}
+
+pub fn diff(before: &[DistFile], after: &[DistFile]) -> Vec<UnitDiff> {
+    let by_path = |files: &[DistFile]| -> HashMap<PathBuf, DistFile> {
+        files.iter().map(|f| (f.path.clone(), f.clone())).collect()
+    };
+    let before_by_path = by_path(before);
+    let after_by_path = by_path(after);
+
+    // (before, after) files of every unit
+    let mut units: BTreeMap<Unit, (Vec<&DistFile>, Vec<&DistFile>)> = BTreeMap::new();
+    for f in before_by_path.values() {
+        units.entry(unit_of_file(f)).or_default().0.push(f);
+    }
+    for f in after_by_path.values() {
+        units.entry(unit_of_file(f)).or_default().1.push(f);
+    }
+
+    let mut diffs = Vec::new();
+    for (unit, (files_before, files_after)) in units {
+        let size_before = files_before.iter().map(|f| f.size).sum();
+        let size_after = files_after.iter().map(|f| f.size).sum();
+        let (added, removed, changed) = match unit {
+            // shared libraries change their file name (digest prefix) when they change, compare the digests directly
+            Unit::SharedLibrary(_) => {
+                let mut shas_before: Vec<&str> =
+                    files_before.iter().map(|f| f.sha.as_str()).collect();
+                let mut shas_after: Vec<&str> =
+                    files_after.iter().map(|f| f.sha.as_str()).collect();
+                shas_before.sort();
+                shas_after.sort();
+                if files_before.is_empty() {
+                    (files_after.len(), 0, 0)
+                } else if files_after.is_empty() {
+                    (0, files_before.len(), 0)
+                } else {
+                    (0, 0, usize::from(shas_before != shas_after))
+                }
+            }
+            _ => {
+                let added = files_after
+                    .iter()
+                    .filter(|f| !before_by_path.contains_key(&f.path))
+                    .count();
+                let removed = files_before
+                    .iter()
+                    .filter(|f| !after_by_path.contains_key(&f.path))
+                    .count();
+                let changed = files_after
+                    .iter()
+                    .filter(|f| before_by_path.get(&f.path).is_some_and(|b| b.sha != f.sha))
+                    .count();
+                (added, removed, changed)
+            }
+        };
+        let status = if files_before.is_empty() {
+            Status::Added
+        } else if files_after.is_empty() {
+            Status::Removed
+        } else if added + removed + changed > 0 {
+            Status::Changed
+        } else {
+            continue;
+        };
+        diffs.push(UnitDiff {
+            unit,
+            status,
+            size_before,
+            size_after,
+            files_added: added,
+            files_removed: removed,
+            files_changed: changed,
+        });
+    }
+    diffs
+}
+
+/// total size of all files
+pub fn total_size(files: &[DistFile]) -> u64 {
+    files.iter().map(|f| f.size).sum()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pkg::{paths::NodeLayout, record::NodeRecord};
+    use std::os::unix::fs::symlink;
+
+    fn file(path: &str, sha: &str, size: u64) -> DistFile {
+        DistFile {
+            path: PathBuf::from(path),
+            sha: sha.to_string(),
+            size,
+            name: None,
+        }
+    }
+
+    #[test]
+    fn test_unit_of() {
+        assert_eq!(
+            unit_of(Path::new("reals/r/ab12_libz.so.1")),
+            Unit::SharedLibrary("libz.so.1".to_string())
+        );
+        assert_eq!(
+            unit_of(Path::new("site_packages/sp_0/numpy-2.0.0.dist-info/RECORD")),
+            Unit::Package("numpy".to_string())
+        );
+        assert_eq!(
+            unit_of(Path::new("site_packages/sp_0/PyYAML.libs/x.so")),
+            Unit::Package("pyyaml".to_string())
+        );
+        assert_eq!(
+            unit_of(Path::new("python/lib/python3.11/os.py")),
+            Unit::Stdlib
+        );
+        assert_eq!(
+            unit_of(Path::new("main.py")),
+            Unit::Other("main.py".to_string())
+        );
+    }
+
+    #[test]
+    fn test_diff() {
+        let before = vec![
+            file("reals/r/aaaa_libz.so", "aaaa1", 10),
+            file("reals/r/bbbb_libold.so", "bbbb1", 5),
+            file("site_packages/sp/six.py", "s1", 3),
+            file("site_packages/sp/numpy/__init__.py", "n1", 7),
+        ];
+        let after = vec![
+            file("reals/r/cccc_libz.so", "cccc1", 12),
+            file("site_packages/sp/six.py", "s1", 3),
+            file("site_packages/sp/numpy/__init__.py", "n2", 8),
+            file("site_packages/sp/numpy/linalg.py", "n3", 1),
+        ];
+        let diffs = diff(&before, &after);
+        let find = |u: Unit| diffs.iter().find(|d| d.unit == u).unwrap();
+
+        assert_eq!(diffs.len(), 3);
+        let libz = find(Unit::SharedLibrary("libz.so".to_string()));
+        assert_eq!(libz.status, Status::Changed);
+        assert_eq!(libz.size_delta(), 2);
+        assert_eq!(
+            find(Unit::SharedLibrary("libold.so".to_string())).status,
+            Status::Removed
+        );
+        let numpy = find(Unit::Package("numpy".to_string()));
+        assert_eq!(numpy.status, Status::Changed);
+        assert_eq!(
+            (numpy.files_added, numpy.files_removed, numpy.files_changed),
+            (1, 0, 1)
+        );
+        assert_eq!(numpy.size_delta(), 2);
+    }
+
+    #[test]
+    fn test_full_digest_reals_names() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dist = tmp.path().join("dist");
+        let reals = dist.join("reals").join("r");
+        fs::create_dir_all(&reals).unwrap();
+        fs::create_dir_all(dist.join("bin").join("b")).unwrap();
+        let sha = "a".repeat(64);
+        fs::write(reals.join(&sha), "ffmpeg").unwrap();
+        symlink(
+            Path::new("../../reals/r").join(&sha),
+            dist.join("bin").join("b").join("ffmpeg"),
+        )
+        .unwrap();
+
+        let files = files_from_dist(&dist).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(
+            unit_of_file(&files[0]),
+            Unit::SharedLibrary("ffmpeg".to_string())
+        );
+
+        // a rebuilt executable has another digest as its file name, it is still the same unit
+        let mut before = file(&format!("reals/r/{}", "b".repeat(64)), "b", 5);
+        before.name = Some("ffmpeg".to_string());
+        let diffs = diff(&[before], &files);
+        assert_eq!(diffs.len(), 1);
+        assert_eq!(diffs[0].unit, Unit::SharedLibrary("ffmpeg".to_string()));
+        assert_eq!(diffs[0].status, Status::Changed);
+    }
+
+    #[test]
+    fn test_dist_without_record_against_record() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dist = tmp.path().join("dist");
+        let reals = dist.join("reals").join("r");
+        let lib = dist.join("lib").join("l");
+        fs::create_dir_all(&reals).unwrap();
+        fs::create_dir_all(&lib).unwrap();
+        fs::write(reals.join("abcd_libz.so.1"), "libz").unwrap();
+        // the alias comes first when walking the symlinks
+        for name in ["libz.so", "libz.so.1"] {
+            symlink(Path::new("../../reals/r/abcd_libz.so.1"), lib.join(name)).unwrap();
+        }
+        let without_record = files_from_dist(&dist).unwrap();
+        assert_eq!(without_record[0].name.as_deref(), Some("libz.so.1"));
+
+        let record = BuildRecord {
+            shenzi_version: String::new(),
+            bytecode: Default::default(),
+            zip_packages: false,
+            strip: Default::default(),
+            debug_archive: false,
+            nodes: vec![NodeRecord {
+                path: PathBuf::from("/env/lib/libz.so.1"),
+                sha: "abcd1".to_string(),
+                size: 4,
+                kind: "BinaryInLDPath".to_string(),
+                layout: NodeLayout {
+                    destination: None,
+                    reals: Some(PathBuf::from("reals/r/abcd_libz.so.1")),
+                    symlink_farm: Some(PathBuf::from("symlinks/abcd_libz.so.1")),
+                },
+                deps: Vec::new(),
+            }],
+        };
+        let with_record = files_from_record(&record);
+        // the digests differ between the modes, the library is changed and not renamed
+        let diffs = diff(&with_record, &without_record);
+        assert_eq!(diffs.len(), 1);
+        assert_eq!(diffs[0].unit, Unit::SharedLibrary("libz.so.1".to_string()));
+        assert_eq!(diffs[0].status, Status::Changed);
+    }
+}
@@ -1,21 +1,24 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::path::PathBuf;
 
 use anyhow::Result;
 
-use crate::node::{Node, Pkg, deps::Deps};
+use crate::{
+    node::{Node, Pkg, deps::Deps},
+    parse::KnownLibs,
+};
 
 pub trait Factory {
     fn make(
         &self,
         path: &PathBuf,
-        known_libs: &HashMap<String, PathBuf>,
+        known_libs: &KnownLibs,
         extra_search_paths: &Vec<PathBuf>,
     ) -> Result<Option<Node>>;
 
     fn make_binary(
         &self,
         path: &PathBuf,
-        known_libs: &HashMap<String, PathBuf>,
+        known_libs: &KnownLibs,
         extra_search_paths: &Vec<PathBuf>,
     ) -> Result<Option<Node>>;
 
@@ -23,7 +26,7 @@ pub trait Factory {
         &self,
         path: &PathBuf,
         symlinks: &Vec<String>,
-        known_libs: &HashMap<String, PathBuf>,
+        known_libs: &KnownLibs,
         extra_search_paths: &Vec<PathBuf>,
     ) -> Result<Option<Node>>;
 
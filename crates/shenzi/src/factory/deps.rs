@@ -2,14 +2,18 @@ use std::{collections::HashMap, path::PathBuf};
 
 use anyhow::Result;
 
-use crate::{node::deps::Deps, parse::BinaryParseError, paths::is_maybe_object_file};
+use crate::{
+    node::deps::Deps,
+    parse::{BinaryParseError, KnownLibs},
+    paths::is_maybe_object_file,
+};
 
 pub fn create_deps(
     path: &PathBuf,
     executable_path: &PathBuf,
     cwd: &PathBuf,
     env: &HashMap<String, String>,
-    known_libs: &HashMap<String, PathBuf>,
+    known_libs: &KnownLibs,
     extra_search_paths: &Vec<PathBuf>,
     force_object_file_parsing: bool,
 ) -> Result<Deps> {
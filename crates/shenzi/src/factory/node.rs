@@ -13,6 +13,7 @@ use crate::{
     },
     manifest::{Skip, Version},
     node::{Node, Pkg, deps::Deps},
+    parse::KnownLibs,
     paths::is_maybe_object_file,
     paths::normalize_path,
     site_pkgs::SitePkgs,
@@ -52,7 +53,7 @@ impl NodeFactory {
     fn create_deps(
         &self,
         path: &PathBuf,
-        known_libs: &HashMap<String, PathBuf>,
+        known_libs: &KnownLibs,
         extra_search_paths: &Vec<PathBuf>,
         force_object_file_parsing: bool,
     ) -> Result<Deps> {
@@ -99,7 +100,7 @@ impl Factory for NodeFactory {
         &self,
         path: &PathBuf,
         symlinks: &Vec<String>,
-        known_libs: &HashMap<String, PathBuf>,
+        known_libs: &KnownLibs,
         extra_search_paths: &Vec<PathBuf>,
     ) -> Result<Option<Node>> {
         let deps = self.create_deps(&path, known_libs, extra_search_paths, true)?;
@@ -127,7 +128,7 @@ impl Factory for NodeFactory {
     fn make_binary(
         &self,
         path: &PathBuf,
-        known_libs: &HashMap<String, PathBuf>,
+        known_libs: &KnownLibs,
         extra_search_paths: &Vec<PathBuf>,
     ) -> Result<Option<Node>> {
         let deps = self.create_deps(&path, known_libs, extra_search_paths, true)?;
@@ -149,7 +150,7 @@ impl Factory for NodeFactory {
     fn make(
         &self,
         path: &PathBuf,
-        known_libs: &HashMap<String, PathBuf>,
+        known_libs: &KnownLibs,
         extra_search_paths: &Vec<PathBuf>,
     ) -> Result<Option<Node>> {
         let p = normalize_path(path);
@@ -216,7 +217,7 @@ impl Factory for NodeFactory {
         Node::new(
             path.clone(),
             Pkg::Executable,
-            self.create_deps(path, &HashMap::new(), &Vec::new(), true)?,
+            self.create_deps(path, &KnownLibs::default(), &Vec::new(), true)?,
         )
     }
 }
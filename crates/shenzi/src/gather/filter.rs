@@ -0,0 +1,112 @@
+// exclude/include glob rules for files in site-packages and the stdlib (`[files]` in the shenzi workspace file)
+// paths are matched relative to the site-packages directory (or the stdlib directory) they are in
+// a file is excluded if it matches an exclude rule and does not match any include rule
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::Serialize;
+
+use crate::workspace::FileRules;
+
+/// a file which was not added to the graph, and the rule which excluded it
+#[derive(Debug, Clone, Serialize)]
+pub struct ExcludedFile {
+    pub path: PathBuf,
+    pub rule: String,
+}
+
+pub struct FileFilter {
+    exclude: GlobSet,
+    exclude_patterns: Vec<String>,
+    include: GlobSet,
+    // every excluded file, in the order they were seen
+    pub excluded: Vec<ExcludedFile>,
+}
+
+impl FileFilter {
+    pub fn new(rules: &FileRules) -> Result<Self> {
+        Ok(Self {
+            exclude: glob_set(&rules.exclude)?,
+            exclude_patterns: rules.exclude.clone(),
+            include: glob_set(&rules.include)?,
+            excluded: Vec::new(),
+        })
+    }
+
+    /// the paths which are kept, `base` is the site-packages (or stdlib) directory the paths are in
+    /// paths outside `base` are always kept
+    pub fn retain(&mut self, paths: Vec<PathBuf>, base: &Path) -> Vec<PathBuf> {
+        if self.exclude_patterns.is_empty() {
+            return paths;
+        }
+        let mut kept = Vec::with_capacity(paths.len());
+        for path in paths {
+            match self.excluded_by(&path, base) {
+                Some(rule) => self.excluded.push(ExcludedFile { path, rule }),
+                None => kept.push(path),
+            }
+        }
+        kept
+    }
+
+    fn excluded_by(&self, path: &Path, base: &Path) -> Option<String> {
+        let rel_path = path.strip_prefix(base).ok()?;
+        if self.include.is_match(rel_path) {
+            return None;
+        }
+        self.exclude
+            .matches(rel_path)
+            .first()
+            .map(|i| self.exclude_patterns[*i].clone())
+    }
+}
+
+fn glob_set(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(
+            Glob::new(pattern).with_context(|| anyhow!("invalid glob pattern {:?}", pattern))?,
+        );
+    }
+    builder.build().context("failed in building glob set")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_retain() {
+        let rules = FileRules {
+            exclude: vec!["**/tests/**".to_string(), "**/*.pyi".to_string()],
+            include: vec!["numpy/tests/keep.py".to_string()],
+        };
+        let mut filter = FileFilter::new(&rules).unwrap();
+        let base = PathBuf::from("/env/site-packages");
+        let paths = [
+            "numpy/__init__.py",
+            "numpy/__init__.pyi",
+            "numpy/tests/test_a.py",
+            "numpy/tests/keep.py",
+        ]
+        .iter()
+        .map(|p| base.join(p))
+        .chain([PathBuf::from("/usr/bin/tests/x.pyi")])
+        .collect();
+
+        let kept = filter.retain(paths, &base);
+        assert_eq!(
+            kept,
+            vec![
+                base.join("numpy/__init__.py"),
+                base.join("numpy/tests/keep.py"),
+                PathBuf::from("/usr/bin/tests/x.pyi"),
+            ]
+        );
+        assert_eq!(filter.excluded.len(), 2);
+        assert_eq!(filter.excluded[0].rule, "**/*.pyi");
+        assert_eq!(filter.excluded[1].rule, "**/tests/**");
+    }
+}
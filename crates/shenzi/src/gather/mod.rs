@@ -2,40 +2,51 @@
 // // given a shenzi manifest, gather all the nodes that we can discover
 use std::{
     collections::{HashMap, HashSet},
-    path::PathBuf,
+    path::{Path, PathBuf},
+    time::Instant,
 };
 
 use anyhow::{Context, Error, Result, anyhow, bail};
 use log::{info, warn};
+use rayon::prelude::*;
 use walkdir::WalkDir;
 
 mod error;
+pub mod filter;
 
 pub use crate::factory::NodeFactory;
 pub use crate::site_pkgs::PythonPathComponent;
 
 use crate::{
     factory::Factory,
-    gather::error::MultipleGatherErrors,
+    gather::{
+        error::MultipleGatherErrors,
+        filter::{ExcludedFile, FileFilter},
+    },
     graph::FileGraph,
     manifest::{LoadKind, ShenziManifest},
     node::{Node, deps::Deps},
-    parse::{ErrDidNotFindDependencies, ErrDidNotFindDependency},
+    parse::{ErrDidNotFindDependencies, ErrDidNotFindDependency, KnownLibs},
     paths::{
         file_name_as_str, marker_file_name, normalize_path,
         split_colon_separated_into_valid_search_paths,
     },
+    report::Timings,
     site_pkgs::{PyPackage, SitePkgs, normalize_package_name},
     warnings::Warning,
+    workspace::FileRules,
 };
 
 pub fn build_graph_from_manifest(
     manifest: &ShenziManifest,
     cwd: &PathBuf,
+    rules: &FileRules,
+    timings: &mut Timings,
 ) -> Result<(
     FileGraph<NodeFactory>,
     Vec<PythonPathComponent>,
     Vec<Warning>,
+    Vec<ExcludedFile>,
 )> {
     let site_pkgs = SitePkgs::from_manifest(manifest);
     let factory = NodeFactory::new(
@@ -46,18 +57,28 @@ pub fn build_graph_from_manifest(
         manifest.env.clone(),
         manifest.skip.clone(),
     );
-    let (g, warnings) = build_graph(manifest, &factory, &site_pkgs)?;
+    let mut filter =
+        FileFilter::new(rules).context("failed in reading [files] rules of the workspace")?;
+    let (g, warnings) = build_graph(manifest, &factory, &site_pkgs, &mut filter, timings)?;
+    if !filter.excluded.is_empty() {
+        info!(
+            "excluded {} files using the [files] rules of the workspace",
+            filter.excluded.len()
+        );
+    }
 
-    Ok((g, site_pkgs.comps, warnings))
+    Ok((g, site_pkgs.comps, warnings, filter.excluded))
 }
 
 fn build_graph(
     manifest: &ShenziManifest,
     factory: &NodeFactory,
     site_pkgs: &SitePkgs,
+    filter: &mut FileFilter,
+    timings: &mut Timings,
 ) -> Result<(FileGraph<NodeFactory>, Vec<Warning>)> {
     let executable_path = &manifest.python.sys.executable;
-    let known_libs = HashMap::new();
+    let known_libs = KnownLibs::default();
     let mut g = FileGraph::new(factory.clone());
     info!("Build graph: pass 1, begin");
 
@@ -68,12 +89,14 @@ fn build_graph(
     );
     // extra search paths for executable will be empty
     // always serial
+    let start = Instant::now();
     g.add_tree(
         factory.make_py_executable(executable_path)?,
         &known_libs,
         true,
         &Vec::new(),
     )?;
+    timings.record_since("gather: python executable", start);
 
     let executable_extra_paths_to_search = g
         .get_node_by_path(executable_path)
@@ -87,6 +110,7 @@ fn build_graph(
 
     // now add all loads, in the correct order, again, should not fail
     // always serial
+    let start = Instant::now();
     for l in &manifest.loads {
         info!(
             "adding load detected in manifest, path={}",
@@ -122,8 +146,10 @@ fn build_graph(
                 })?,
         };
     }
+    timings.record_since("gather: loads", start);
 
     // binaries in shenzi.json cannot fail
+    let start = Instant::now();
     add_binaries(
         &mut g,
         &get_binaries_which_exist_in_path(manifest),
@@ -131,14 +157,23 @@ fn build_graph(
         &known_libs,
         &executable_extra_paths_to_search,
     )?;
+    timings.record_since("gather: binaries", start);
 
     let mut failures = Vec::new();
+    let start = Instant::now();
     // add exec prefix, can fail
+    // rules see `lib-dynload/...` like in the stdlib, relative to the stdlib directory of the exec prefix
+    // (the stdlib itself when prefix and exec prefix are the same)
     info!("adding stdlib, path={}", site_pkgs.lib_dynload.display());
     add_nodes_recursive(
         &mut g,
         &mut failures,
         &site_pkgs.lib_dynload,
+        site_pkgs
+            .lib_dynload
+            .parent()
+            .unwrap_or(&site_pkgs.lib_dynload),
+        filter,
         &factory,
         &known_libs,
         true,
@@ -151,18 +186,23 @@ fn build_graph(
         &mut g,
         &mut failures,
         &site_pkgs.stdlib,
+        &site_pkgs.stdlib,
+        filter,
         &factory,
         &known_libs,
         true,
         &executable_extra_paths_to_search,
     )?;
 
+    timings.record_since("gather: stdlib", start);
+
     // site-packages addition start
     // we only add the packages which are allowed
 
     let allowed_packages = get_normalized_allowed_packages(manifest);
 
     // now all site-packages, can fail
+    let start = Instant::now();
     for (pkg, _) in &site_pkgs.site_pkg_by_alias {
         info!("adding site-package: path={}", pkg.display());
         if pkg.exists() {
@@ -171,6 +211,7 @@ fn build_graph(
                 &mut g,
                 &mut failures,
                 pkg,
+                filter,
                 &factory,
                 &known_libs,
                 true,
@@ -184,13 +225,16 @@ fn build_graph(
             );
         }
     }
+    timings.record_since("gather: site-packages", start);
 
+    let start = Instant::now();
     let warnings = add_failures(
         &mut g,
         failures,
         &factory,
         &executable_extra_paths_to_search,
     )?;
+    timings.record_since("gather: unresolved dependencies", start);
 
     if !g.contains_path(&manifest.python.main) {
         g.add_tree(
@@ -337,7 +381,7 @@ fn add_binaries(
     g: &mut FileGraph<NodeFactory>,
     bins: &Vec<PathBuf>,
     factory: &NodeFactory,
-    known_libs: &HashMap<String, PathBuf>,
+    known_libs: &KnownLibs,
     extra_search_paths: &Vec<PathBuf>,
 ) -> Result<()> {
     for bin in bins {
@@ -354,8 +398,9 @@ fn add_site_packages(
     g: &mut FileGraph<NodeFactory>,
     failures: &mut Vec<PathBuf>,
     directory: &PathBuf,
+    filter: &mut FileFilter,
     factory: &NodeFactory,
-    known_libs: &HashMap<String, PathBuf>,
+    known_libs: &KnownLibs,
     replace: bool,
     extra_search_paths: &Vec<PathBuf>,
     allowed_packages: &Option<HashSet<String>>,
@@ -365,6 +410,7 @@ fn add_site_packages(
         g,
         failures,
         directory,
+        filter,
         factory,
         known_libs,
         replace,
@@ -378,6 +424,7 @@ fn add_site_packages(
         g,
         failures,
         directory,
+        filter,
         factory,
         known_libs,
         replace,
@@ -393,8 +440,9 @@ fn add_using_dist_info(
     g: &mut FileGraph<NodeFactory>,
     failures: &mut Vec<PathBuf>,
     directory: &PathBuf,
+    filter: &mut FileFilter,
     factory: &NodeFactory,
-    known_libs: &HashMap<String, PathBuf>,
+    known_libs: &KnownLibs,
     replace: bool,
     extra_search_paths: &Vec<PathBuf>,
     allowed_packages: &Option<HashSet<String>>,
@@ -411,6 +459,8 @@ fn add_using_dist_info(
             let (paths, outside_site_packages) = py_pkg.get_installed_files()?;
             build_graph_from_paths(
                 paths,
+                directory,
+                filter,
                 g,
                 failures,
                 factory,
@@ -452,8 +502,9 @@ fn add_remaining_in_site_packages(
     g: &mut FileGraph<NodeFactory>,
     failures: &mut Vec<PathBuf>,
     directory: &PathBuf,
+    filter: &mut FileFilter,
     factory: &NodeFactory,
-    known_libs: &HashMap<String, PathBuf>,
+    known_libs: &KnownLibs,
     replace: bool,
     extra_search_paths: &Vec<PathBuf>,
     allowed_packages: &Option<HashSet<String>>,
@@ -479,6 +530,8 @@ fn add_remaining_in_site_packages(
                         g,
                         failures,
                         &path,
+                        directory,
+                        filter,
                         factory,
                         known_libs,
                         replace,
@@ -498,8 +551,10 @@ fn add_nodes_recursive(
     g: &mut FileGraph<NodeFactory>,
     failures: &mut Vec<PathBuf>,
     directory: &PathBuf,
+    base: &Path,
+    filter: &mut FileFilter,
     factory: &NodeFactory,
-    known_libs: &HashMap<String, PathBuf>,
+    known_libs: &KnownLibs,
     replace: bool,
     extra_search_paths: &Vec<PathBuf>,
 ) -> Result<()> {
@@ -520,6 +575,8 @@ fn add_nodes_recursive(
     let paths = get_paths_recursive_from_dir(directory)?;
     build_graph_from_paths(
         paths,
+        base,
+        filter,
         g,
         failures,
         factory,
@@ -530,26 +587,53 @@ fn add_nodes_recursive(
     Ok(())
 }
 
+// paths excluded by `filter` never become nodes, `base` is the directory the filter rules are relative to
 fn build_graph_from_paths(
     paths: Vec<PathBuf>,
+    base: &Path,
+    filter: &mut FileFilter,
     g: &mut FileGraph<NodeFactory>,
     failures: &mut Vec<PathBuf>,
     factory: &NodeFactory,
-    known_libs: &HashMap<String, PathBuf>,
+    known_libs: &KnownLibs,
     replace: bool,
     extra_search_paths: &Vec<PathBuf>,
 ) {
+    let paths = filter.retain(paths, base);
+    let paths: Vec<PathBuf> = if replace {
+        paths
+    } else {
+        // skip already done
+        paths
+            .into_iter()
+            .filter(|p| g.get_node_by_path(p).is_none())
+            .collect()
+    };
+
+    // parsing and dependency search do not depend on the graph, they run in parallel
+    // nodes are inserted serially in the order of `paths`, the graph is the same as the one of a serial build
+    let start = Instant::now();
+    let made: Vec<(PathBuf, Result<Option<Node>>)> = paths
+        .into_par_iter()
+        .map(|p| {
+            let maybe_node = factory.make(&p, known_libs, extra_search_paths);
+            (p, maybe_node)
+        })
+        .collect();
+    info!(
+        "graph: pass 1: parsed {} files in {:.2}s",
+        made.len(),
+        start.elapsed().as_secs_f64()
+    );
+
     let mut i = 0;
-    let total = paths.len();
-    for p in paths {
-        if !replace {
-            if let Some(_) = g.get_node_by_path(&p) {
-                // skip already done
-                continue;
-            }
+    let total = made.len();
+    for (p, maybe_node) in made {
+        // added as a dependency of a previous path
+        if !replace && g.get_node_by_path(&p).is_some() {
+            continue;
         }
-        let res = factory
-            .make(&p, known_libs, extra_search_paths)
+        let res = maybe_node
             .and_then(|n| add_to_graph_if_some(g, n, known_libs, replace, extra_search_paths));
         if let Err(_) = res {
             if let None = g.get_node_by_path(&p) {
@@ -566,7 +650,7 @@ fn build_graph_from_paths(
 fn add_to_graph_if_some(
     g: &mut FileGraph<NodeFactory>,
     maybe_node: Option<Node>,
-    known_libs: &HashMap<String, PathBuf>,
+    known_libs: &KnownLibs,
     replace: bool,
     extra_search_paths: &Vec<PathBuf>,
 ) -> Result<()> {
@@ -579,7 +663,7 @@ fn add_to_graph_if_some(
     }
 }
 
-fn get_libs_from_graph(g: &FileGraph<NodeFactory>) -> HashMap<String, PathBuf> {
+fn get_libs_from_graph(g: &FileGraph<NodeFactory>) -> KnownLibs {
     let mut known_libs = HashMap::new();
     for n in g.iter_nodes() {
         match n.deps {
@@ -595,7 +679,7 @@ fn get_libs_from_graph(g: &FileGraph<NodeFactory>) -> HashMap<String, PathBuf> {
             Deps::Mock { paths: _ } => {}
         };
     }
-    known_libs
+    KnownLibs::new(known_libs)
 }
 
 fn get_paths_recursive_from_dir(base_path: &PathBuf) -> Result<Vec<PathBuf>> {
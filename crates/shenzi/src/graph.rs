@@ -1,5 +1,5 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{HashMap, HashSet, VecDeque},
     fmt::Display,
     path::{Path, PathBuf},
 };
@@ -7,9 +7,16 @@ use std::{
 use anyhow::{Context, Result, anyhow};
 use bimap::BiHashMap;
 use log::info;
-use petgraph::{algo::toposort, graph::NodeIndex, prelude::StableGraph, visit::EdgeRef, Direction::{Incoming, Outgoing}, Graph};
+use petgraph::{
+    Direction::{Incoming, Outgoing},
+    Graph,
+    algo::toposort,
+    graph::NodeIndex,
+    prelude::StableGraph,
+    visit::EdgeRef,
+};
 
-use crate::{factory::Factory, node::Node, paths::normalize_path};
+use crate::{factory::Factory, node::Node, parse::KnownLibs, paths::normalize_path};
 
 #[derive(Debug)]
 pub struct FileGraph<T: Factory> {
@@ -106,7 +113,7 @@ impl<T: Factory> FileGraph<T> {
     pub fn add_tree(
         &mut self,
         node: Node,
-        known_libs: &HashMap<String, PathBuf>,
+        known_libs: &KnownLibs,
         replace: bool,
         search_paths: &Vec<PathBuf>,
     ) -> Result<NodeIndex> {
@@ -142,7 +149,7 @@ impl<T: Factory> FileGraph<T> {
     fn add_parents(
         &mut self,
         deps: &Vec<PathBuf>,
-        known_libs: &HashMap<String, PathBuf>,
+        known_libs: &KnownLibs,
         search_paths: &Vec<PathBuf>,
     ) -> Result<Vec<NodeIndex>> {
         let mut all_parent_idx = Vec::new();
@@ -203,6 +210,52 @@ impl<T: Factory> FileGraph<T> {
             .unwrap_or(vec![])
     }
 
+    pub fn get_node_dependents(&self, node: &Node) -> Vec<Node> {
+        // given a node, return all the nodes which depend on it
+        self.idx_by_path
+            .get_by_right(&node.path)
+            .map(|idx| {
+                self.inner
+                    .edges_directed(*idx, Outgoing)
+                    .map(|e| self.get_node_by_index_or_panic(e.target()))
+                    .collect::<Vec<Node>>()
+            })
+            .unwrap_or(vec![])
+    }
+
+    /// walk the dependents of `node` breadth first, for every node accepted by `is_root` return the shortest chain
+    /// the chain starts at the root and ends at `node`, shorter chains come first
+    pub fn chains_to_node<F: Fn(&Node) -> bool>(&self, node: &Node, is_root: F) -> Vec<Vec<Node>> {
+        let start = match self.idx_by_path.get_by_right(&node.path) {
+            Some(idx) => *idx,
+            None => return vec![],
+        };
+        // for every visited node, the node we came from (one step closer to `node`)
+        let mut came_from: HashMap<NodeIndex, NodeIndex> = HashMap::new();
+        let mut visited = HashSet::from([start]);
+        let mut queue = VecDeque::from([start]);
+        let mut chains = Vec::new();
+        while let Some(idx) = queue.pop_front() {
+            let current = self.get_node_by_index_or_panic(idx);
+            if is_root(&current) {
+                let mut chain = vec![current];
+                let mut step = idx;
+                while let Some(next) = came_from.get(&step) {
+                    chain.push(self.get_node_by_index_or_panic(*next));
+                    step = *next;
+                }
+                chains.push(chain);
+            }
+            for e in self.inner.edges_directed(idx, Outgoing) {
+                if visited.insert(e.target()) {
+                    came_from.insert(e.target(), idx);
+                    queue.push_back(e.target());
+                }
+            }
+        }
+        chains
+    }
+
     pub fn _replace_node_in_graph(
         &mut self,
         idx: NodeIndex,
@@ -269,7 +322,7 @@ mod test {
         fn make(
             &self,
             path: &PathBuf,
-            _known_libs: &HashMap<String, PathBuf>,
+            _known_libs: &KnownLibs,
             _extra_search_paths: &Vec<PathBuf>,
         ) -> Result<Option<Node>> {
             let deps = self
@@ -283,7 +336,7 @@ mod test {
         fn make_binary(
             &self,
             path: &PathBuf,
-            known_libs: &HashMap<String, PathBuf>,
+            known_libs: &KnownLibs,
             extra_search_paths: &Vec<PathBuf>,
         ) -> Result<Option<Node>> {
             // TODO add actual binary creation support if neded
@@ -291,7 +344,7 @@ mod test {
         }
 
         fn make_py_executable(&self, path: &PathBuf) -> Result<Node> {
-            self.make(path, &HashMap::new(), &Vec::new())
+            self.make(path, &KnownLibs::default(), &Vec::new())
                 .map(|n| n.unwrap())
         }
 
@@ -299,10 +352,10 @@ mod test {
             &self,
             path: &PathBuf,
             _symlinks: &Vec<String>,
-            _known_libs: &HashMap<String, PathBuf>,
+            _known_libs: &KnownLibs,
             _extra_search_paths: &Vec<PathBuf>,
         ) -> Result<Option<Node>> {
-            self.make(path, &HashMap::new(), &Vec::new())
+            self.make(path, &KnownLibs::default(), &Vec::new())
         }
     }
     fn create_temp_dir() -> tempfile::TempDir {
@@ -320,7 +373,7 @@ mod test {
         let path = touch_path(&tmp, "python");
         let node = Node::mock(path, vec![]).unwrap();
         let idx = graph
-            .add_tree(node, &HashMap::new(), false, &Vec::new())
+            .add_tree(node, &KnownLibs::default(), false, &Vec::new())
             .unwrap();
         assert_eq!(graph.inner.node_count(), 1);
         assert!(graph.idx_by_path.contains_left(&idx));
@@ -338,7 +391,7 @@ mod test {
         let mut graph = get_graph(HashMap::from([(p_python, vec![p_lib_test])]));
 
         graph
-            .add_tree(py_node.clone(), &HashMap::new(), false, &Vec::new())
+            .add_tree(py_node.clone(), &KnownLibs::default(), false, &Vec::new())
             .unwrap();
         assert_eq!(graph.inner.node_count(), 2);
         assert_eq!(graph.inner.edge_count(), 1);
@@ -355,12 +408,12 @@ mod test {
         let node = Node::mock(p_python, vec![]).unwrap();
 
         graph
-            .add_tree(node.clone(), &HashMap::new(), false, &Vec::new())
+            .add_tree(node.clone(), &KnownLibs::default(), false, &Vec::new())
             .unwrap();
         assert_eq!(graph.inner.node_count(), 1);
 
         graph
-            .add_tree(node.clone(), &HashMap::new(), false, &Vec::new())
+            .add_tree(node.clone(), &KnownLibs::default(), false, &Vec::new())
             .unwrap();
         assert_eq!(graph.inner.node_count(), 1); // Should not add duplicate
     }
@@ -394,7 +447,7 @@ mod test {
         ]);
         let mut graph = get_graph(path_by_deps);
 
-        let result = graph.add_tree(main.clone(), &HashMap::new(), false, &Vec::new());
+        let result = graph.add_tree(main.clone(), &KnownLibs::default(), false, &Vec::new());
         println!("*************end complex adding**********************");
         assert!(result.is_ok());
         assert_eq!(graph.inner.node_count(), 4);
@@ -413,6 +466,79 @@ mod test {
         assert_before(&nodes, &dep3, &main);
     }
 
+    // main -> dep1 -> dep2, main -> dep3 -> dep2
+    fn get_diamond_graph(
+        tmp: &tempfile::TempDir,
+    ) -> (FileGraph<MockFactory>, Node, Node, Node, Node) {
+        let dep2_path = touch_path(tmp, "dep2.py");
+        let dep1_path = touch_path(tmp, "libdep1");
+        let dep3_path = touch_path(tmp, "libdep3");
+        let main_path = touch_path(tmp, "python");
+        let path_by_deps = HashMap::from([
+            (
+                main_path.clone(),
+                vec![dep1_path.clone(), dep3_path.clone()],
+            ),
+            (dep1_path.clone(), vec![dep2_path.clone()]),
+            (dep3_path.clone(), vec![dep2_path.clone()]),
+            (dep2_path.clone(), vec![]),
+        ]);
+        let main = Node::mock(main_path, vec![dep1_path.clone(), dep3_path.clone()]).unwrap();
+        let dep1 = Node::mock(dep1_path, vec![dep2_path.clone()]).unwrap();
+        let dep3 = Node::mock(dep3_path, vec![dep2_path.clone()]).unwrap();
+        let dep2 = Node::mock(dep2_path, vec![]).unwrap();
+
+        let mut graph = get_graph(path_by_deps);
+        graph
+            .add_tree(main.clone(), &KnownLibs::default(), false, &Vec::new())
+            .unwrap();
+        (graph, main, dep1, dep2, dep3)
+    }
+
+    #[test]
+    fn test_get_node_dependents() {
+        let tmp = create_temp_dir();
+        let (graph, main, dep1, dep2, dep3) = get_diamond_graph(&tmp);
+
+        let dependents = |node: &Node| {
+            let mut paths: Vec<PathBuf> = graph
+                .get_node_dependents(node)
+                .into_iter()
+                .map(|n| n.path)
+                .collect();
+            paths.sort();
+            paths
+        };
+        assert_eq!(
+            dependents(&dep2),
+            vec![dep1.path.clone(), dep3.path.clone()]
+        );
+        assert_eq!(dependents(&dep1), vec![main.path.clone()]);
+        assert!(dependents(&main).is_empty());
+    }
+
+    #[test]
+    fn test_chains_to_node() {
+        let tmp = create_temp_dir();
+        let (graph, main, dep1, dep2, dep3) = get_diamond_graph(&tmp);
+
+        // main is reachable through both dep1 and dep3, only the first chain found is kept
+        let chains = graph.chains_to_node(&dep2, |n| n.path == main.path || n.path == dep3.path);
+        assert_eq!(chains.len(), 2);
+        assert_eq!(chains[0], vec![dep3.clone(), dep2.clone()]);
+        assert_eq!(chains[1].len(), 3);
+        assert_eq!(chains[1][0], main);
+        assert!(chains[1][1] == dep1 || chains[1][1] == dep3);
+        assert_eq!(chains[1][2], dep2);
+
+        // a node is a chain of its own if it is a root
+        assert_eq!(
+            graph.chains_to_node(&main, |n| n.path == main.path),
+            vec![vec![main.clone()]]
+        );
+        assert!(graph.chains_to_node(&dep2, |_| false).is_empty());
+    }
+
     fn assert_before(vec: &Vec<Node>, first: &Node, second: &Node) {
         let first_pos = vec
             .iter()
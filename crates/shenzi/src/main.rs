@@ -1,23 +1,30 @@
 use std::process::exit;
 
-
-
-
+mod archive;
+mod ask;
 mod cli;
+mod deb;
+mod diff;
 mod digest;
+mod external;
 mod factory;
 mod gather;
 mod graph;
 mod manifest;
+mod merge;
 mod node;
+mod oci;
 mod parse;
+mod path_map;
 mod paths;
 mod pkg;
+mod redact;
+mod report;
+mod self_extracting;
 mod site_pkgs;
+mod verify;
 mod warnings;
-mod external;
 mod workspace;
-mod ask;
 
 fn main() {
     env_logger::init();
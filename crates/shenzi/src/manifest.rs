@@ -1,17 +1,26 @@
 use std::{collections::HashMap, path::PathBuf};
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, anyhow, bail};
+use log::info;
 /// the module defining types for deserializing shenzi.json (or called shenzi manifest)
 /// an example json is in this test module, code is duplicated between `python/shenzi` and our crate
 /// both should always be synced
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
 
 use crate::paths::normalize_path;
 
+/// version of the manifest written by `shenzi.discover`, `MANIFEST_VERSION` in `python/shenzi` has to match
+/// bump it when the format changes, and add a migration from the previous version to `migrate`
+pub const MANIFEST_VERSION: u64 = 2;
+
 pub type Env = HashMap<String, String>;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct ShenziManifest {
+    /// version of the manifest format, manifests without it are version 1
+    pub version: u64,
     pub loads: Vec<Load>,
     pub libs: Vec<Lib>,
     pub bins: Vec<Bin>,
@@ -21,9 +30,13 @@ pub struct ShenziManifest {
 }
 
 impl ShenziManifest {
+    /// parse a manifest of any version up to `MANIFEST_VERSION`, older manifests are migrated first
     pub fn from_str(manifest_contents: &str) -> Result<Self> {
-        let mut manifest: ShenziManifest = serde_json::from_str(manifest_contents)
+        let value: Value = serde_json::from_str(manifest_contents)
             .context("Failed to parse shenzi manifest as JSON")?;
+        let value = migrate(value)?;
+        let mut manifest: ShenziManifest = serde_path_to_error::deserialize(value)
+            .map_err(|e| anyhow!("invalid shenzi manifest at `{}`: {}", e.path(), e.inner()))?;
         manifest.python.sys.path = manifest
             .python
             .sys
@@ -35,13 +48,79 @@ impl ShenziManifest {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// JSON Schema of the current manifest version, published at `docs/manifest.schema.json`
+pub fn schema() -> Value {
+    serde_json::to_value(schemars::schema_for!(ShenziManifest))
+        .expect("fatal: failed in serializing manifest schema")
+}
+
+/// upgrade a manifest to `MANIFEST_VERSION`, one version at a time
+fn migrate(mut manifest: Value) -> Result<Value> {
+    let Some(object) = manifest.as_object() else {
+        bail!("invalid shenzi manifest: expected a JSON object");
+    };
+    let mut version = match object.get("version") {
+        None => 1,
+        Some(v) => v.as_u64().filter(|v| *v >= 1).ok_or_else(|| {
+            anyhow!(
+                "invalid shenzi manifest at `version`: expected a positive integer, found {}",
+                v
+            )
+        })?,
+    };
+    if version > MANIFEST_VERSION {
+        bail!(
+            "shenzi manifest has version {}, this shenzi only understands versions up to {}, upgrade shenzi",
+            version,
+            MANIFEST_VERSION
+        );
+    }
+    let from = version;
+    while version < MANIFEST_VERSION {
+        manifest = match version {
+            1 => migrate_v1_to_v2(manifest),
+            _ => unreachable!("no migration from manifest version {}", version),
+        };
+        version += 1;
+    }
+    if from != version {
+        info!(
+            "migrated shenzi manifest from version {} to {}",
+            from, version
+        );
+    }
+    Ok(manifest)
+}
+
+/// version 1 is every manifest written before manifests had a version
+/// older discovers did not write `bins`, `libs`, `skip`, `python.allowed_packages` and the symlinks of loads, they get their empty defaults
+fn migrate_v1_to_v2(mut manifest: Value) -> Value {
+    if let Some(object) = manifest.as_object_mut() {
+        object.entry("bins").or_insert_with(|| json!([]));
+        object.entry("libs").or_insert_with(|| json!([]));
+        object
+            .entry("skip")
+            .or_insert_with(|| json!({"prefixes": [], "libs": []}));
+        if let Some(python) = object.get_mut("python").and_then(|p| p.as_object_mut()) {
+            python.entry("allowed_packages").or_insert(Value::Null);
+        }
+        if let Some(loads) = object.get_mut("loads").and_then(|l| l.as_array_mut()) {
+            for load in loads.iter_mut().filter_map(|l| l.as_object_mut()) {
+                load.entry("symlinks").or_insert_with(|| json!([]));
+            }
+        }
+        object.insert("version".to_string(), json!(2));
+    }
+    manifest
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 pub struct Skip {
     pub prefixes: Vec<PathBuf>,
     pub libs: Vec<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum LoadKind {
     Extension,
@@ -50,7 +129,7 @@ pub enum LoadKind {
 
 /// these are the ones which are dlopen-ed
 /// they would be kept in ld-library-path
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct Load {
     pub kind: LoadKind,
     pub path: PathBuf,
@@ -58,33 +137,31 @@ pub struct Load {
 }
 
 /// only dependent libraries, only kept in reals and their symlink farms are created, but not kept in path
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct Lib {
     pub path: PathBuf,
 }
 
-
 /// binaries that are needed to be distributed
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct Bin {
     pub path: String,
 }
 
-
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct Python {
     pub sys: Sys,
-    // path to the main script
+    /// path to the main script
     pub main: PathBuf,
-    // packages in site-packages which are allowed to be added to the packaged application
-    // if None, everything is moved
+    /// packages in site-packages which are allowed to be added to the packaged application
+    /// if None, everything is moved
     pub allowed_packages: Option<Vec<String>>,
 
-    // the current directory of the process
+    /// the current directory of the process
     pub cwd: PathBuf,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct Sys {
     pub prefix: PathBuf,
     pub exec_prefix: PathBuf,
@@ -94,7 +171,7 @@ pub struct Sys {
     pub executable: PathBuf,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
 pub struct Version {
     pub major: u32,
     pub minor: u32,
@@ -107,8 +184,36 @@ impl Version {
     }
 }
 
+/// a current manifest of an environment in `/env` running `/app/main.py`, tests override the fields they need
+#[cfg(test)]
+pub fn test_manifest() -> Value {
+    json!({
+        "version": MANIFEST_VERSION,
+        "loads": [],
+        "libs": [],
+        "bins": [],
+        "skip": {"prefixes": [], "libs": []},
+        "python": {
+            "sys": {
+                "prefix": "/env",
+                "exec_prefix": "/env",
+                "platlibdir": "lib",
+                "version": {"major": 3, "minor": 12, "abi_thread": ""},
+                "path": [],
+                "executable": "/env/bin/python"
+            },
+            "main": "/app/main.py",
+            "allowed_packages": null,
+            "cwd": "/app"
+        },
+        "env": {}
+    })
+}
+
 #[cfg(test)]
 mod test {
+    use super::*;
+
     #[test]
     fn test_deserialize() {
         let json_str = r#"
@@ -145,8 +250,8 @@ mod test {
             "executable": "/Users/hariomnarang/miniconda3/bin/python"
         },
         "main": "<path>/to/main.py",
-        "allowed_packages": None,
-        "cwd": "/path/to/cwd",
+        "allowed_packages": null,
+        "cwd": "/path/to/cwd"
     },
     "env": {
         "PATH": "..."
@@ -154,9 +259,11 @@ mod test {
 }
 "#;
 
-        let manifest: super::ShenziManifest =
-            serde_json::from_str(json_str).expect("Failed to deserialize manifest");
+        let manifest = ShenziManifest::from_str(json_str).expect("Failed to deserialize manifest");
 
+        // written without a version and without bins, migrated from version 1
+        assert_eq!(manifest.version, MANIFEST_VERSION);
+        assert!(manifest.bins.is_empty());
         assert_eq!(manifest.loads.len(), 1);
         assert_eq!(
             manifest.loads[0].path.to_str().unwrap(),
@@ -185,4 +292,60 @@ mod test {
             "/Users/hariomnarang/miniconda3/bin/python"
         );
     }
+
+    fn minimal_v1() -> Value {
+        json!({
+            "loads": [{"kind": "extension", "path": "/env/lib/foo.so"}],
+            "python": {
+                "sys": {
+                    "prefix": "/env",
+                    "exec_prefix": "/env",
+                    "platlibdir": "lib",
+                    "version": {"major": 3, "minor": 12, "abi_thread": ""},
+                    "path": [],
+                    "executable": "/env/bin/python"
+                },
+                "main": "/app/main.py",
+                "cwd": "/app"
+            },
+            "env": {}
+        })
+    }
+
+    #[test]
+    fn test_migrate_v1() {
+        let manifest = ShenziManifest::from_str(&minimal_v1().to_string()).unwrap();
+        assert_eq!(manifest.version, MANIFEST_VERSION);
+        assert!(manifest.loads[0].symlinks.is_empty());
+        assert!(manifest.skip.prefixes.is_empty());
+        assert!(manifest.python.allowed_packages.is_none());
+    }
+
+    #[test]
+    fn test_errors_name_the_field() {
+        let mut value = test_manifest();
+        value["python"]["sys"]["version"]["major"] = json!("three");
+        let err = ShenziManifest::from_str(&value.to_string()).unwrap_err();
+        assert!(
+            err.to_string().contains("`python.sys.version.major`"),
+            "{}",
+            err
+        );
+
+        let mut value = test_manifest();
+        value["version"] = json!(MANIFEST_VERSION + 1);
+        let err = ShenziManifest::from_str(&value.to_string()).unwrap_err();
+        assert!(err.to_string().contains("upgrade shenzi"), "{}", err);
+    }
+
+    #[test]
+    fn test_published_schema_is_current() {
+        let published: Value =
+            serde_json::from_str(include_str!("../../../docs/manifest.schema.json")).unwrap();
+        assert_eq!(
+            published,
+            schema(),
+            "docs/manifest.schema.json is outdated, regenerate it with `shenzi manifest schema`"
+        );
+    }
 }
@@ -0,0 +1,220 @@
+// merging manifests of several discovery runs (pytest, manual sessions, integration runs) of the same environment
+// every run catches different `dlopen` loads, the merged manifest has all of them
+// `loads`, `libs`, `bins` and `skip` are unions in the order they were first seen
+// `python.main`, `python.cwd`, `python.sys.path` and `env` come from the first manifest, the runs have to use the same interpreter
+// `sys.path` is the import order, a union would let entries of one run (pytest's rootdir) shadow packages of another
+
+use std::{collections::HashSet, hash::Hash, path::PathBuf};
+
+use anyhow::{Result, bail};
+use log::warn;
+use serde::Serialize;
+
+use crate::manifest::{LoadKind, ShenziManifest, Sys};
+
+/// merge `manifests` (named for errors) into the first one
+pub fn merge_manifests(manifests: Vec<(String, ShenziManifest)>) -> Result<ShenziManifest> {
+    let mut manifests = manifests.into_iter();
+    let Some((first_name, mut merged)) = manifests.next() else {
+        bail!("nothing to merge, pass at least one manifest");
+    };
+    for (name, manifest) in manifests {
+        if let Some(field) = interpreter_mismatch(&merged.python.sys, &manifest.python.sys) {
+            bail!(
+                "manifests {} and {} come from different python interpreters (`python.sys.{}` differs), only runs of the same environment can be merged",
+                first_name,
+                name,
+                field
+            );
+        }
+        for load in manifest.loads {
+            match merged.loads.iter_mut().find(|l| l.path == load.path) {
+                Some(existing) => union_into(&mut existing.symlinks, load.symlinks, |s| s.clone()),
+                None => merged.loads.push(load),
+            }
+        }
+        union_into(&mut merged.libs, manifest.libs, |l| l.path.clone());
+        union_into(&mut merged.bins, manifest.bins, |b| b.path.clone());
+        union_into(&mut merged.skip.prefixes, manifest.skip.prefixes, |p| {
+            p.clone()
+        });
+        union_into(&mut merged.skip.libs, manifest.skip.libs, |l| l.clone());
+        if merged.python.sys.path != manifest.python.sys.path {
+            warn!(
+                "`python.sys.path` of {} differs from {}, the merged manifest keeps the one of {}",
+                name, first_name, first_name
+            );
+        }
+        // no list means every package is allowed
+        merged.python.allowed_packages = match (
+            merged.python.allowed_packages,
+            manifest.python.allowed_packages,
+        ) {
+            (Some(mut allowed), Some(other)) => {
+                union_into(&mut allowed, other, |p| p.clone());
+                Some(allowed)
+            }
+            _ => None,
+        };
+    }
+    Ok(merged)
+}
+
+/// the loads every manifest adds to the ones before it
+#[derive(Debug, Serialize)]
+pub struct AddedLoads {
+    pub manifest: String,
+    pub total: usize,
+    pub added: Vec<AddedLoad>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AddedLoad {
+    pub kind: LoadKind,
+    pub path: PathBuf,
+}
+
+pub fn added_loads(manifests: Vec<(String, ShenziManifest)>) -> Vec<AddedLoads> {
+    let mut seen = HashSet::new();
+    manifests
+        .into_iter()
+        .map(|(name, manifest)| AddedLoads {
+            manifest: name,
+            total: manifest.loads.len(),
+            added: manifest
+                .loads
+                .into_iter()
+                .filter(|load| seen.insert(load.path.clone()))
+                .map(|load| AddedLoad {
+                    kind: load.kind,
+                    path: load.path,
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+/// the first field which tells two interpreters apart, `sys.path` can differ between runs (pytest adds its rootdir)
+fn interpreter_mismatch(a: &Sys, b: &Sys) -> Option<&'static str> {
+    if a.executable != b.executable {
+        Some("executable")
+    } else if a.prefix != b.prefix {
+        Some("prefix")
+    } else if a.exec_prefix != b.exec_prefix {
+        Some("exec_prefix")
+    } else if a.platlibdir != b.platlibdir {
+        Some("platlibdir")
+    } else if a.version != b.version {
+        Some("version")
+    } else {
+        None
+    }
+}
+
+/// append the items of `other` which are not in `items` yet, by `key`
+fn union_into<T, K: Hash + Eq>(items: &mut Vec<T>, other: Vec<T>, key: impl Fn(&T) -> K) {
+    let mut seen: HashSet<K> = items.iter().map(&key).collect();
+    for item in other {
+        if seen.insert(key(&item)) {
+            items.push(item);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::manifest::test_manifest;
+    use serde_json::json;
+
+    fn manifest(loads: &[&str], executable: &str) -> ShenziManifest {
+        let loads: Vec<_> = loads
+            .iter()
+            .map(|p| json!({"kind": "dlopen", "path": p, "symlinks": [p.rsplit('/').next().unwrap()]}))
+            .collect();
+        let mut value = test_manifest();
+        value["loads"] = json!(loads);
+        value["python"]["sys"]["executable"] = json!(executable);
+        ShenziManifest::from_str(&value.to_string()).unwrap()
+    }
+
+    #[test]
+    fn test_merge_keeps_first_seen_order() {
+        let inputs = vec![
+            (
+                "pytest.json".to_string(),
+                manifest(&["/env/lib/libb.so", "/env/lib/liba.so"], "/env/bin/python"),
+            ),
+            (
+                "gui.json".to_string(),
+                manifest(
+                    &["/env/lib/liba.so", "/env/lib/libgl.so"],
+                    "/env/bin/python",
+                ),
+            ),
+        ];
+        let merged = merge_manifests(inputs).unwrap();
+        let paths: Vec<&str> = merged
+            .loads
+            .iter()
+            .map(|l| l.path.to_str().unwrap())
+            .collect();
+        assert_eq!(
+            paths,
+            vec!["/env/lib/libb.so", "/env/lib/liba.so", "/env/lib/libgl.so"]
+        );
+        assert_eq!(merged.loads[1].symlinks, vec!["liba.so"]);
+
+        let inputs = vec![
+            ("a.json".to_string(), manifest(&[], "/env/bin/python")),
+            ("b.json".to_string(), manifest(&[], "/other/bin/python")),
+        ];
+        let err = merge_manifests(inputs).unwrap_err();
+        assert!(
+            err.to_string().contains("`python.sys.executable`"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_merge_keeps_sys_path_of_first() {
+        let mut gui = manifest(&[], "/env/bin/python");
+        gui.python.sys.path = vec![PathBuf::from("/env/lib/python3.12")];
+        let mut pytest = manifest(&[], "/env/bin/python");
+        pytest.python.sys.path = vec![
+            PathBuf::from("/app/tests"),
+            PathBuf::from("/env/lib/python3.12"),
+        ];
+        let merged = merge_manifests(vec![
+            ("gui.json".to_string(), gui),
+            ("pytest.json".to_string(), pytest),
+        ])
+        .unwrap();
+        assert_eq!(
+            merged.python.sys.path,
+            vec![PathBuf::from("/env/lib/python3.12")]
+        );
+    }
+
+    #[test]
+    fn test_added_loads() {
+        let inputs = vec![
+            (
+                "pytest.json".to_string(),
+                manifest(&["/env/lib/liba.so"], "/env/bin/python"),
+            ),
+            (
+                "gui.json".to_string(),
+                manifest(
+                    &["/env/lib/liba.so", "/env/lib/libgl.so"],
+                    "/env/bin/python",
+                ),
+            ),
+        ];
+        let added = added_loads(inputs);
+        assert_eq!(added[0].added.len(), 1);
+        assert_eq!(added[1].total, 2);
+        assert_eq!(added[1].added[0].path, PathBuf::from("/env/lib/libgl.so"));
+    }
+}
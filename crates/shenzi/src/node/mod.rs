@@ -32,7 +32,7 @@ pub struct PrefixBinary {
 
 #[derive(Debug, Clone)]
 pub enum Pkg {
-    SitePackagesPlain{
+    SitePackagesPlain {
         // original site-packages path
         _site_packages: PathBuf,
         // to prevent collisions, we create an alias which is the name of this site-packages destination in dist
@@ -62,11 +62,54 @@ pub enum Pkg {
     PrefixBinary(PrefixBinary),
 
     Executable,
-    Binary {sha: String},
-    BinaryInLDPath { symlinks: Vec<String>, sha: String },
-    BinaryInPath {sha: String},
+    Binary {
+        sha: String,
+    },
+    BinaryInLDPath {
+        symlinks: Vec<String>,
+        sha: String,
+    },
+    BinaryInPath {
+        sha: String,
+    },
 }
 
+impl Pkg {
+    /// name of the variant, used when describing a node outside the codebase (build records, reports)
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Pkg::SitePackagesPlain { .. } => "SitePackagesPlain",
+            Pkg::SitePackagesBinary { .. } => "SitePackagesBinary",
+            Pkg::PlainPyBinaryFile => "PlainPyBinaryFile",
+            Pkg::MainPyScript => "MainPyScript",
+            Pkg::ExecPrefixPlain(_) => "ExecPrefixPlain",
+            Pkg::ExecPrefixBinary(_) => "ExecPrefixBinary",
+            Pkg::PrefixPlain(_) => "PrefixPlain",
+            Pkg::PrefixBinary(_) => "PrefixBinary",
+            Pkg::Executable => "Executable",
+            Pkg::Binary { .. } => "Binary",
+            Pkg::BinaryInLDPath { .. } => "BinaryInLDPath",
+            Pkg::BinaryInPath { .. } => "BinaryInPath",
+        }
+    }
+
+    /// the digest of the file, only binaries carry one
+    pub fn sha(&self) -> Option<&str> {
+        match self {
+            Pkg::SitePackagesBinary { sha, .. }
+            | Pkg::Binary { sha }
+            | Pkg::BinaryInLDPath { sha, .. }
+            | Pkg::BinaryInPath { sha } => Some(sha),
+            Pkg::ExecPrefixBinary(pkg) | Pkg::PrefixBinary(pkg) => Some(&pkg.sha),
+            Pkg::SitePackagesPlain { .. }
+            | Pkg::PlainPyBinaryFile
+            | Pkg::MainPyScript
+            | Pkg::ExecPrefixPlain(_)
+            | Pkg::PrefixPlain(_)
+            | Pkg::Executable => None,
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Node {
@@ -101,11 +144,7 @@ impl Display for Node {
 
 impl Node {
     pub fn new(path: PathBuf, pkg: Pkg, deps: Deps) -> Result<Node> {
-        Ok(Node {
-            path,
-            deps,
-            pkg,
-        })
+        Ok(Node { path, deps, pkg })
     }
 
     #[cfg(test)]
@@ -116,7 +155,7 @@ impl Node {
         Ok(Node {
             path,
             deps: Deps::mock(deps),
-            pkg: Pkg::Binary {sha},
+            pkg: Pkg::Binary { sha },
         })
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,397 @@
+// OCI image layout of a dist (https://github.com/opencontainers/image-spec/blob/main/image-layout.md)
+// the image can be loaded with `skopeo copy oci:<dir> ...` or `podman load`, no registry is involved
+// the dist is split into layers which change at different rates, so that a rebuild only changes the layers it has to:
+// the python interpreter and stdlib, shared libraries (reals and symlink farms), site-packages, and the rest (app code, bootstrap.sh)
+// the dist needs bash and the system libraries (libc, ...), the layers are put on top of a base image which provides them
+// the image is written in a staging directory and moved into place, only a previous image written by shenzi (with a SHENZI_MARKER) is replaced
+
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use flate2::{Compression, GzBuilder};
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use tar::Builder;
+
+use crate::{
+    archive::{append_directory, append_tree},
+    pkg::staging::StagedDist,
+};
+
+const MANIFEST_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+const CONFIG_MEDIA_TYPE: &str = "application/vnd.oci.image.config.v1+json";
+const LAYER_MEDIA_TYPE: &str = "application/vnd.oci.image.layer.v1.tar+gzip";
+
+// the dist is at `/app` inside the image
+const APP_DIR: &str = "app";
+
+/// top level entries of dist in every layer, in the order of the layers
+/// entries which are not listed here go into the last layer
+const LAYERS: [(&str, &[&str]); 3] = [
+    ("python", &["python"]),
+    ("shared libraries", &["reals", "symlinks", "lib", "bin"]),
+    ("site-packages", &["site_packages"]),
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Descriptor {
+    #[serde(rename = "mediaType")]
+    pub media_type: String,
+    pub digest: String,
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<Value>,
+}
+
+/// `dist.oci` next to `dist`
+pub fn oci_path(dist: &Path) -> PathBuf {
+    let name = dist
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| "dist".to_string());
+    dist.with_file_name(format!("{}.oci", name))
+}
+
+/// write `dist` as an OCI image layout at `out`, `base` is an OCI image layout whose layers go below the layers of dist
+pub fn write_oci(dist: &Path, out: &Path, tag: &str, base: &Path) -> Result<()> {
+    info!(
+        "writing OCI image of {} to {}",
+        dist.display(),
+        out.display()
+    );
+    // a previous image is replaced, but only if shenzi wrote it
+    let staged = StagedDist::new(out, true)?;
+    let root = staged.path();
+    let blobs = root.join("blobs").join("sha256");
+    fs::create_dir_all(&blobs)
+        .with_context(|| anyhow!("failed in creating {}", blobs.display()))?;
+
+    let (mut layers, mut diff_ids, base_config) = read_base(base, &blobs)
+        .with_context(|| anyhow!("failed in reading base image {}", base.display()))?;
+
+    for (name, tops) in dist_layers(dist)? {
+        if tops.is_empty() {
+            continue;
+        }
+        info!("oci: writing layer {} ({} entries)", name, tops.len());
+        let (layer, diff_id) = write_layer(dist, &tops, &blobs)
+            .with_context(|| anyhow!("failed in writing layer {}", name))?;
+        layers.push(layer);
+        diff_ids.push(diff_id);
+    }
+
+    let app = format!("/{}", APP_DIR);
+    let mut config = base_config
+        .get("config")
+        .cloned()
+        .unwrap_or_else(|| json!({}));
+    config["Entrypoint"] = json!(["/bin/bash", format!("{}/bootstrap.sh", app)]);
+    config["WorkingDir"] = json!(app);
+    if let Some(c) = config.as_object_mut() {
+        c.remove("Cmd");
+    }
+    let image_config = json!({
+        "architecture": base_config.get("architecture").cloned().unwrap_or_else(|| json!(oci_architecture())),
+        "os": base_config.get("os").cloned().unwrap_or_else(|| json!("linux")),
+        "config": config,
+        "rootfs": {"type": "layers", "diff_ids": diff_ids},
+    });
+    let config = write_blob(
+        &blobs,
+        CONFIG_MEDIA_TYPE,
+        &serde_json::to_vec(&image_config)?,
+    )?;
+
+    let manifest = json!({
+        "schemaVersion": 2,
+        "mediaType": MANIFEST_MEDIA_TYPE,
+        "config": config,
+        "layers": layers,
+    });
+    let mut manifest = write_blob(&blobs, MANIFEST_MEDIA_TYPE, &serde_json::to_vec(&manifest)?)?;
+    manifest.annotations = Some(json!({"org.opencontainers.image.ref.name": tag}));
+
+    fs::write(
+        root.join("oci-layout"),
+        serde_json::to_vec(&json!({"imageLayoutVersion": "1.0.0"}))?,
+    )?;
+    fs::write(
+        root.join("index.json"),
+        serde_json::to_vec_pretty(&json!({"schemaVersion": 2, "manifests": [manifest]}))?,
+    )?;
+    staged.commit()?;
+    Ok(())
+}
+
+/// the top level entries of dist in every layer, sorted by name
+fn dist_layers(dist: &Path) -> Result<Vec<(&'static str, Vec<String>)>> {
+    let mut rest = Vec::new();
+    let mut layers: Vec<(&'static str, Vec<String>)> =
+        LAYERS.iter().map(|(name, _)| (*name, Vec::new())).collect();
+    let mut entries: Vec<String> = fs::read_dir(dist)
+        .with_context(|| anyhow!("failed in reading {}", dist.display()))?
+        .map(|e| e.map(|e| e.file_name().to_string_lossy().to_string()))
+        .collect::<std::io::Result<_>>()?;
+    entries.sort();
+    for entry in entries {
+        match LAYERS
+            .iter()
+            .position(|(_, tops)| tops.contains(&entry.as_str()))
+        {
+            Some(i) => layers[i].1.push(entry),
+            None => rest.push(entry),
+        }
+    }
+    layers.push(("app", rest));
+    Ok(layers)
+}
+
+/// a gzipped tar of the `tops` entries of dist, returns its descriptor and its diff id (digest of the uncompressed tar)
+fn write_layer(dist: &Path, tops: &[String], blobs: &Path) -> Result<(Descriptor, String)> {
+    let mut tmp = tempfile::NamedTempFile::new_in(blobs)?;
+    let compressed = HashWriter::new(tmp.as_file_mut());
+    let uncompressed = HashWriter::new(GzBuilder::new().write(compressed, Compression::default()));
+    let mut builder = Builder::new(uncompressed);
+    append_directory(&mut builder, Path::new(APP_DIR))?;
+    for top in tops {
+        append_tree(&mut builder, &dist.join(top), &Path::new(APP_DIR).join(top))?;
+    }
+    let (encoder, diff_id, _) = builder.into_inner()?.finish();
+    let (_, digest, size) = encoder.finish()?.finish();
+    tmp.persist(blobs.join(&digest))?;
+    Ok((
+        Descriptor {
+            media_type: LAYER_MEDIA_TYPE.to_string(),
+            digest: format!("sha256:{}", digest),
+            size,
+            annotations: None,
+        },
+        format!("sha256:{}", diff_id),
+    ))
+}
+
+fn write_blob(blobs: &Path, media_type: &str, contents: &[u8]) -> Result<Descriptor> {
+    let digest = hex(&Sha256::digest(contents));
+    fs::write(blobs.join(&digest), contents)?;
+    Ok(Descriptor {
+        media_type: media_type.to_string(),
+        digest: format!("sha256:{}", digest),
+        size: contents.len() as u64,
+        annotations: None,
+    })
+}
+
+/// layers, diff ids and config of the image in an OCI image layout, the layer blobs are copied into `blobs`
+fn read_base(base: &Path, blobs: &Path) -> Result<(Vec<Descriptor>, Vec<String>, Value)> {
+    let read_json = |path: PathBuf| -> Result<Value> {
+        let contents =
+            fs::read(&path).with_context(|| anyhow!("failed in reading {}", path.display()))?;
+        Ok(serde_json::from_slice(&contents)?)
+    };
+    let blob_path = |digest: &str| -> Result<PathBuf> {
+        match digest.strip_prefix("sha256:") {
+            Some(hex) => Ok(base.join("blobs").join("sha256").join(hex)),
+            None => bail!("unsupported digest {}", digest),
+        }
+    };
+
+    let index = read_json(base.join("index.json"))?;
+    let manifests: Vec<Descriptor> = serde_json::from_value(index["manifests"].clone())?;
+    let manifest = match manifests.as_slice() {
+        [manifest] if manifest.media_type == MANIFEST_MEDIA_TYPE => manifest,
+        _ => bail!(
+            "the base image should have exactly one image manifest, copy a single platform with `skopeo copy docker://<image> oci:<dir>`"
+        ),
+    };
+    let manifest = read_json(blob_path(&manifest.digest)?)?;
+    let config_descriptor: Descriptor = serde_json::from_value(manifest["config"].clone())?;
+    let config = read_json(blob_path(&config_descriptor.digest)?)?;
+    let layers: Vec<Descriptor> = serde_json::from_value(manifest["layers"].clone())?;
+    let diff_ids: Vec<String> = serde_json::from_value(config["rootfs"]["diff_ids"].clone())?;
+    for layer in &layers {
+        let hex = layer.digest.trim_start_matches("sha256:");
+        fs::copy(blob_path(&layer.digest)?, blobs.join(hex))
+            .with_context(|| anyhow!("failed in copying base layer {}", layer.digest))?;
+    }
+    Ok((layers, diff_ids, config))
+}
+
+fn oci_architecture() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        "x86" => "386",
+        arch => arch,
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// sha256 and size of everything written through it
+struct HashWriter<W: Write> {
+    inner: W,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl<W: Write> HashWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            size: 0,
+        }
+    }
+
+    fn finish(self) -> (W, String, u64) {
+        (self.inner, hex(&self.hasher.finalize()), self.size)
+    }
+}
+
+impl<W: Write> Write for HashWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// an OCI image layout with one layer
+    fn write_base(base: &Path) {
+        let blobs = base.join("blobs").join("sha256");
+        fs::create_dir_all(&blobs).unwrap();
+        let layer = write_blob(&blobs, LAYER_MEDIA_TYPE, b"base layer").unwrap();
+        let config = json!({
+            "architecture": "amd64",
+            "os": "linux",
+            "config": {"Cmd": ["/bin/bash"], "Env": ["PATH=/usr/bin:/bin"]},
+            "rootfs": {"type": "layers", "diff_ids": ["sha256:base"]},
+        });
+        let config = write_blob(
+            &blobs,
+            CONFIG_MEDIA_TYPE,
+            &serde_json::to_vec(&config).unwrap(),
+        )
+        .unwrap();
+        let manifest = json!({"schemaVersion": 2, "config": config, "layers": [layer]});
+        let manifest = write_blob(
+            &blobs,
+            MANIFEST_MEDIA_TYPE,
+            &serde_json::to_vec(&manifest).unwrap(),
+        )
+        .unwrap();
+        fs::write(
+            base.join("index.json"),
+            serde_json::to_vec(&json!({"schemaVersion": 2, "manifests": [manifest]})).unwrap(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_write_oci() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dist = tmp.path().join("dist");
+        fs::create_dir_all(dist.join("python").join("bin")).unwrap();
+        fs::write(dist.join("python").join("bin").join("python"), "python").unwrap();
+        fs::create_dir_all(dist.join("site_packages").join("sp_0")).unwrap();
+        fs::write(
+            dist.join("site_packages").join("sp_0").join("six.py"),
+            "six",
+        )
+        .unwrap();
+        fs::write(dist.join("bootstrap.sh"), "echo hello").unwrap();
+
+        let base = tmp.path().join("base");
+        write_base(&base);
+
+        let out = oci_path(&dist);
+        write_oci(&dist, &out, "latest", &base).unwrap();
+
+        let index: Value =
+            serde_json::from_slice(&fs::read(out.join("index.json")).unwrap()).unwrap();
+        let manifest_digest = index["manifests"][0]["digest"].as_str().unwrap();
+        let blob = |digest: &str| {
+            let path = out
+                .join("blobs")
+                .join("sha256")
+                .join(digest.trim_start_matches("sha256:"));
+            let contents = fs::read(path).unwrap();
+            // every blob is stored under its digest
+            assert_eq!(
+                format!("sha256:{}", hex(&Sha256::digest(&contents))),
+                digest
+            );
+            contents
+        };
+        let manifest: Value = serde_json::from_slice(&blob(manifest_digest)).unwrap();
+        // base, python, site-packages and app, the shared libraries layer is empty
+        let layers = manifest["layers"].as_array().unwrap();
+        assert_eq!(layers.len(), 4);
+        assert_eq!(blob(layers[0]["digest"].as_str().unwrap()), b"base layer");
+        let config: Value =
+            serde_json::from_slice(&blob(manifest["config"]["digest"].as_str().unwrap())).unwrap();
+        assert_eq!(config["rootfs"]["diff_ids"].as_array().unwrap().len(), 4);
+        assert_eq!(config["rootfs"]["diff_ids"][0], "sha256:base");
+        assert_eq!(config["config"]["Entrypoint"][1], "/app/bootstrap.sh");
+        assert_eq!(config["config"]["Env"][0], "PATH=/usr/bin:/bin");
+        assert!(config["config"].get("Cmd").is_none());
+
+        let app_layer = blob(layers[3]["digest"].as_str().unwrap());
+        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(&app_layer[..]));
+        let paths: Vec<PathBuf> = archive
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().to_path_buf())
+            .collect();
+        assert_eq!(
+            paths,
+            [PathBuf::from("app"), PathBuf::from("app/bootstrap.sh")]
+        );
+    }
+
+    #[test]
+    fn test_write_oci_replaces_only_images_of_shenzi() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dist = tmp.path().join("dist");
+        fs::create_dir_all(&dist).unwrap();
+        fs::write(dist.join("bootstrap.sh"), "echo hello").unwrap();
+        let base = tmp.path().join("base");
+        write_base(&base);
+
+        // a directory the user had at that path is never removed
+        let out = oci_path(&dist);
+        fs::create_dir_all(&out).unwrap();
+        fs::write(out.join("important"), "").unwrap();
+        assert!(write_oci(&dist, &out, "latest", &base).is_err());
+        assert!(out.join("important").exists());
+
+        fs::remove_dir_all(&out).unwrap();
+        write_oci(&dist, &out, "1.0", &base).unwrap();
+        write_oci(&dist, &out, "2.0", &base).unwrap();
+        let index: Value =
+            serde_json::from_slice(&fs::read(out.join("index.json")).unwrap()).unwrap();
+        assert_eq!(
+            index["manifests"][0]["annotations"]["org.opencontainers.image.ref.name"],
+            "2.0"
+        );
+        // only dist, base and the image are left, no staging directories
+        assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 3);
+    }
+}
@@ -0,0 +1,456 @@
+// persistent cache of parsed ELF files, shared by all builds on the machine (`<cache_loc>/parse`)
+// `files/<key>.json` is the blake3 digest of a file, keyed by its device, inode, size and modification time, a file which did not change is not read again
+// `dynamic/<digest>.json` is the raw dynamic section of a file (DT_NEEDED, RPATH, RUNPATH, SONAME), keyed by the blake3 digest of its contents
+// `resolved/<digest>-<context>.json` is the resolved `Elf`, the context is everything resolution depends on besides the file (path, cwd, env, extra rpaths, known libs)
+// and the modification times of every searched directory, a library added to an earlier directory of the search order is a cache miss
+// entries are written to a temporary file and renamed into place, builds running at the same time never read a partial entry
+// a broken or missing entry is a cache miss, never an error
+
+use std::{
+    fs,
+    io::Write,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::{Context, Result, anyhow};
+use lazy_static::lazy_static;
+use log::{info, warn};
+use serde::{Serialize, de::DeserializeOwned};
+use walkdir::WalkDir;
+
+use crate::{
+    digest::make_digest,
+    parse::{Elf, ElfDynamic, KnownLibs, search::linux::default_search_dirs},
+    paths::cache_loc,
+};
+
+// bump when the format of entries changes, old entries are then ignored (and removed by `shenzi cache clean`)
+const FORMAT_VERSION: &str = "v1";
+const DEFAULT_MAX_BYTES: u64 = 256 * 1024 * 1024;
+// read by `ldconfig -p`, rewritten when a system library is installed
+const LD_SO_CACHE: &str = "/etc/ld.so.cache";
+
+lazy_static! {
+    static ref GLOBAL: Option<ParseCache> = match ParseCache::open() {
+        Ok(cache) => Some(cache),
+        Err(e) => {
+            warn!("parse cache is disabled: {:#}", e);
+            None
+        }
+    };
+}
+
+/// the cache used by parsing, `None` if the cache directory can't be created
+pub fn global() -> Option<&'static ParseCache> {
+    GLOBAL.as_ref()
+}
+
+pub fn parse_cache_root() -> Result<PathBuf> {
+    Ok(cache_loc()?.join("parse"))
+}
+
+/// maximum size of the cache, `SHENZI_PARSE_CACHE_MAX_MB` overrides the default of 256MB
+pub fn max_bytes() -> u64 {
+    std::env::var("SHENZI_PARSE_CACHE_MAX_MB")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .map(|mb| mb * 1024 * 1024)
+        .unwrap_or(DEFAULT_MAX_BYTES)
+}
+
+/// everything the resolution of an ELF file depends on, besides the file itself
+#[derive(Serialize)]
+pub struct ResolveContext<'a> {
+    path: &'a Path,
+    cwd: &'a Path,
+    ld_preload: Option<&'a String>,
+    ld_library_path: Option<&'a String>,
+    extra_rpaths: Vec<&'a PathBuf>,
+    known_libs: &'a str,
+    // in search order, `None` for directories which do not exist
+    search_dir_mtimes: Vec<(PathBuf, Option<SystemTime>)>,
+}
+
+impl<'a> ResolveContext<'a> {
+    pub fn new(
+        path: &'a Path,
+        cwd: &'a Path,
+        env: &'a std::collections::HashMap<String, String>,
+        extra_rpaths: &'a [PathBuf],
+        known_libs: &'a KnownLibs,
+        dynamic: &ElfDynamic,
+    ) -> Self {
+        let search_dir_mtimes = search_dirs(path, env, extra_rpaths, dynamic)
+            .into_iter()
+            .map(|dir| {
+                let mtime = fs::metadata(&dir).and_then(|m| m.modified()).ok();
+                (dir, mtime)
+            })
+            .collect();
+        let mut extra_rpaths: Vec<&PathBuf> = extra_rpaths.iter().collect();
+        extra_rpaths.sort();
+        Self {
+            path,
+            cwd,
+            ld_preload: env.get("LD_PRELOAD"),
+            ld_library_path: env.get("LD_LIBRARY_PATH"),
+            extra_rpaths,
+            known_libs: known_libs.fingerprint(),
+            search_dir_mtimes,
+        }
+    }
+
+    fn key(&self) -> String {
+        let contents =
+            serde_json::to_vec(self).expect("fatal: failed in serializing resolve context");
+        blake3::hash(&contents).to_hex()[..16].to_string()
+    }
+}
+
+/// every directory resolution of `dynamic` can look into, and the ldconfig cache
+/// adding or removing a file changes the modification time of its directory
+fn search_dirs(
+    path: &Path,
+    env: &std::collections::HashMap<String, String>,
+    extra_rpaths: &[PathBuf],
+    dynamic: &ElfDynamic,
+) -> Vec<PathBuf> {
+    // `$ORIGIN` which can't be expanded is an error of resolution itself, the entry is then never written
+    let (rpaths, runpaths) = dynamic.search_dirs(&path.to_path_buf()).unwrap_or_default();
+    // directories of the environment which do not exist yet are kept, creating one changes the key
+    let env_dirs = |name: &str| -> Vec<PathBuf> {
+        env.get(name)
+            .map(|v| {
+                v.split(':')
+                    .filter(|d| !d.is_empty())
+                    .map(PathBuf::from)
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let mut dirs = env_dirs("LD_PRELOAD");
+    dirs.extend(rpaths);
+    dirs.extend(extra_rpaths.iter().cloned());
+    dirs.extend(env_dirs("LD_LIBRARY_PATH"));
+    dirs.extend(runpaths);
+    dirs.push(PathBuf::from(LD_SO_CACHE));
+    dirs.extend(default_search_dirs());
+    dirs
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CacheStats {
+    pub root: PathBuf,
+    pub file_entries: usize,
+    pub dynamic_entries: usize,
+    pub resolved_entries: usize,
+    pub bytes: u64,
+    pub max_bytes: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct ParseCache {
+    root: PathBuf,
+}
+
+impl ParseCache {
+    pub fn open() -> Result<Self> {
+        Self::new(&parse_cache_root()?)
+    }
+
+    pub fn new(root: &Path) -> Result<Self> {
+        let cache = Self::existing(root);
+        for dir in [cache.files_dir(), cache.dynamic_dir(), cache.resolved_dir()] {
+            fs::create_dir_all(&dir)
+                .with_context(|| anyhow!("failed in creating cache directory {}", dir.display()))?;
+        }
+        Ok(cache)
+    }
+
+    /// the cache at `root` without creating it, for commands which only read or remove it
+    pub fn existing(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+        }
+    }
+
+    fn files_dir(&self) -> PathBuf {
+        self.root.join(FORMAT_VERSION).join("files")
+    }
+
+    fn dynamic_dir(&self) -> PathBuf {
+        self.root.join(FORMAT_VERSION).join("dynamic")
+    }
+
+    fn resolved_dir(&self) -> PathBuf {
+        self.root.join(FORMAT_VERSION).join("resolved")
+    }
+
+    /// the digest of the file at `path`, the file is only read if it changed since it was last hashed
+    pub fn digest(&self, path: &Path) -> Result<String> {
+        let meta = fs::metadata(path)
+            .with_context(|| anyhow!("failed in reading metadata of {}", path.display()))?;
+        let key = format!(
+            "{}-{}-{}-{}.{}",
+            meta.dev(),
+            meta.ino(),
+            meta.size(),
+            meta.mtime(),
+            meta.mtime_nsec()
+        );
+        let key = blake3::hash(key.as_bytes()).to_hex()[..16].to_string();
+        let entry = self.files_dir().join(format!("{}.json", key));
+        if let Some(digest) = read_entry::<String>(&entry) {
+            return Ok(digest);
+        }
+        let digest = make_digest(&path.to_path_buf())?;
+        write_entry(&entry, &digest);
+        Ok(digest)
+    }
+
+    pub fn get_dynamic(&self, digest: &str) -> Option<ElfDynamic> {
+        read_entry(&self.dynamic_dir().join(format!("{}.json", digest)))
+    }
+
+    pub fn put_dynamic(&self, digest: &str, dynamic: &ElfDynamic) {
+        write_entry(
+            &self.dynamic_dir().join(format!("{}.json", digest)),
+            dynamic,
+        );
+    }
+
+    /// the resolved file, only if every dependency it was resolved to still exists
+    pub fn get_resolved(&self, digest: &str, context: &ResolveContext) -> Option<Elf> {
+        let path = self
+            .resolved_dir()
+            .join(format!("{}-{}.json", digest, context.key()));
+        let elf: Elf = read_entry(&path)?;
+        if elf.dt_needed.values().all(|p| p.exists()) {
+            Some(elf)
+        } else {
+            None
+        }
+    }
+
+    pub fn put_resolved(&self, digest: &str, context: &ResolveContext, elf: &Elf) {
+        let path = self
+            .resolved_dir()
+            .join(format!("{}-{}.json", digest, context.key()));
+        write_entry(&path, elf);
+    }
+
+    pub fn stats(&self) -> Result<CacheStats> {
+        let mut stats = CacheStats {
+            root: self.root.clone(),
+            max_bytes: max_bytes(),
+            ..Default::default()
+        };
+        for (path, _, size) in self.entries()? {
+            if path.starts_with(self.files_dir()) {
+                stats.file_entries += 1;
+            } else if path.starts_with(self.dynamic_dir()) {
+                stats.dynamic_entries += 1;
+            } else if path.starts_with(self.resolved_dir()) {
+                stats.resolved_entries += 1;
+            }
+            stats.bytes += size;
+        }
+        Ok(stats)
+    }
+
+    /// remove the least recently used entries until the cache is smaller than `max_bytes`
+    /// returns the number of removed entries
+    pub fn trim(&self, max_bytes: u64) -> Result<usize> {
+        let mut entries = self.entries()?;
+        let mut total: u64 = entries.iter().map(|(_, _, size)| size).sum();
+        if total <= max_bytes {
+            return Ok(0);
+        }
+        entries.sort_by_key(|(_, modified, _)| *modified);
+        let mut removed = 0;
+        for (path, _, size) in entries {
+            if total <= max_bytes {
+                break;
+            }
+            // another build might have removed it already
+            match fs::remove_file(&path) {
+                Ok(()) => removed += 1,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    return Err(e)
+                        .context(anyhow!("failed in removing cache entry {}", path.display()));
+                }
+            }
+            total = total.saturating_sub(size);
+        }
+        info!(
+            "parse cache: removed {} entries, {} bytes left",
+            removed, total
+        );
+        Ok(removed)
+    }
+
+    /// remove every entry, including entries of older formats
+    pub fn clean(&self) -> Result<()> {
+        if self.root.exists() {
+            fs::remove_dir_all(&self.root)
+                .with_context(|| anyhow!("failed in removing cache at {}", self.root.display()))?;
+        }
+        Ok(())
+    }
+
+    fn entries(&self) -> Result<Vec<(PathBuf, SystemTime, u64)>> {
+        let mut entries = Vec::new();
+        if !self.root.exists() {
+            return Ok(entries);
+        }
+        for entry in WalkDir::new(&self.root) {
+            // entries can disappear while walking, removed by another build
+            let Ok(entry) = entry else {
+                continue;
+            };
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let Ok(meta) = entry.metadata() else {
+                continue;
+            };
+            let modified = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            entries.push((entry.into_path(), modified, meta.len()));
+        }
+        Ok(entries)
+    }
+}
+
+fn read_entry<T: DeserializeOwned>(path: &Path) -> Option<T> {
+    let contents = fs::read(path).ok()?;
+    match serde_json::from_slice(&contents) {
+        Ok(value) => {
+            // the modification time is the last use, `trim` removes the least recently used entries
+            if let Ok(f) = fs::File::options().append(true).open(path) {
+                let _ = f.set_modified(SystemTime::now());
+            }
+            Some(value)
+        }
+        Err(e) => {
+            warn!(
+                "ignoring broken parse cache entry {}: {}",
+                path.display(),
+                e
+            );
+            None
+        }
+    }
+}
+
+fn write_entry<T: Serialize>(path: &Path, value: &T) {
+    if let Err(e) = try_write_entry(path, value) {
+        warn!(
+            "failed in writing parse cache entry {}: {:#}",
+            path.display(),
+            e
+        );
+    }
+}
+
+fn try_write_entry<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    let dir = path
+        .parent()
+        .expect("fatal: cache entry does not have a parent");
+    let mut tmp = tempfile::NamedTempFile::new_in(dir)?;
+    tmp.write_all(&serde_json::to_vec(value)?)?;
+    tmp.persist(path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_cache_round_trip_and_trim() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cache = ParseCache::new(&tmp.path().join("parse")).unwrap();
+        let dynamic = ElfDynamic {
+            needed: vec!["libz.so.1".to_string()],
+            rpaths: vec!["$ORIGIN".to_string()],
+            runpaths: vec![],
+            soname: "libhello.so".to_string(),
+        };
+        assert!(cache.get_dynamic("abcd").is_none());
+        cache.put_dynamic("abcd", &dynamic);
+        assert_eq!(cache.get_dynamic("abcd").unwrap().needed, dynamic.needed);
+
+        let lib = tmp.path().join("deps").join("libz.so.1");
+        fs::create_dir_all(lib.parent().unwrap()).unwrap();
+        fs::write(&lib, "").unwrap();
+        let elf = Elf {
+            dt_needed: HashMap::from([("libz.so.1".to_string(), lib.clone())]),
+            dt_rpaths: HashMap::new(),
+            _dt_runpaths: HashMap::new(),
+            _path: tmp.path().join("libhello.so"),
+            all_dt_rpaths: vec![],
+            all_dt_runpaths: vec![],
+        };
+        let env = HashMap::new();
+        let known_libs = KnownLibs::default();
+        let object = tmp.path().join("libhello.so");
+        let context = ResolveContext::new(&object, tmp.path(), &env, &[], &known_libs, &dynamic);
+        cache.put_resolved("abcd", &context, &elf);
+        assert!(cache.get_resolved("abcd", &context).is_some());
+
+        // a different search context is a different entry
+        let other_env = HashMap::from([("LD_LIBRARY_PATH".to_string(), "/opt".to_string())]);
+        let other =
+            ResolveContext::new(&object, tmp.path(), &other_env, &[], &known_libs, &dynamic);
+        assert!(cache.get_resolved("abcd", &other).is_none());
+
+        // a library added to a searched directory (the `$ORIGIN` rpath) could shadow the resolved one
+        let origin = fs::File::open(tmp.path()).unwrap();
+        origin
+            .set_modified(SystemTime::now() + std::time::Duration::from_secs(60))
+            .unwrap();
+        let touched = ResolveContext::new(&object, tmp.path(), &env, &[], &known_libs, &dynamic);
+        assert!(cache.get_resolved("abcd", &touched).is_none());
+        cache.put_resolved("abcd", &touched, &elf);
+        assert!(cache.get_resolved("abcd", &touched).is_some());
+
+        // a dependency which was removed invalidates the entry
+        fs::remove_file(&lib).unwrap();
+        assert!(cache.get_resolved("abcd", &touched).is_none());
+
+        let stats = cache.stats().unwrap();
+        assert_eq!((stats.dynamic_entries, stats.resolved_entries), (1, 2));
+        assert_eq!(cache.trim(0).unwrap(), 3);
+        assert_eq!(cache.stats().unwrap().bytes, 0);
+    }
+
+    #[test]
+    fn test_digest_is_keyed_by_file_metadata() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cache = ParseCache::new(&tmp.path().join("parse")).unwrap();
+        let lib = tmp.path().join("libhello.so");
+        fs::write(&lib, "hello").unwrap();
+        let digest = cache.digest(&lib).unwrap();
+        assert_eq!(digest, make_digest(&lib).unwrap());
+        assert_eq!(cache.stats().unwrap().file_entries, 1);
+        assert_eq!(cache.digest(&lib).unwrap(), digest);
+
+        // a rewritten file has a new size and modification time
+        fs::write(&lib, "hello world").unwrap();
+        assert_eq!(cache.digest(&lib).unwrap(), make_digest(&lib).unwrap());
+        assert_eq!(cache.stats().unwrap().file_entries, 2);
+    }
+
+    #[test]
+    fn test_stats_of_missing_cache() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("parse");
+        let stats = ParseCache::existing(&root).stats().unwrap();
+        assert_eq!(stats.bytes, 0);
+        assert!(!root.exists());
+    }
+}
@@ -2,6 +2,8 @@ use std::collections::HashMap;
 use std::fmt;
 use std::path::PathBuf;
 
+use serde::{Deserialize, Serialize};
+
 #[derive(Debug, Clone)]
 pub struct Macho {
     // all load commands, along with the resolved path of the dependency
@@ -20,7 +22,7 @@ pub struct Macho {
     pub all_rpaths: Vec<String>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Elf {
     // parsed and found libraries that the elf file needs, equivalent to load_commands
     pub dt_needed: HashMap<String, PathBuf>,
@@ -41,6 +43,22 @@ pub struct Elf {
     pub all_dt_runpaths: Vec<String>,
 }
 
+/// the raw dynamic section of an elf, nothing in it is resolved
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ElfDynamic {
+    // DT_NEEDED entries
+    pub needed: Vec<String>,
+
+    // all rpath entries, unresolved
+    pub rpaths: Vec<String>,
+
+    // all runpath entries, unresolved
+    pub runpaths: Vec<String>,
+
+    // DT_SONAME, the file name if there is none
+    pub soname: String,
+}
+
 #[derive(Debug, Clone)]
 pub enum BinaryParseError {
     UnsupportedArchitecture,
@@ -76,6 +94,14 @@ impl Binary {
         }
     }
 
+    /// names of the needed libraries (DT_NEEDED or load commands) mapped to their resolved paths
+    pub fn needed(&self) -> &HashMap<String, PathBuf> {
+        match self {
+            Binary::Macho(macho) => &macho.load_cmds,
+            Binary::Elf(elf) => &elf.dt_needed,
+        }
+    }
+
     pub fn paths_to_add_for_next_search(&self) -> Vec<PathBuf> {
         // DT_RPATH in linux requires us to use the current RPATH the dependencies search space too
         match self {
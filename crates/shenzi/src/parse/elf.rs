@@ -4,25 +4,28 @@ use anyhow::{Result, anyhow, bail};
 use lief::elf::{Binary, DynamicEntries};
 
 use crate::{
-    parse::{error::ErrDidNotFindDependency, search::linux::parse_linux_rpath, Elf},
+    parse::{
+        Elf, ElfDynamic, KnownLibs, error::ErrDidNotFindDependency,
+        search::linux::parse_linux_rpath,
+    },
     paths::{is_sys_lib_linux, split_colon_separated_into_valid_search_paths},
 };
 
-pub fn parse(
-    binary: Binary,
+/// resolve the dependencies and rpaths of the dynamic section of `object_path`
+pub fn resolve(
+    dynamic: &ElfDynamic,
     object_path: &PathBuf,
     cwd: &PathBuf,
     env: &HashMap<String, String>,
     extra_rpaths: &Vec<PathBuf>,
-    known_libs: &HashMap<String, PathBuf>,
+    known_libs: &KnownLibs,
 ) -> Result<Elf> {
     let ld_preload = split_colon_separated_into_valid_search_paths(env.get("LD_PRELOAD"));
     let ld_library_path = split_colon_separated_into_valid_search_paths(env.get("LD_LIBRARY_PATH"));
-    let (rpaths, runpaths, libs_needed, _soname) = get_dynamic_entries(&binary, object_path)?;
     do_parse(
-        rpaths,
-        runpaths,
-        libs_needed,
+        dynamic.rpaths.clone(),
+        dynamic.runpaths.clone(),
+        dynamic.needed.clone(),
         object_path,
         cwd,
         &ld_preload,
@@ -32,6 +35,35 @@ pub fn parse(
     )
 }
 
+pub fn read_dynamic(binary: Binary, object_path: &PathBuf) -> Result<ElfDynamic> {
+    let (rpaths, runpaths, needed, soname) = get_dynamic_entries(&binary, object_path)?;
+    Ok(ElfDynamic {
+        needed,
+        rpaths,
+        runpaths,
+        soname,
+    })
+}
+
+impl ElfDynamic {
+    /// existing RPATH and RUNPATH directories, `$ORIGIN` is the parent of `loaded_from`
+    /// `loaded_from` is the path the loader opened the object with (can be a symlink to the object)
+    pub fn search_dirs(&self, loaded_from: &PathBuf) -> Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+        let rpaths = resolve_rpaths(&self.rpaths, loaded_from)?;
+        let runpaths = resolve_rpaths(&self.runpaths, loaded_from)?;
+        // keep the order of the entries, the loader searches them in order
+        let in_order = |raw: &Vec<String>, resolved: &HashMap<String, PathBuf>| {
+            raw.iter()
+                .filter_map(|r| resolved.get(r).cloned())
+                .collect::<Vec<PathBuf>>()
+        };
+        Ok((
+            in_order(&self.rpaths, &rpaths),
+            in_order(&self.runpaths, &runpaths),
+        ))
+    }
+}
+
 fn do_parse(
     rpaths: Vec<String>,
     runpaths: Vec<String>,
@@ -41,7 +73,7 @@ fn do_parse(
     ld_preload: &Vec<PathBuf>,
     ld_library_path: &Vec<PathBuf>,
     extra_rpaths: &Vec<PathBuf>,
-    known_libs: &HashMap<String, PathBuf>,
+    known_libs: &KnownLibs,
 ) -> Result<Elf> {
     let dt_rpaths = resolve_rpaths(&rpaths, object_path)?;
     let dt_runpaths = resolve_rpaths(&runpaths, object_path)?;
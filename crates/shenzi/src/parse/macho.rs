@@ -13,8 +13,12 @@ use lief::macho::{
 };
 use log::{debug, warn};
 
-use crate::{parse::error::ErrDidNotFindDependency, paths::{is_sys_lib_mac, normalize_path, split_colon_separated_into_valid_search_paths}};
+use crate::{
+    parse::error::ErrDidNotFindDependency,
+    paths::{is_sys_lib_mac, normalize_path, split_colon_separated_into_valid_search_paths},
+};
 
+use crate::parse::KnownLibs;
 use crate::parse::core::{BinaryParseError, Macho};
 
 #[derive(Debug)]
@@ -41,7 +45,7 @@ pub fn parse(
     executable_path: &PathBuf,
     cwd: &PathBuf,
     env: &HashMap<String, String>,
-    known_libs: &HashMap<String, PathBuf>,
+    known_libs: &KnownLibs,
 ) -> Result<Macho> {
     let dyld_library_path =
         &split_colon_separated_into_valid_search_paths(env.get("DYLD_LIBRARY_PATH"));
@@ -50,15 +54,20 @@ pub fn parse(
         cwd,
         dyld_library_path,
     };
-    _parse(macho, macho_path, &ctx, known_libs)
-        .with_context(|| anyhow!("failed in parsing macho={} context={:?}", macho_path.display(), ctx))
+    _parse(macho, macho_path, &ctx, known_libs).with_context(|| {
+        anyhow!(
+            "failed in parsing macho={} context={:?}",
+            macho_path.display(),
+            ctx
+        )
+    })
 }
 
 fn _parse(
     fat: FatBinary,
     macho_path: &PathBuf,
     ctx: &SharedLibCtx,
-    known_libs: &HashMap<String, PathBuf>,
+    known_libs: &KnownLibs,
 ) -> Result<Macho> {
     let host_cpu_type = get_host_cpu_type()?;
 
@@ -74,7 +83,8 @@ fn _parse(
     }
     warn!(
         "No binary found inside FAT Macho Binary for the host architecture, ignoring. path={} arch={:?}",
-        macho_path.display(), host_cpu_type
+        macho_path.display(),
+        host_cpu_type
     );
     return Err(Error::new(BinaryParseError::UnsupportedArchitecture));
 }
@@ -83,7 +93,7 @@ fn _parse_single_macho(
     macho_path: &PathBuf,
     macho: Binary,
     ctx: &SharedLibCtx,
-    known_libs: &HashMap<String, PathBuf>,
+    known_libs: &KnownLibs,
 ) -> Result<(Macho, CpuType)> {
     let loader_path = macho_path
         .parent()
@@ -167,7 +177,7 @@ fn get_load_commands(
     macho: &lief::macho::Binary,
     macho_path: &PathBuf,
     ctx: &PathResolverCtx,
-    known_libs: &HashMap<String, PathBuf>,
+    known_libs: &KnownLibs,
 ) -> Result<(Option<String>, HashMap<String, PathBuf>)> {
     let mut id_dylib = None;
     let mut load_cmds = HashMap::new();
@@ -180,7 +190,8 @@ fn get_load_commands(
                     if is_sys_lib_mac(&val) {
                         debug!(
                             "skipping system library {} in macho parsing, dependency of {}",
-                            val, macho_path.display()
+                            val,
+                            macho_path.display()
                         );
                         continue;
                     }
@@ -195,7 +206,10 @@ fn get_load_commands(
                         }
                         None => match known_libs.get(&val) {
                             None => {
-                                bail!(ErrDidNotFindDependency { lib: macho_path.clone(), name: val});
+                                bail!(ErrDidNotFindDependency {
+                                    lib: macho_path.clone(),
+                                    name: val
+                                });
                             }
                             Some(lib_path) => {
                                 load_cmds.insert(val, lib_path.clone());
@@ -239,7 +253,7 @@ fn resolve_rpath(
 fn resolve_load_cmd_path_with_dyld_fallback(
     load_cmd_path: &str,
     ctx: &PathResolverCtx,
-    known_libs: &HashMap<String, PathBuf>,
+    known_libs: &KnownLibs,
 ) -> Result<Option<PathBuf>> {
     let resolved = resolve_load_cmd_path(load_cmd_path, ctx)?;
     match resolved {
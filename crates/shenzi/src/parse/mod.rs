@@ -1,49 +1,123 @@
+pub mod cache;
 mod core;
 mod elf;
-mod macho;
 mod error;
+mod macho;
 mod search;
 
 use anyhow::Context;
 use anyhow::Error;
 use anyhow::Result;
 use anyhow::anyhow;
-use std::collections::HashSet;
 use log::warn;
-use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Deref;
 use std::path::PathBuf;
 
-use elf::parse as parse_linux;
+use cache::ResolveContext;
+
+use elf::read_dynamic as read_linux_dynamic;
+use elf::resolve as resolve_linux;
 use macho::parse as parse_macho;
 
-pub use core::{Binary, BinaryParseError, Elf, Macho};
-pub use error::{ErrDidNotFindDependency, ErrDidNotFindDependencies};
+pub use core::{Binary, BinaryParseError, Elf, ElfDynamic, Macho};
+pub use error::{ErrDidNotFindDependencies, ErrDidNotFindDependency};
+
+/// file name -> path of the shared libraries which are already in the graph, the last resort of resolution
+/// the fingerprint is part of the key of resolved entries in the parse cache, it is computed once when the map is built
+#[derive(Debug, Clone)]
+pub struct KnownLibs {
+    libs: HashMap<String, PathBuf>,
+    fingerprint: String,
+}
+
+impl KnownLibs {
+    pub fn new(libs: HashMap<String, PathBuf>) -> Self {
+        let sorted: BTreeMap<&String, &PathBuf> = libs.iter().collect();
+        let contents =
+            serde_json::to_vec(&sorted).expect("fatal: failed in serializing known libs");
+        let fingerprint = blake3::hash(&contents).to_hex()[..16].to_string();
+        Self { libs, fingerprint }
+    }
+
+    pub fn fingerprint(&self) -> &str {
+        &self.fingerprint
+    }
+}
+
+impl Default for KnownLibs {
+    fn default() -> Self {
+        Self::new(HashMap::new())
+    }
+}
+
+impl Deref for KnownLibs {
+    type Target = HashMap<String, PathBuf>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.libs
+    }
+}
 
 pub fn parse_and_search(
     path: &PathBuf,
     executable_path: &PathBuf,
     cwd: &PathBuf,
     env: &HashMap<String, String>,
-    known_libs: &HashMap<String, PathBuf>,
+    known_libs: &KnownLibs,
     extra_rpaths: &Vec<PathBuf>,
 ) -> Result<Binary> {
     // TODO: take a set instead of doing this, this is very inefficient way of doing this
     let extra_rpaths = &deduplicate_paths(extra_rpaths);
+    let os = std::env::consts::OS;
+    // only ELF files are cached, a file which is not in the cache might be anything
+    let cached = match (os, cache::global()) {
+        ("linux", Some(cache)) => cache.digest(path).ok().map(|digest| (cache, digest)),
+        _ => None,
+    };
+    // the key of a resolved entry depends on the search directories of the dynamic section
+    if let Some((cache, digest)) = &cached
+        && let Some(dynamic) = cache.get_dynamic(digest)
+    {
+        let context = ResolveContext::new(path, cwd, env, extra_rpaths, known_libs, &dynamic);
+        if let Some(elf) = cache.get_resolved(digest, &context) {
+            return Ok(Binary::Elf(elf));
+        }
+        let elf = resolve_linux(&dynamic, path, cwd, env, extra_rpaths, known_libs)?;
+        cache.put_resolved(digest, &context, &elf);
+        return Ok(Binary::Elf(elf));
+    }
+
     let mut file =
         std::fs::File::open(path).context(anyhow!("Can't open the file={}", path.display()))?;
-    let os = std::env::consts::OS;
     let binary = match lief::Binary::from(&mut file) {
         Some(lief::Binary::ELF(elf)) => {
             if os != "linux" {
-                warn!("found an ELF file in non-linux system, path={}", path.display());
+                warn!(
+                    "found an ELF file in non-linux system, path={}",
+                    path.display()
+                );
                 return Err(Error::new(BinaryParseError::UnsupportedArchitecture));
             }
-            let elf = parse_linux(elf, path, cwd, env, extra_rpaths, known_libs)?;
+            let dynamic = read_linux_dynamic(elf, path)?;
+            if let Some((cache, digest)) = &cached {
+                cache.put_dynamic(digest, &dynamic);
+            }
+            let elf = resolve_linux(&dynamic, path, cwd, env, extra_rpaths, known_libs)?;
+            if let Some((cache, digest)) = &cached {
+                let context =
+                    ResolveContext::new(path, cwd, env, extra_rpaths, known_libs, &dynamic);
+                cache.put_resolved(digest, &context, &elf);
+            }
             Binary::Elf(elf)
         }
         Some(lief::Binary::MachO(macho)) => {
             if os != "macos" {
-                warn!("found a MACHO file in non-macos system, path={}", path.display());
+                warn!(
+                    "found a MACHO file in non-macos system, path={}",
+                    path.display()
+                );
                 return Err(Error::new(BinaryParseError::UnsupportedArchitecture));
             }
             let macho = parse_macho(macho, path, executable_path, cwd, env, known_libs)?;
@@ -64,6 +138,16 @@ pub fn parse_and_search(
     Ok(binary)
 }
 
+/// only read the dynamic section of an ELF file, nothing is searched
+/// returns `None` if the file is not an ELF
+pub fn read_elf_dynamic(path: &PathBuf) -> Result<Option<ElfDynamic>> {
+    let mut file =
+        std::fs::File::open(path).context(anyhow!("Can't open the file={}", path.display()))?;
+    match lief::Binary::from(&mut file) {
+        Some(lief::Binary::ELF(elf)) => Ok(Some(read_linux_dynamic(elf, path)?)),
+        _ => Ok(None),
+    }
+}
 
 fn deduplicate_paths(paths: &Vec<PathBuf>) -> Vec<PathBuf> {
     let mut set = HashSet::new();
@@ -71,4 +155,4 @@ fn deduplicate_paths(paths: &Vec<PathBuf>) -> Vec<PathBuf> {
         set.insert(path.clone());
     }
     set.into_iter().collect()
-}
\ No newline at end of file
+}
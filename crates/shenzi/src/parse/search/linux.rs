@@ -1,7 +1,7 @@
 mod auxval;
 mod ldconfig;
-mod rpath;
 mod ldd;
+mod rpath;
 
 use std::path::PathBuf;
 
@@ -57,23 +57,25 @@ pub fn search(
         return Some(path);
     }
 
-    try_find_in_dirs!(
-        name,
-        &vec![
-            PathBuf::from("/lib64"),
-            PathBuf::from("/lib"),
-            PathBuf::from("/usr/lib64"),
-            PathBuf::from("/usr/lib")
-        ]
-    );
+    try_find_in_dirs!(name, &default_search_dirs());
 
     if let Ok(path) = ldd::find(name, object_path) {
-        return Some(path)
+        return Some(path);
     }
 
     None
 }
 
+/// the directories searched after ldconfig
+pub fn default_search_dirs() -> Vec<PathBuf> {
+    vec![
+        PathBuf::from("/lib64"),
+        PathBuf::from("/lib"),
+        PathBuf::from("/usr/lib64"),
+        PathBuf::from("/usr/lib"),
+    ]
+}
+
 fn search_name_as_path(name: &str, cwd: &PathBuf) -> Option<PathBuf> {
     if !name.contains("/") {
         None
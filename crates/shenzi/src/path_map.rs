@@ -0,0 +1,275 @@
+// rewriting the absolute paths of a manifest, for environments which moved after discovery
+// (the manifest is written in one container path, the build runs in another)
+// a mapping `OLD=NEW` rewrites every path under `OLD` to the same path under `NEW`, the longest matching `OLD` wins
+// paths are compared by components, `/opt/env=/env` does not touch `/opt/env2`
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use log::warn;
+
+use crate::manifest::ShenziManifest;
+
+/// `OLD=NEW`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathMapping {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+impl FromStr for PathMapping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (from, to) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected OLD=NEW, found {}", s))?;
+        let (from, to) = (PathBuf::from(from), PathBuf::from(to));
+        if !from.is_absolute() || !to.is_absolute() {
+            return Err(format!(
+                "both paths of a path map have to be absolute, found {}",
+                s
+            ));
+        }
+        Ok(Self { from, to })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PathMap {
+    // longest `from` first
+    mappings: Vec<PathMapping>,
+}
+
+impl PathMap {
+    /// `workspace` is the `[path_map]` table of the workspace, `cli` wins over it for the same `OLD`
+    pub fn new(workspace: &BTreeMap<PathBuf, PathBuf>, cli: &[PathMapping]) -> Self {
+        let mut by_from: BTreeMap<PathBuf, PathBuf> = workspace.clone();
+        for m in cli {
+            by_from.insert(m.from.clone(), m.to.clone());
+        }
+        let mut mappings: Vec<PathMapping> = by_from
+            .into_iter()
+            .map(|(from, to)| PathMapping { from, to })
+            .collect();
+        mappings.sort_by_key(|m| std::cmp::Reverse(m.from.components().count()));
+        Self { mappings }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mappings.is_empty()
+    }
+
+    /// the rewritten path, `None` if no mapping matches
+    pub fn map(&self, path: &Path) -> Option<PathBuf> {
+        self.mappings.iter().find_map(|m| {
+            path.strip_prefix(&m.from).ok().map(|rest| {
+                if rest.as_os_str().is_empty() {
+                    m.to.clone()
+                } else {
+                    m.to.join(rest)
+                }
+            })
+        })
+    }
+}
+
+/// a path of the manifest which no mapping matched and which does not exist on this machine
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnmappedPath {
+    // `loads[3].path`, `python.sys.prefix`, ...
+    pub field: String,
+    pub path: PathBuf,
+}
+
+struct Remapper<'a> {
+    map: &'a PathMap,
+    unmapped: Vec<UnmappedPath>,
+}
+
+impl Remapper<'_> {
+    /// `required`: the build reads the path, it is reported if it is unmapped and missing
+    fn path(&mut self, field: String, path: &mut PathBuf, required: bool) {
+        if !path.is_absolute() {
+            return;
+        }
+        match self.map.map(path) {
+            Some(mapped) => *path = mapped,
+            None if required && !path.exists() => self.unmapped.push(UnmappedPath {
+                field,
+                path: path.clone(),
+            }),
+            None => {}
+        }
+    }
+
+    fn string(&mut self, field: String, value: &mut String, required: bool) {
+        let mut path = PathBuf::from(value.as_str());
+        self.path(field, &mut path, required);
+        *value = path.to_string_lossy().to_string();
+    }
+
+    /// `/a/bin:/b/bin`, only if every entry is an absolute path
+    fn path_list(&self, value: &mut String) {
+        let entries: Vec<&str> = value.split(':').collect();
+        if !entries.iter().all(|e| e.starts_with('/')) {
+            return;
+        }
+        *value = entries
+            .into_iter()
+            .map(|e| {
+                self.map
+                    .map(Path::new(e))
+                    .unwrap_or_else(|| PathBuf::from(e))
+            })
+            .map(|p| p.to_string_lossy().to_string())
+            .collect::<Vec<String>>()
+            .join(":");
+    }
+}
+
+/// rewrite every absolute path of `manifest` with `map`
+/// returns the paths the build needs which were not rewritten and do not exist
+pub fn remap_manifest(manifest: &mut ShenziManifest, map: &PathMap) -> Vec<UnmappedPath> {
+    let mut r = Remapper {
+        map,
+        unmapped: Vec::new(),
+    };
+    for (i, load) in manifest.loads.iter_mut().enumerate() {
+        r.path(format!("loads[{}].path", i), &mut load.path, true);
+    }
+    for (i, lib) in manifest.libs.iter_mut().enumerate() {
+        r.path(format!("libs[{}].path", i), &mut lib.path, true);
+    }
+    for (i, bin) in manifest.bins.iter_mut().enumerate() {
+        r.string(format!("bins[{}].path", i), &mut bin.path, true);
+    }
+    for (i, prefix) in manifest.skip.prefixes.iter_mut().enumerate() {
+        r.path(format!("skip.prefixes[{}]", i), prefix, false);
+    }
+
+    let python = &mut manifest.python;
+    r.path("python.main".to_string(), &mut python.main, true);
+    r.path("python.cwd".to_string(), &mut python.cwd, true);
+    let sys = &mut python.sys;
+    r.path("python.sys.prefix".to_string(), &mut sys.prefix, true);
+    r.path(
+        "python.sys.exec_prefix".to_string(),
+        &mut sys.exec_prefix,
+        true,
+    );
+    r.path(
+        "python.sys.executable".to_string(),
+        &mut sys.executable,
+        true,
+    );
+    r.path(
+        "python.sys.platlibdir".to_string(),
+        &mut sys.platlibdir,
+        false,
+    );
+    // entries of sys.path which do not exist are normal (`python312.zip`)
+    for (i, p) in sys.path.iter_mut().enumerate() {
+        r.path(format!("python.sys.path[{}]", i), p, false);
+    }
+
+    // `PATH`, `LD_LIBRARY_PATH`, `VIRTUAL_ENV`, ..., directories in them often do not exist, they are never reported
+    for value in manifest.env.values_mut() {
+        r.path_list(value);
+    }
+    r.unmapped
+}
+
+/// log every unmapped path, with the mapping which would fix it
+pub fn report_unmapped(unmapped: &[UnmappedPath]) {
+    if unmapped.is_empty() {
+        return;
+    }
+    warn!(
+        "{} paths of the manifest do not exist on this machine and no path map matches them, add a mapping with --path-map OLD=NEW (or [path_map] in the workspace):",
+        unmapped.len()
+    );
+    for u in unmapped {
+        warn!("\t{}: {}", u.field, u.path.display());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::manifest::test_manifest;
+    use serde_json::json;
+
+    #[test]
+    fn test_map_longest_prefix_by_component() {
+        let workspace = BTreeMap::from([(PathBuf::from("/opt/env"), PathBuf::from("/ws"))]);
+        let cli: Vec<PathMapping> = ["/opt/env=/env", "/opt/env/lib=/libs"]
+            .iter()
+            .map(|m| m.parse().unwrap())
+            .collect();
+        let map = PathMap::new(&workspace, &cli);
+        assert_eq!(
+            map.map(Path::new("/opt/env/bin/python")),
+            Some(PathBuf::from("/env/bin/python"))
+        );
+        assert_eq!(
+            map.map(Path::new("/opt/env/lib/libz.so")),
+            Some(PathBuf::from("/libs/libz.so"))
+        );
+        assert_eq!(map.map(Path::new("/opt/env")), Some(PathBuf::from("/env")));
+        assert_eq!(map.map(Path::new("/opt/env2/bin")), None);
+        assert!("relative=/env".parse::<PathMapping>().is_err());
+        assert!("/no-separator".parse::<PathMapping>().is_err());
+    }
+
+    #[test]
+    fn test_remap_manifest() {
+        let mut value = test_manifest();
+        value["loads"] = json!([
+            {"kind": "dlopen", "path": "/env/lib/libgl.so", "symlinks": []},
+            {"kind": "dlopen", "path": "/elsewhere/libmissing.so", "symlinks": []}
+        ]);
+        value["bins"] = json!([{"path": "ls"}]);
+        value["skip"]["prefixes"] = json!(["/env/lib/python3.12/site-packages/pygraphviz"]);
+        value["python"]["sys"]["path"] = json!(["/env/lib/python312.zip"]);
+        value["env"] = json!({"PATH": "/env/bin:/usr/bin", "LANG": "C.UTF-8"});
+        let mut manifest = ShenziManifest::from_str(&value.to_string()).unwrap();
+        let cli: Vec<PathMapping> = vec![
+            "/env=/build/env".parse().unwrap(),
+            "/app=/build/app".parse().unwrap(),
+        ];
+        let unmapped = remap_manifest(&mut manifest, &PathMap::new(&BTreeMap::new(), &cli));
+
+        assert_eq!(
+            manifest.loads[0].path,
+            PathBuf::from("/build/env/lib/libgl.so")
+        );
+        assert_eq!(
+            manifest.python.sys.executable,
+            PathBuf::from("/build/env/bin/python")
+        );
+        assert_eq!(manifest.python.cwd, PathBuf::from("/build/app"));
+        assert_eq!(
+            manifest.skip.prefixes[0],
+            PathBuf::from("/build/env/lib/python3.12/site-packages/pygraphviz")
+        );
+        assert_eq!(
+            manifest.python.sys.path[0],
+            PathBuf::from("/build/env/lib/python312.zip")
+        );
+        assert_eq!(manifest.python.sys.platlibdir, PathBuf::from("lib"));
+        assert_eq!(manifest.bins[0].path, "ls");
+        assert_eq!(manifest.env["PATH"], "/build/env/bin:/usr/bin");
+        assert_eq!(manifest.env["LANG"], "C.UTF-8");
+        assert_eq!(
+            unmapped,
+            vec![UnmappedPath {
+                field: "loads[1].path".to_string(),
+                path: PathBuf::from("/elsewhere/libmissing.so"),
+            }]
+        );
+    }
+}
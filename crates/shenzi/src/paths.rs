@@ -52,6 +52,15 @@ pub fn normalize_path(path: &Path) -> PathBuf {
     ret
 }
 
+/// `path` relative to the current directory if it is not absolute, normalized
+pub fn absolute_path(path: &Path) -> Result<PathBuf> {
+    if path.is_absolute() {
+        return Ok(normalize_path(path));
+    }
+    let cwd = std::env::current_dir().context("failed in getting current directory")?;
+    Ok(normalize_path(&cwd.join(path)))
+}
+
 pub fn is_sys_lib_mac(path: &str) -> bool {
     path.starts_with("/usr/lib/")
         || path.starts_with("/System/Library/Frameworks/")
@@ -67,7 +76,6 @@ pub fn is_sys_lib_linux(path: &str) -> bool {
     return false;
 }
 
-
 pub fn to_string_path(path: &Path) -> Result<String> {
     path.to_str().map(|s| s.to_string()).with_context(|| {
         anyhow!(
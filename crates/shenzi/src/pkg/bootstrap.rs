@@ -9,7 +9,10 @@ use pathdiff::diff_paths;
 use crate::{
     gather::PythonPathComponent,
     manifest::Version,
-    pkg::paths::{lib_dynload_relative_path, site_pkgs_relative_path, stdlib_relative_path},
+    pkg::{
+        paths::{lib_dynload_relative_path, site_pkgs_relative_path, stdlib_relative_path},
+        zipimport::zip_relative_path,
+    },
 };
 
 const MAC_BOOTSTRAP_SCRIPT: &str = r#"
@@ -150,6 +153,22 @@ fn python_path_from_components(
     comps: &Vec<PythonPathComponent>,
     version: &Version,
 ) -> Result<String> {
+    let res = python_path_entries(comps, version)?;
+    let bash_array_contents = res
+        .iter()
+        .map(|s| format!("\"{}\"", s))
+        .collect::<Vec<String>>()
+        .join(" ");
+
+    let bash_array = format!("({})", bash_array_contents);
+    Ok(bash_array)
+}
+
+/// the PYTHONPATH entries set by the bootstrap script, relative to dist
+pub fn python_path_entries(
+    comps: &Vec<PythonPathComponent>,
+    version: &Version,
+) -> Result<Vec<String>> {
     let mut res = Vec::new();
     let stdlib_rel_path = path_buf_to_str(&stdlib_relative_path(version))?;
     let lib_dynload_rel_path = path_buf_to_str(&lib_dynload_relative_path(version))?;
@@ -175,16 +194,12 @@ fn python_path_from_components(
                 let rel_path = path_buf_to_str(&rel_path)?;
                 res.push(format!("{}/{}", site_pkgs_path, rel_path));
             }
+            PythonPathComponent::SitePkgZip { alias } => {
+                res.push(path_buf_to_str(&zip_relative_path(alias))?);
+            }
         }
     }
-    let bash_array_contents = res
-        .iter()
-        .map(|s| format!("\"{}\"", s))
-        .collect::<Vec<String>>()
-        .join(" ");
-
-    let bash_array = format!("({})", bash_array_contents);
-    Ok(bash_array)
+    Ok(res)
 }
 
 fn path_buf_to_str(b: &PathBuf) -> Result<String> {
@@ -196,3 +211,52 @@ fn path_buf_to_str(b: &PathBuf) -> Result<String> {
     })?;
     Ok(p.to_string())
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::pkg::zipimport::with_zips;
+
+    #[test]
+    fn test_python_path_entries_keep_zips_next_to_their_site_package() {
+        let version = Version {
+            major: 3,
+            minor: 12,
+            abi_thread: "".to_string(),
+        };
+        let comps = vec![
+            PythonPathComponent::RelativeToStdlib {
+                rel_path: PathBuf::from("site-packages"),
+            },
+            PythonPathComponent::TopLevel {
+                alias: "sp_0".to_string(),
+            },
+            PythonPathComponent::RelativeToSitePkg {
+                top_level_alias: "sp_0".to_string(),
+                rel_path: PathBuf::from("nested"),
+            },
+            PythonPathComponent::TopLevel {
+                alias: "sp_1".to_string(),
+            },
+            PythonPathComponent::TopLevel {
+                alias: "sp_2".to_string(),
+            },
+        ];
+        let comps = with_zips(&comps, &HashSet::from(["sp_0", "sp_2"]));
+        let entries = python_path_entries(&comps, &version).unwrap();
+        let zip_0 = entries
+            .iter()
+            .position(|e| e == "site_packages/sp_0.zip")
+            .unwrap();
+        let zip_2 = entries
+            .iter()
+            .position(|e| e == "site_packages/sp_2.zip")
+            .unwrap();
+        assert_eq!(entries[zip_0 - 1], "site_packages/sp_0");
+        assert_eq!(entries[zip_0 + 1], "site_packages/sp_0/nested");
+        assert_eq!(entries[zip_2 - 1], "site_packages/sp_2");
+        assert_eq!(entries.len(), 7);
+    }
+}
@@ -0,0 +1,226 @@
+// compiling the python files in dist to bytecode, using the packaged interpreter (`python/bin/python`) on the build machine
+// only the stdlib (`python/lib`) and `site_packages` are compiled, the main script and scripts in `bin/b` are run by path and stay as they are
+// pyc files are hash based, their validity does not depend on file timestamps (archives normalize them)
+
+use std::{
+    collections::HashMap,
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    process::Command,
+    time::SystemTime,
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BytecodeMode {
+    /// do not compile anything, python writes `__pycache__` at runtime if it can
+    #[default]
+    None,
+    /// write `__pycache__/*.pyc` next to every `.py` file
+    Compile,
+    /// write a legacy `.pyc` next to every `.py` file and remove the `.py` file
+    Sourceless,
+}
+
+/// compile the stdlib and site-packages in `dist` with the packaged interpreter at `python`
+pub fn compile_bytecode(dist: &Path, python: &Path, mode: BytecodeMode) -> Result<()> {
+    if mode == BytecodeMode::None {
+        return Ok(());
+    }
+    info!("Step: compile python files to bytecode, mode={:?}", mode);
+    check_interpreter(dist, python)?;
+
+    let dirs: Vec<PathBuf> = [dist.join("python").join("lib"), dist.join("site_packages")]
+        .into_iter()
+        .filter(|d| d.exists())
+        .collect();
+    // legacy `.pyc` files shipped in the environment, they can be stale, only sources with a `.pyc` written by compileall are removed
+    let mut shipped = HashMap::new();
+    if mode == BytecodeMode::Sourceless {
+        for dir in &dirs {
+            shipped.extend(legacy_pycs(dir)?);
+        }
+    }
+    let mut cmd = interpreter_command(dist, python);
+    cmd.args(["-m", "compileall", "-q", "-j", "0"]);
+    match mode {
+        // no source to check against
+        BytecodeMode::Sourceless => cmd.args(["-b", "--invalidation-mode", "unchecked-hash"]),
+        _ => cmd.args(["--invalidation-mode", "checked-hash"]),
+    };
+    let output = cmd
+        .args(&dirs)
+        .output()
+        .with_context(|| anyhow!("failed in running compileall with {}", python.display()))?;
+    // files which do not compile (test data with syntax errors, python 2 only modules) are kept as source
+    if !output.status.success() {
+        warn!(
+            "some python files could not be compiled, they are kept as source: {}",
+            String::from_utf8_lossy(&output.stdout).trim()
+        );
+    }
+
+    if mode == BytecodeMode::Sourceless {
+        let mut removed = 0;
+        for dir in &dirs {
+            removed += remove_compiled_sources(dir, &shipped)?;
+        }
+        info!("removed {} python sources which have bytecode", removed);
+    }
+    Ok(())
+}
+
+/// fail early with a readable error if the packaged interpreter does not run on this machine
+fn check_interpreter(dist: &Path, python: &Path) -> Result<()> {
+    let output = interpreter_command(dist, python)
+        .args(["-c", "import compileall"])
+        .output()
+        .with_context(|| {
+            anyhow!(
+                "failed in running packaged interpreter {}",
+                python.display()
+            )
+        })?;
+    if !output.status.success() {
+        bail!(
+            "packaged interpreter {} can not run on this machine, bytecode is compiled on the build machine: status={:?} stderr={}",
+            python.display(),
+            output.status,
+            String::from_utf8_lossy(&output.stderr),
+        );
+    }
+    Ok(())
+}
+
+/// the interpreter with the same library path as `bootstrap.sh`, isolated from the environment of the build machine
+fn interpreter_command(dist: &Path, python: &Path) -> Command {
+    let lib_path_var = if cfg!(target_os = "macos") {
+        "DYLD_LIBRARY_PATH"
+    } else {
+        "LD_LIBRARY_PATH"
+    };
+    let mut cmd = Command::new(python);
+    cmd.arg("-I")
+        .env(lib_path_var, dist.join("lib").join("l"))
+        .env_remove("PYTHONHOME")
+        .env_remove("PYTHONPATH");
+    cmd
+}
+
+/// inode and modification time of a file, compileall replaces a `.pyc` it writes with a new file
+type FileStamp = (u64, SystemTime);
+
+fn file_stamp(path: &Path) -> Result<Option<FileStamp>> {
+    match fs::metadata(path) {
+        Ok(meta) => Ok(Some((meta.ino(), meta.modified()?))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => {
+            Err(e).with_context(|| anyhow!("failed in reading metadata of {}", path.display()))
+        }
+    }
+}
+
+/// every `x.pyc` next to a `x.py` in `dir`
+fn legacy_pycs(dir: &Path) -> Result<HashMap<PathBuf, FileStamp>> {
+    let mut pycs = HashMap::new();
+    for path in python_sources(dir)? {
+        let pyc = legacy_pyc_path(&path);
+        if let Some(stamp) = file_stamp(&pyc)? {
+            pycs.insert(pyc, stamp);
+        }
+    }
+    Ok(pycs)
+}
+
+fn python_sources(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut sources = Vec::new();
+    for entry in WalkDir::new(dir) {
+        let entry = entry.with_context(|| anyhow!("failed in walking {}", dir.display()))?;
+        let path = entry.path();
+        if entry.file_type().is_file() && path.extension().is_some_and(|e| e == "py") {
+            sources.push(path.to_path_buf());
+        }
+    }
+    Ok(sources)
+}
+
+/// remove every `x.py` which has a `x.pyc` next to it written by compileall, returns the number of removed files
+/// `shipped` are the `.pyc` files which existed before compileall ran, a source is kept if its `.pyc` is still one of them
+fn remove_compiled_sources(dir: &Path, shipped: &HashMap<PathBuf, FileStamp>) -> Result<usize> {
+    let mut removed = 0;
+    for path in python_sources(dir)? {
+        let pyc = legacy_pyc_path(&path);
+        let Some(stamp) = file_stamp(&pyc)? else {
+            continue;
+        };
+        if shipped.get(&pyc) == Some(&stamp) {
+            continue;
+        }
+        fs::remove_file(&path)
+            .with_context(|| anyhow!("failed in removing source {}", path.display()))?;
+        removed += 1;
+    }
+    Ok(removed)
+}
+
+/// `x.py` -> `x.pyc`
+pub fn legacy_pyc_path(path: &Path) -> PathBuf {
+    path.with_extension("pyc")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_remove_compiled_sources() {
+        let tmp = tempfile::tempdir().unwrap();
+        let pkg = tmp.path().join("pkg");
+        fs::create_dir_all(&pkg).unwrap();
+        fs::write(pkg.join("a.py"), "").unwrap();
+        fs::write(pkg.join("a.pyc"), "").unwrap();
+        // did not compile
+        fs::write(pkg.join("b.py"), "").unwrap();
+        fs::write(pkg.join("data.txt"), "").unwrap();
+
+        assert_eq!(
+            remove_compiled_sources(tmp.path(), &HashMap::new()).unwrap(),
+            1
+        );
+        assert!(!pkg.join("a.py").exists());
+        assert!(pkg.join("a.pyc").exists());
+        assert!(pkg.join("b.py").exists());
+        assert!(pkg.join("data.txt").exists());
+    }
+
+    #[test]
+    fn test_keep_sources_of_shipped_pycs() {
+        let tmp = tempfile::tempdir().unwrap();
+        // shipped in the wheel, compileall failed on the source or skipped it
+        fs::write(tmp.path().join("stale.py"), "").unwrap();
+        fs::write(tmp.path().join("stale.pyc"), "old").unwrap();
+        // shipped in the wheel, recompiled
+        fs::write(tmp.path().join("fresh.py"), "").unwrap();
+        fs::write(tmp.path().join("fresh.pyc"), "old").unwrap();
+        let shipped = legacy_pycs(tmp.path()).unwrap();
+        assert_eq!(shipped.len(), 2);
+
+        // compileall writes a temporary file and renames it over the `.pyc`
+        fs::write(tmp.path().join("fresh.pyc.tmp"), "new").unwrap();
+        fs::rename(
+            tmp.path().join("fresh.pyc.tmp"),
+            tmp.path().join("fresh.pyc"),
+        )
+        .unwrap();
+
+        assert_eq!(remove_compiled_sources(tmp.path(), &shipped).unwrap(), 1);
+        assert!(tmp.path().join("stale.py").exists());
+        assert!(!tmp.path().join("fresh.py").exists());
+    }
+}
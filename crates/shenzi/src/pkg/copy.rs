@@ -0,0 +1,127 @@
+// how files are copied from the environment into dist
+// reflinks share data with the source until one of them is written (copy on write, btrfs, XFS, APFS), hardlinks share the file itself
+// every mode falls back to a plain copy per file, when the filesystem does not support it or the source is on another device
+// files which are modified in dist (reals are patched and stripped, scripts get a shebang) are never hardlinked,
+// writing them would modify the source environment
+
+use std::{fs, io, path::Path};
+
+use anyhow::{Context, Result, anyhow};
+use log::debug;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CopyMode {
+    /// plain copies
+    #[default]
+    Copy,
+    /// copy on write clones, plain copies where the filesystem does not support them
+    Reflink,
+    /// hardlinks to the source for files which are not modified in dist, plain copies otherwise
+    Hardlink,
+    /// reflinks, then hardlinks for files which are not modified in dist, then plain copies
+    Auto,
+}
+
+/// how a single file ended up in dist
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CopyMethod {
+    Copy,
+    Reflink,
+    Hardlink,
+}
+
+/// copy `from` to `to` (replacing it) with `mode`
+/// `modified` is true if the file at `to` is written after the copy, it is then never hardlinked
+pub fn copy_file(from: &Path, to: &Path, mode: CopyMode, modified: bool) -> Result<CopyMethod> {
+    remove_existing(to)?;
+    let try_reflink = matches!(mode, CopyMode::Reflink | CopyMode::Auto);
+    let try_hardlink = matches!(mode, CopyMode::Hardlink | CopyMode::Auto) && !modified;
+
+    if try_reflink {
+        match reflink(from, to) {
+            Ok(()) => return Ok(CopyMethod::Reflink),
+            Err(e) => {
+                debug!(
+                    "reflink failed, falling back: from={} error={}",
+                    from.display(),
+                    e
+                );
+                remove_existing(to)?;
+            }
+        }
+    }
+    if try_hardlink {
+        match fs::hard_link(from, to) {
+            Ok(()) => return Ok(CopyMethod::Hardlink),
+            Err(e) => {
+                debug!(
+                    "hardlink failed, falling back: from={} error={}",
+                    from.display(),
+                    e
+                );
+                remove_existing(to)?;
+            }
+        }
+    }
+    fs::copy(from, to)
+        .with_context(|| anyhow!("failed in copying {} to {}", from.display(), to.display()))?;
+    Ok(CopyMethod::Copy)
+}
+
+/// a clone has the permissions of a new file, they are copied like `fs::copy` does
+fn reflink(from: &Path, to: &Path) -> io::Result<()> {
+    reflink_copy::reflink(from, to)?;
+    fs::set_permissions(to, fs::metadata(from)?.permissions())
+}
+
+fn remove_existing(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e).context(anyhow!(
+            "failed in removing existing file at {}",
+            path.display()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::os::unix::fs::MetadataExt;
+
+    #[test]
+    fn test_copy_file_never_hardlinks_modified_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let src = tmp.path().join("libfoo.so");
+        fs::write(&src, "foo").unwrap();
+        let dest = tmp.path().join("dest");
+        fs::write(&dest, "old").unwrap();
+
+        assert_eq!(
+            copy_file(&src, &dest, CopyMode::Hardlink, false).unwrap(),
+            CopyMethod::Hardlink
+        );
+        assert_eq!(
+            fs::metadata(&dest).unwrap().ino(),
+            fs::metadata(&src).unwrap().ino()
+        );
+
+        assert_eq!(
+            copy_file(&src, &dest, CopyMode::Hardlink, true).unwrap(),
+            CopyMethod::Copy
+        );
+        assert_ne!(
+            fs::metadata(&dest).unwrap().ino(),
+            fs::metadata(&src).unwrap().ino()
+        );
+
+        // reflink or its fallback, depending on the filesystem of the test machine
+        assert_ne!(
+            copy_file(&src, &dest, CopyMode::Auto, true).unwrap(),
+            CopyMethod::Hardlink
+        );
+        fs::write(&dest, "patched").unwrap();
+        assert_eq!(fs::read_to_string(&src).unwrap(), "foo");
+    }
+}
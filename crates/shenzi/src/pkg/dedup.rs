@@ -0,0 +1,189 @@
+// content addressed deduplication of plain files in dist
+// several site-packages (a conda base and a venv, nested site-packages) often contain byte identical data files (model weights, fonts, tzdata)
+// every destination of a plain file with the same digest (and permission bits) is replaced by a hardlink to one of them, sorted by path, so the data is stored once
+// only plain files are deduplicated, binaries are patched for their own location and can't share an inode
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs, io,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, anyhow};
+use log::info;
+use rayon::prelude::*;
+use serde::Serialize;
+
+use crate::{gather::NodeFactory, graph::FileGraph, node::Pkg, pkg::paths::ExportedFileTree};
+
+/// files in dist with the same contents, all of them are hardlinks to the first path
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicateGroup {
+    pub digest: String,
+    pub size: u64,
+    // relative to dist
+    pub paths: Vec<PathBuf>,
+    pub bytes_saved: u64,
+}
+
+/// hardlink the plain files of `graph` in `dist` which have the same contents
+pub fn dedup_plain_files(
+    graph: &FileGraph<NodeFactory>,
+    dist: &Path,
+) -> Result<Vec<DuplicateGroup>> {
+    info!("Step: deduplicate identical plain files in dist");
+    let dist_buf = dist.to_path_buf();
+    let mut destinations: Vec<PathBuf> = graph
+        .iter_nodes()
+        .filter(|node| {
+            matches!(
+                node.pkg,
+                Pkg::SitePackagesPlain { .. } | Pkg::PrefixPlain(_) | Pkg::ExecPrefixPlain(_)
+            )
+        })
+        .filter_map(|node| node.pkg.destination(&node.path, &dist_buf))
+        .collect();
+    destinations.sort();
+    destinations.dedup();
+
+    // only files of the same size can be identical, the others are never read
+    let mut by_size: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+    for path in destinations {
+        // removed after the export (zipped, sourceless bytecode)
+        let meta = match fs::symlink_metadata(&path) {
+            Ok(meta) => meta,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                return Err(e).context(anyhow!("failed in reading metadata of {}", path.display()));
+            }
+        };
+        if meta.is_file() && meta.len() > 0 {
+            by_size.entry(meta.len()).or_default().push(path);
+        }
+    }
+    let candidates: Vec<(u64, PathBuf)> = by_size
+        .into_iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .flat_map(|(size, paths)| paths.into_iter().map(move |p| (size, p)))
+        .collect();
+
+    let digests = candidates
+        .into_par_iter()
+        .map(|(size, path)| digest(&path).map(|d| ((d, size), path)))
+        .collect::<Result<Vec<_>>>()?;
+    let mut by_digest: BTreeMap<(String, u64), Vec<PathBuf>> = BTreeMap::new();
+    for (key, path) in digests {
+        by_digest.entry(key).or_default().push(path);
+    }
+
+    let mut groups = Vec::new();
+    for ((digest, size), mut paths) in by_digest {
+        if paths.len() < 2 {
+            continue;
+        }
+        paths.sort();
+        let bytes_saved = link_to_first(&paths)? * size;
+        groups.push(DuplicateGroup {
+            digest,
+            size,
+            paths: paths
+                .iter()
+                .map(|p| p.strip_prefix(dist).unwrap_or(p).to_path_buf())
+                .collect(),
+            bytes_saved,
+        });
+    }
+    info!(
+        "deduplicated {} groups of identical files, saved {} bytes",
+        groups.len(),
+        groups.iter().map(|g| g.bytes_saved).sum::<u64>()
+    );
+    Ok(groups)
+}
+
+/// replace `paths[1..]` by hardlinks to `paths[0]`, returns the number of files which were replaced
+/// files which already are the same inode (an earlier build) are left alone
+/// files with other permission bits (an executable data file) keep their own inode, a link would change their mode
+fn link_to_first(paths: &[PathBuf]) -> Result<u64> {
+    let first = &paths[0];
+    let first_meta = fs::metadata(first)
+        .with_context(|| anyhow!("failed in reading metadata of {}", first.display()))?;
+    let mut replaced = 0;
+    for path in &paths[1..] {
+        let meta = fs::metadata(path)
+            .with_context(|| anyhow!("failed in reading metadata of {}", path.display()))?;
+        if meta.dev() != first_meta.dev() || meta.mode() & 0o7777 != first_meta.mode() & 0o7777 {
+            continue;
+        }
+        if meta.ino() != first_meta.ino() {
+            replace_with_link(first, path)?;
+        }
+        replaced += 1;
+    }
+    Ok(replaced)
+}
+
+/// link `first` to a temporary name next to `path` and rename it over `path`
+/// `path` is never missing, a failure leaves the duplicate in place
+fn replace_with_link(first: &Path, path: &Path) -> Result<()> {
+    let dir = path
+        .parent()
+        .ok_or_else(|| anyhow!("duplicate does not have a parent {}", path.display()))?;
+    let link = tempfile::Builder::new()
+        .prefix(".shenzi-dedup-")
+        .make_in(dir, |tmp| fs::hard_link(first, tmp))
+        .with_context(|| {
+            anyhow!(
+                "failed in hardlinking {} in {}",
+                first.display(),
+                dir.display()
+            )
+        })?;
+    link.persist(path)
+        .with_context(|| anyhow!("failed in replacing duplicate {}", path.display()))?;
+    Ok(())
+}
+
+fn digest(path: &Path) -> Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    let mut file =
+        fs::File::open(path).with_context(|| anyhow!("failed in opening {}", path.display()))?;
+    io::copy(&mut file, &mut hasher)
+        .with_context(|| anyhow!("failed in reading {}", path.display()))?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_link_to_first() {
+        let tmp = tempfile::tempdir().unwrap();
+        let paths: Vec<PathBuf> = ["a", "b", "c", "d"]
+            .iter()
+            .map(|n| tmp.path().join(n))
+            .collect();
+        for p in &paths {
+            fs::write(p, "weights").unwrap();
+        }
+        fs::set_permissions(&paths[3], fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(link_to_first(&paths).unwrap(), 2);
+        let ino = fs::metadata(&paths[0]).unwrap().ino();
+        assert!(
+            paths[..3]
+                .iter()
+                .all(|p| fs::metadata(p).unwrap().ino() == ino)
+        );
+        assert_eq!(fs::read_to_string(&paths[2]).unwrap(), "weights");
+        // other permission bits, kept as a copy
+        assert_ne!(fs::metadata(&paths[3]).unwrap().ino(), ino);
+        assert_eq!(fs::metadata(&paths[3]).unwrap().mode() & 0o777, 0o755);
+        // no temporary links are left behind
+        assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 4);
+        // a second run finds the links in place and reports the same savings
+        assert_eq!(link_to_first(&paths).unwrap(), 2);
+    }
+}
@@ -0,0 +1,30 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub struct MultipleExportErrors {
+    pub errors: Vec<anyhow::Error>,
+}
+
+impl std::error::Error for MultipleExportErrors {}
+
+impl fmt::Display for MultipleExportErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} errors occurred while exporting files to dist:",
+            self.errors.len()
+        )?;
+        for err in self.errors.iter() {
+            writeln!(
+                f,
+                "---------------------------------------------------------------------------"
+            )?;
+            writeln!(f, "{:#}", err)?;
+            writeln!(
+                f,
+                "---------------------------------------------------------------------------\n\n"
+            )?;
+        }
+        Ok(())
+    }
+}
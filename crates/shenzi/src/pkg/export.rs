@@ -1,29 +1,53 @@
-use std::{fs, path::{Path, PathBuf}};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 
 use anyhow::{Context, Result, anyhow};
 use pathdiff::diff_paths;
 
-use crate::{node::Pkg, paths::make_executable};
+use crate::{
+    node::Pkg,
+    paths::make_executable,
+    pkg::copy::{CopyMode, copy_file},
+};
 
 pub trait Export {
-    fn to_destination(&self, path: &PathBuf, dest: &PathBuf, dist: &PathBuf) -> Result<()>;
+    fn to_destination(
+        &self,
+        path: &PathBuf,
+        dest: &PathBuf,
+        dist: &PathBuf,
+        mode: CopyMode,
+    ) -> Result<()>;
 }
 
 impl Export for Pkg {
-    fn to_destination(&self, path: &PathBuf, dest: &PathBuf, dist: &PathBuf) -> Result<()> {
+    fn to_destination(
+        &self,
+        path: &PathBuf,
+        dest: &PathBuf,
+        dist: &PathBuf,
+        mode: CopyMode,
+    ) -> Result<()> {
         mk_parent_dirs(dest)?;
         match self {
-            Pkg::SitePackagesPlain { _site_packages: _, alias: _, rel_path: _ }
+            Pkg::SitePackagesPlain {
+                _site_packages: _,
+                alias: _,
+                rel_path: _,
+            }
             | Pkg::Executable
             | Pkg::PrefixPlain(_)
             | Pkg::MainPyScript
             | Pkg::ExecPrefixPlain(_) => {
-                fs::copy(path, dest)?;
-            },
+                copy_file(path, dest, mode, false)?;
+            }
             Pkg::PlainPyBinaryFile => {
-                fs::copy(path, dest)?;
-                mk_file_executable(path, true)?;
-            },
+                // the shebang is written to the copy in dist
+                copy_file(path, dest, mode, true)?;
+                mk_file_executable(dest, true)?;
+            }
 
             Pkg::BinaryInLDPath { symlinks, sha: _ } => {
                 let (rel_path, dest_dir) = mk_symlink_in_dest(dest, dist, path)?;
@@ -41,24 +65,28 @@ impl Export for Pkg {
                         )
                     })?;
                 }
-            },
+            }
 
-            Pkg::SitePackagesBinary { _site_packages: _, alias: _, rel_path: _, sha: _ }
+            Pkg::SitePackagesBinary {
+                _site_packages: _,
+                alias: _,
+                rel_path: _,
+                sha: _,
+            }
             | Pkg::Binary { sha: _ }
             | Pkg::PrefixBinary(_)
             | Pkg::ExecPrefixBinary(_) => {
                 mk_symlink_in_dest(dest, dist, path)?;
-            },
-            | Pkg::BinaryInPath { sha: _ } => {
+            }
+            Pkg::BinaryInPath { sha: _ } => {
                 mk_symlink_in_dest(dest, dist, path)?;
                 mk_file_executable(path, false)?;
-            },
+            }
         };
         Ok(())
     }
 }
 
-
 fn mk_file_executable(path: &Path, is_plain: bool) -> Result<()> {
     if is_plain {
         let ext = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
@@ -73,7 +101,6 @@ fn mk_file_executable(path: &Path, is_plain: bool) -> Result<()> {
     Ok(())
 }
 
-
 fn prepend_shebang(path: &Path, shebang: &str) -> Result<()> {
     let content = std::fs::read_to_string(path)?;
     let new_content = format!("{}\n{}", shebang, content);
@@ -81,8 +108,11 @@ fn prepend_shebang(path: &Path, shebang: &str) -> Result<()> {
     Ok(())
 }
 
-
-fn mk_symlink_in_dest(dest: &PathBuf, dist: &PathBuf, path: &PathBuf) -> Result<(PathBuf, PathBuf)> {
+fn mk_symlink_in_dest(
+    dest: &PathBuf,
+    dist: &PathBuf,
+    path: &PathBuf,
+) -> Result<(PathBuf, PathBuf)> {
     if !dest.starts_with(&dist) {
         panic!(
             "failed in moving path to destination, it is mandatory for a shared lib path to be inside dist, dist={} source_path={} destination={}",
@@ -91,7 +121,10 @@ fn mk_symlink_in_dest(dest: &PathBuf, dist: &PathBuf, path: &PathBuf) -> Result<
             dest.display()
         );
     }
-    let parent_dir = dest.parent().expect(&format!("fatal error: tried symlinking file at dest={}, but it does not have any parent", dest.display()));
+    let parent_dir = dest.parent().expect(&format!(
+        "fatal error: tried symlinking file at dest={}, but it does not have any parent",
+        dest.display()
+    ));
     let rel_path = diff_paths(&path, &parent_dir).ok_or_else(|| {
         anyhow!(
             "failed in finding relative path for symlinking to destination, destination={} path={}",
@@ -0,0 +1,219 @@
+// incremental builds: compare the build record of the previous build with the current graph
+// and only export the nodes which changed, everything else in dist is left untouched
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, anyhow};
+use log::info;
+
+use crate::pkg::{
+    bytecode::legacy_pyc_path,
+    record::{BuildRecord, NodeRecord},
+};
+
+#[derive(Debug)]
+pub struct IncrementalPlan {
+    // original paths of all nodes which need to be exported again
+    pub changed: HashSet<PathBuf>,
+    // paths inside dist (relative to dist) which the current build does not produce anymore
+    pub stale: Vec<PathBuf>,
+}
+
+pub fn plan(previous: &BuildRecord, current: &BuildRecord) -> IncrementalPlan {
+    let previous_by_path = previous.by_path();
+    let mut changed: HashSet<PathBuf> = current
+        .nodes
+        .iter()
+        .filter(|n| match previous_by_path.get(&n.path) {
+            None => true,
+            Some(prev) => is_changed(prev, n),
+        })
+        .map(|n| n.path.clone())
+        .collect();
+
+    // symlink farms of dependents point to the reals of their dependencies, the reals file name contains the digest
+    // if a dependency changed, the farm (and the patched copy) of every direct dependent is re-created
+    let mut dependents: HashMap<&PathBuf, Vec<&PathBuf>> = HashMap::new();
+    for n in &current.nodes {
+        for dep in &n.deps {
+            dependents.entry(dep).or_default().push(&n.path);
+        }
+    }
+    let directly_changed: Vec<PathBuf> = changed.iter().cloned().collect();
+    for p in &directly_changed {
+        if let Some(ds) = dependents.get(p) {
+            changed.extend(ds.iter().map(|d| (*d).clone()));
+        }
+    }
+
+    // true copies (same digest) share their reals and symlink farm, they are exported together
+    let mut by_reals: HashMap<&PathBuf, Vec<&PathBuf>> = HashMap::new();
+    for n in &current.nodes {
+        if let Some(ref reals) = n.layout.reals {
+            by_reals.entry(reals).or_default().push(&n.path);
+        }
+    }
+    for group in by_reals.values() {
+        if group.iter().any(|p| changed.contains(*p)) {
+            changed.extend(group.iter().map(|p| (*p).clone()));
+        }
+    }
+
+    let current_paths = current.dist_paths();
+    let mut stale: Vec<PathBuf> = previous
+        .dist_paths()
+        .into_iter()
+        .filter(|p| !current_paths.contains(p))
+        .cloned()
+        .collect();
+    stale.sort();
+
+    IncrementalPlan { changed, stale }
+}
+
+fn is_changed(previous: &NodeRecord, current: &NodeRecord) -> bool {
+    previous.sha != current.sha
+        || previous.kind != current.kind
+        || previous.layout != current.layout
+        || previous.deps != current.deps
+}
+
+/// delete everything which the previous build wrote, but the current one does not
+pub fn remove_stale(dist: &Path, stale: &Vec<PathBuf>) -> Result<()> {
+    info!(
+        "incremental: removing {} stale paths from dist",
+        stale.len()
+    );
+    for rel_path in stale {
+        let path = dist.join(rel_path);
+        // with `--bytecode sourceless` the pyc is all that is left of the file, and it is still importable
+        if path.extension().is_some_and(|e| e == "py") {
+            remove_if_exists(&legacy_pyc_path(&path))?;
+        }
+        remove_if_exists(&path)?;
+    }
+    Ok(())
+}
+
+/// symlink farms are re-created from scratch, links to dependencies which were removed should not stay behind
+pub fn clear_symlink_farms(dist: &Path, records: &Vec<&NodeRecord>) -> Result<()> {
+    for record in records {
+        if let Some(ref farm) = record.layout.symlink_farm {
+            remove_if_exists(&dist.join(farm))?;
+        }
+    }
+    Ok(())
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    let meta = match fs::symlink_metadata(path) {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => {
+            return Err(e).context(anyhow!("failed in reading metadata of {}", path.display()));
+        }
+    };
+    if meta.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+    .with_context(|| anyhow!("failed in removing stale path {}", path.display()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pkg::paths::NodeLayout;
+
+    fn record(path: &str, sha: &str, reals: Option<&str>, deps: Vec<&str>) -> NodeRecord {
+        NodeRecord {
+            path: PathBuf::from(path),
+            sha: sha.to_string(),
+            size: 0,
+            kind: "Binary".to_string(),
+            layout: NodeLayout {
+                destination: None,
+                reals: reals.map(PathBuf::from),
+                symlink_farm: reals.map(|r| PathBuf::from("symlinks").join(r)),
+            },
+            deps: deps.into_iter().map(PathBuf::from).collect(),
+        }
+    }
+
+    fn build_record(nodes: Vec<NodeRecord>) -> BuildRecord {
+        BuildRecord {
+            shenzi_version: "test".to_string(),
+            bytecode: Default::default(),
+            zip_packages: false,
+            strip: Default::default(),
+            debug_archive: false,
+            nodes,
+        }
+    }
+
+    #[test]
+    fn test_plan_unchanged() {
+        let nodes = vec![
+            record("/a.so", "aaaa", Some("reals/r/aaaa_a.so"), vec!["/b.so"]),
+            record("/b.so", "bbbb", Some("reals/r/bbbb_b.so"), vec![]),
+        ];
+        let p = plan(&build_record(nodes.clone()), &build_record(nodes));
+        assert!(p.changed.is_empty());
+        assert!(p.stale.is_empty());
+    }
+
+    #[test]
+    fn test_plan_changed_dependency() {
+        let previous = build_record(vec![
+            record("/a.so", "aaaa", Some("reals/r/aaaa_a.so"), vec!["/b.so"]),
+            record("/b.so", "bbbb", Some("reals/r/bbbb_b.so"), vec![]),
+            record("/c.so", "cccc", Some("reals/r/cccc_c.so"), vec![]),
+            record("/gone.so", "dddd", Some("reals/r/dddd_gone.so"), vec![]),
+        ]);
+        let current = build_record(vec![
+            record("/a.so", "aaaa", Some("reals/r/aaaa_a.so"), vec!["/b.so"]),
+            record("/b.so", "eeee", Some("reals/r/eeee_b.so"), vec![]),
+            record("/c.so", "cccc", Some("reals/r/cccc_c.so"), vec![]),
+        ]);
+        let p = plan(&previous, &current);
+
+        let mut changed: Vec<PathBuf> = p.changed.into_iter().collect();
+        changed.sort();
+        // a depends on b, its symlink farm needs to point to the new reals of b
+        assert_eq!(
+            changed,
+            vec![PathBuf::from("/a.so"), PathBuf::from("/b.so")]
+        );
+        assert_eq!(
+            p.stale,
+            vec![
+                PathBuf::from("reals/r/bbbb_b.so"),
+                PathBuf::from("reals/r/dddd_gone.so"),
+                PathBuf::from("symlinks/reals/r/bbbb_b.so"),
+                PathBuf::from("symlinks/reals/r/dddd_gone.so"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_true_copies_are_exported_together() {
+        let previous = build_record(vec![record(
+            "/a.so",
+            "aaaa",
+            Some("reals/r/aaaa_a.so"),
+            vec![],
+        )]);
+        let current = build_record(vec![
+            record("/a.so", "aaaa", Some("reals/r/aaaa_a.so"), vec![]),
+            record("/copy/a.so", "aaaa", Some("reals/r/aaaa_a.so"), vec![]),
+        ]);
+        let p = plan(&previous, &current);
+        assert_eq!(p.changed.len(), 2);
+        assert!(p.stale.is_empty());
+    }
+}
@@ -1,60 +1,143 @@
 // main function which moves stuff to dist
 
-use std::{collections::{HashMap, HashSet}, fs, path::PathBuf};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
 
 use anyhow::{Context, Result, anyhow};
 use log::info;
 use pathdiff::diff_paths;
+use rayon::prelude::*;
 
 use crate::{
     external::download_patchelf,
     gather::NodeFactory,
     graph::FileGraph,
-    node::Node,
+    node::{Node, Pkg},
     pkg::{
+        bytecode::{BytecodeMode, compile_bytecode},
+        copy::{CopyMode, copy_file},
+        error::MultipleExportErrors,
         export::{Export, mk_parent_dirs},
         paths::ExportedFileTree,
+        strip::{StripMode, StrippedLibrary, strip_reals},
     },
+    report::Timings,
     warnings::Warning,
 };
 
 pub use patch::LibPatch;
 
 pub mod bootstrap;
+pub mod bytecode;
+pub mod copy;
+pub mod dedup;
+mod error;
 pub mod export;
+pub mod incremental;
 pub mod patch;
 pub mod paths;
+pub mod plan;
+pub mod record;
+pub mod staging;
+pub mod strip;
+pub mod zipimport;
+
+/// how nodes are exported to dist
+#[derive(Debug, Clone, Default)]
+pub struct ExportOptions {
+    pub strip: StripMode,
+    // where the debug info of stripped binaries is collected, if at all
+    pub debug_dir: Option<PathBuf>,
+    pub bytecode: BytecodeMode,
+    pub copy_mode: CopyMode,
+}
+
+/// result of exporting nodes
+#[derive(Debug)]
+pub struct Exported {
+    pub main_script: PathBuf,
+    pub stripped: Vec<StrippedLibrary>,
+}
 
 pub fn move_all_nodes(
     graph: &FileGraph<NodeFactory>,
     dist: &PathBuf,
     main_script_path: &PathBuf,
-) -> Result<PathBuf> {
-    info!("exporting files to dist");
+    options: &ExportOptions,
+    timings: &mut Timings,
+) -> Result<Exported> {
+    let nodes: Vec<&Node> = graph.iter_nodes().collect();
+    move_nodes(graph, &nodes, dist, main_script_path, options, timings)
+}
+
+/// same as `move_all_nodes`, but only exports `nodes`, used by incremental builds
+pub fn move_nodes(
+    graph: &FileGraph<NodeFactory>,
+    nodes: &Vec<&Node>,
+    dist: &PathBuf,
+    main_script_path: &PathBuf,
+    options: &ExportOptions,
+    timings: &mut Timings,
+) -> Result<Exported> {
+    info!("exporting files to dist, nodes={}", nodes.len());
     download_patchelf().context("error in downloading patchelf")?;
 
-    // TODO: parallelize each step
-    let nodes: Vec<&Node> = graph.iter_nodes().collect();
-    move_reals(&nodes, dist)?;
-    mk_symlink_farms(&nodes, graph, dist)?;
-    cp_to_destinations(&nodes, dist)?;
+    let start = Instant::now();
+    move_reals(nodes, dist, options.copy_mode)?;
+    timings.record_since("export: move_reals", start);
+    // before the reals are patched
+    let start = Instant::now();
+    let stripped = strip_reals(nodes, dist, options.strip, options.debug_dir.as_deref())
+        .context("failed in stripping binaries")?;
+    timings.record_since("export: strip", start);
+    let start = Instant::now();
+    mk_symlink_farms(nodes, graph, dist)?;
+    timings.record_since("export: mk_symlink_farms", start);
+    let start = Instant::now();
+    cp_to_destinations(nodes, dist, options.copy_mode)?;
+    timings.record_since("export: cp_to_destinations", start);
+    // needs the complete python tree in dist
+    if options.bytecode != BytecodeMode::None {
+        let start = Instant::now();
+        let python = graph
+            .iter_nodes()
+            .find(|n| matches!(n.pkg, Pkg::Executable))
+            .and_then(|n| n.pkg.reals(n, dist))
+            .ok_or_else(|| anyhow!("could not find the packaged python executable"))?;
+        compile_bytecode(dist, &python, options.bytecode)
+            .context("failed in compiling python files to bytecode")?;
+        timings.record_since("export: bytecode", start);
+    }
 
-    graph
+    let main_script = graph
         .get_node_by_path(main_script_path)
         .map(|n| n.path.clone())
         .ok_or(anyhow!(
             "could not find the final path for main script, script={}",
             main_script_path.display()
-        ))
+        ))?;
+    Ok(Exported {
+        main_script,
+        stripped,
+    })
+}
+
+pub fn warnings_file_path(dist: &Path) -> PathBuf {
+    dist.join("warnings.txt")
 }
 
-pub fn write_warnings(warnings: Vec<Warning>, dist: &PathBuf) -> Result<(PathBuf, bool)> {
-    let p = dist.join("warnings.txt");
-    if warnings.len() == 0 {
+pub fn write_warnings(warnings: &[Warning], dist: &Path) -> Result<(PathBuf, bool)> {
+    let p = warnings_file_path(dist);
+    if warnings.is_empty() {
         Ok((p, false))
     } else {
         let contents = warnings
-            .into_iter()
+            .iter()
             .map(|w| format!("{}", w))
             .collect::<Vec<String>>()
             .join("\n");
@@ -63,24 +146,66 @@ pub fn write_warnings(warnings: Vec<Warning>, dist: &PathBuf) -> Result<(PathBuf
     }
 }
 
-pub fn move_reals(nodes: &Vec<&Node>, dist: &PathBuf) -> Result<()> {
-    info!("Step: copy assets to dist/reals");
-    let total = nodes.len();
-    let mut i = 0;
-    for node in nodes {
-        mk_reals(node, dist).with_context(|| {
-            format!(
-                "could not create reals directory for path={} dist={}",
-                node.path.display(),
-                dist.display()
-            )
-        })?;
-        i += 1;
-        if total / 10 != 0 && i % (total / 10) == 0 {
-            info!("\tprogress: {}/{} files", i, total);
+/// counts finished files of a step which runs in parallel, logs every 10%
+struct Progress {
+    total: usize,
+    done: AtomicUsize,
+}
+
+impl Progress {
+    fn new(total: usize) -> Self {
+        Self {
+            total,
+            done: AtomicUsize::new(0),
         }
     }
-    Ok(())
+
+    fn tick(&self) {
+        let i = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.total / 10 != 0 && i.is_multiple_of(self.total / 10) {
+            info!("\tprogress: {}/{} files", i, self.total);
+        }
+    }
+}
+
+/// every failure of a step is reported, not only the first one
+fn collect_errors(results: Vec<Result<()>>) -> Result<()> {
+    let errors: Vec<anyhow::Error> = results.into_iter().filter_map(|r| r.err()).collect();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow::Error::from(MultipleExportErrors { errors }))
+    }
+}
+
+pub fn move_reals(nodes: &Vec<&Node>, dist: &PathBuf, mode: CopyMode) -> Result<()> {
+    info!("Step: copy assets to dist/reals");
+    // true copies share their reals, the file is copied once
+    let mut seen_reals = HashSet::new();
+    let unique: Vec<&Node> = nodes
+        .iter()
+        .filter(|node| match node.pkg.reals(node, dist) {
+            Some(reals) => seen_reals.insert(reals),
+            None => false,
+        })
+        .copied()
+        .collect();
+    let progress = Progress::new(unique.len());
+    let results = unique
+        .par_iter()
+        .map(|node| {
+            let res = mk_reals(node, dist, mode).map(|_| ()).with_context(|| {
+                format!(
+                    "could not create reals directory for path={} dist={}",
+                    node.path.display(),
+                    dist.display()
+                )
+            });
+            progress.tick();
+            res
+        })
+        .collect();
+    collect_errors(results)
 }
 
 pub fn mk_symlink_farms(
@@ -89,10 +214,41 @@ pub fn mk_symlink_farms(
     dist: &PathBuf,
 ) -> Result<()> {
     info!("Step: make symlink farms");
-    let total = nodes.len();
-    let mut i = 0;
-    let mut done_reals = HashSet::new();
+    // true copies share their symlink farm and their reals, they are handled together in their original order
+    // the reals are patched once, this can happen if two libraries have the same sha but exist separately on the file system
+    let mut groups: Vec<Vec<&Node>> = Vec::new();
+    let mut group_by_farm: HashMap<PathBuf, usize> = HashMap::new();
     for node in nodes {
+        let Some(farm) = node.pkg.symlink_farm(&node.path, dist) else {
+            continue;
+        };
+        match group_by_farm.get(&farm) {
+            Some(i) => groups[*i].push(node),
+            None => {
+                group_by_farm.insert(farm, groups.len());
+                groups.push(vec![node]);
+            }
+        }
+    }
+    let progress = Progress::new(nodes.len());
+    let results = groups
+        .par_iter()
+        .map(|group| {
+            let res = mk_symlink_farm_group(group, g, dist);
+            group.iter().for_each(|_| progress.tick());
+            res
+        })
+        .collect();
+    collect_errors(results)
+}
+
+fn mk_symlink_farm_group(
+    group: &[&Node],
+    g: &FileGraph<NodeFactory>,
+    dist: &PathBuf,
+) -> Result<()> {
+    let mut patched = false;
+    for node in group {
         let deps = g.get_node_dependencies(node);
         let symlink_farm = mk_symlink_farm(node, &deps, dist).with_context(|| {
             format!(
@@ -101,94 +257,107 @@ pub fn mk_symlink_farms(
                 dist.display()
             )
         })?;
-        let real_path = node.pkg.reals(&node, dist);
-        if let Some(ref real_path) = real_path {
-            if done_reals.contains(real_path) {
-                // skip repeated node. this can happen if two libraries have the same sha but exist separately on the file system (true copies)
-                continue;
-            }
-            done_reals.insert(real_path.clone());
+        if patched {
+            continue;
         }
-        symlink_farm
-            .as_ref()
-            .map(|p| -> Result<()> {
-                match real_path {
-                    Some(ref real_path) => node.deps.patch(real_path, &p).with_context(|| {
-                        anyhow!(
-                            "failed in patching shared library at node_path={} real_path={} symlink_farm={}",
-                            node.path.display(),
-                            real_path.display(),
-                            p.display()
-                        )
-                    }),
-                    None => Ok(()),
-                }
+        let (Some(p), Some(real_path)) = (symlink_farm, node.pkg.reals(node, dist)) else {
+            continue;
+        };
+        node.deps
+            .patch(&real_path, &p)
+            .with_context(|| {
+                anyhow!(
+                    "failed in patching shared library at node_path={} real_path={} symlink_farm={}",
+                    node.path.display(),
+                    real_path.display(),
+                    p.display()
+                )
             })
-            .transpose()
             .with_context(|| {
                 anyhow!(
                     "failed in patching library for node, path={}",
                     node.path.display()
                 )
             })?;
-        i += 1;
-        if total / 10 != 0 && i % (total / 10) == 0 {
-            info!("\tprogress: {}/{} files", i, total);
-        }
+        patched = true;
     }
     Ok(())
 }
 
-pub fn cp_to_destinations(nodes: &Vec<&Node>, dist: &PathBuf) -> Result<()> {
+pub fn cp_to_destinations(nodes: &Vec<&Node>, dist: &PathBuf, mode: CopyMode) -> Result<()> {
     info!("Step: copy/move/symlink to destination (site-packages)");
-    let total = nodes.len();
-    let mut i = 0;
+    // the reals of true copies are patched for the destination of the first node only
     let mut done_reals = HashSet::new();
-    for node in nodes {
-        let real_path = node.pkg.reals(&node, dist);
-        let symlink_farm = node.pkg.symlink_farm(&node.path, dist);
-        let path_to_cp_to_destination = real_path.as_ref().unwrap_or(&node.path);
-        let destination = node.pkg.destination(&node.path, dist);
-        destination
-            .as_ref()
-            .map(|dest| {
-                node.pkg
-                    .to_destination(&path_to_cp_to_destination, &dest, &dist)
-            })
-            .transpose()
-            .with_context(|| {
-                format!(
-                    "could not move to destination for path={} dist={}",
-                    node.path.display(),
-                    dist.display()
-                )
-            })?;
-
-        if let (Some(dest_path), Some(symlink_farm_path), Some(real_path)) = (
-            destination.as_ref(),
-            symlink_farm.as_ref(),
-            real_path.as_ref(),
-        ) {
-            // hack: this is very bad
-            // need better code for this
-            if done_reals.contains(real_path) {
-                // skip repeated node. this can happen if two libraries have the same sha but exist separately on the file system (true copies)
-                continue;
+    let patch_for_destination: HashSet<&PathBuf> = nodes
+        .iter()
+        .filter(|node| {
+            let has_destination = node.pkg.destination(&node.path, dist).is_some()
+                && node.pkg.symlink_farm(&node.path, dist).is_some();
+            match node.pkg.reals(node, dist) {
+                Some(real_path) if has_destination => done_reals.insert(real_path),
+                _ => false,
             }
-            node.deps.patch_for_destination(dest_path, real_path, symlink_farm_path).with_context(|| {
-                anyhow!("failure in patching destination for destination={} real_path={} symlink_farm={}", dest_path.display(), real_path.display(), symlink_farm_path.display())
-            })?;
-            done_reals.insert(real_path.clone());
-        }
-        i += 1;
-        if total / 10 != 0 && i % (total / 10) == 0 {
-            info!("\tprogress: {}/{} files", i, total);
-        }
+        })
+        .map(|node| &node.path)
+        .collect();
+
+    // `lib/l` and `bin/b` are flat, two nodes can write the same file name there, they are exported serially in order
+    let (flat, rest): (Vec<&Node>, Vec<&Node>) = nodes.iter().partition(|node| {
+        matches!(
+            node.pkg,
+            Pkg::BinaryInLDPath { .. } | Pkg::BinaryInPath { .. } | Pkg::PlainPyBinaryFile
+        )
+    });
+    let progress = Progress::new(nodes.len());
+    let export = |node: &Node| {
+        let res = cp_to_destination(node, dist, patch_for_destination.contains(&node.path), mode);
+        progress.tick();
+        res
+    };
+    let mut results: Vec<Result<()>> = flat.iter().map(|node| export(node)).collect();
+    results.extend(rest.par_iter().map(|node| export(node)).collect::<Vec<_>>());
+    collect_errors(results)
+}
+
+fn cp_to_destination(
+    node: &Node,
+    dist: &PathBuf,
+    patch_for_destination: bool,
+    mode: CopyMode,
+) -> Result<()> {
+    let real_path = node.pkg.reals(node, dist);
+    let symlink_farm = node.pkg.symlink_farm(&node.path, dist);
+    let path_to_cp_to_destination = real_path.as_ref().unwrap_or(&node.path);
+    let destination = node.pkg.destination(&node.path, dist);
+    destination
+        .as_ref()
+        .map(|dest| {
+            node.pkg
+                .to_destination(path_to_cp_to_destination, dest, dist, mode)
+        })
+        .transpose()
+        .with_context(|| {
+            format!(
+                "could not move to destination for path={} dist={}",
+                node.path.display(),
+                dist.display()
+            )
+        })?;
+
+    if let (true, Some(dest_path), Some(symlink_farm_path), Some(real_path)) = (
+        patch_for_destination,
+        destination.as_ref(),
+        symlink_farm.as_ref(),
+        real_path.as_ref(),
+    ) {
+        node.deps.patch_for_destination(dest_path, real_path, symlink_farm_path).with_context(|| {
+            anyhow!("failure in patching destination for destination={} real_path={} symlink_farm={}", dest_path.display(), real_path.display(), symlink_farm_path.display())
+        })?;
     }
     Ok(())
 }
 
-fn mk_reals(node: &Node, dist: &PathBuf) -> Result<Option<PathBuf>> {
+fn mk_reals(node: &Node, dist: &PathBuf, mode: CopyMode) -> Result<Option<PathBuf>> {
     node.pkg
         .reals(&node, dist)
         .map(|dest| -> Result<PathBuf> {
@@ -198,15 +367,8 @@ fn mk_reals(node: &Node, dist: &PathBuf) -> Result<Option<PathBuf>> {
                     dest.display()
                 )
             })?;
-            if dest.exists() {
-                fs::remove_file(&dest).with_context(|| {
-                    anyhow!(
-                        "failed in removing existing file at destination, dest={}",
-                        dest.display()
-                    )
-                })?;
-            }
-            fs::copy(&node.path, &dest).with_context(|| {
+            // reals are patched and stripped in place
+            copy_file(&node.path, &dest, mode, true).with_context(|| {
                 anyhow!(
                     "failed in copying reals to destination, dest={}",
                     dest.display()
@@ -1,47 +1,109 @@
 use std::{
-    path::PathBuf,
+    path::{Path, PathBuf},
     process::{Command, Stdio},
 };
 
 use anyhow::{Context, Result, anyhow, bail};
 use pathdiff::diff_paths;
+use serde::Serialize;
 
 use crate::{external::patchelf_path, parse::Elf, paths::file_name_as_str};
 
+/// a single patchelf invocation
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum ElfPatchOp {
+    RemoveRpath {
+        path: PathBuf,
+    },
+    AddRpath {
+        path: PathBuf,
+        rpath: String,
+    },
+    ReplaceNeeded {
+        path: PathBuf,
+        old: String,
+        new: String,
+    },
+}
+
 pub fn patch_elf_for_destination(
-    dest_path: &PathBuf,
-    real_path: &PathBuf,
-    symlink_farm_path: &PathBuf,
+    dest_path: &Path,
+    real_path: &Path,
+    symlink_farm_path: &Path,
 ) -> Result<()> {
+    execute(&plan_patch_elf_for_destination(
+        dest_path,
+        real_path,
+        symlink_farm_path,
+    )?)
+}
+
+pub fn plan_patch_elf_for_destination(
+    dest_path: &Path,
+    real_path: &Path,
+    symlink_farm_path: &Path,
+) -> Result<Vec<ElfPatchOp>> {
     let rpath = get_new_rpath(dest_path, symlink_farm_path)?;
-    add_rpath(&rpath, real_path)?;
-    Ok(())
+    Ok(vec![ElfPatchOp::AddRpath {
+        path: real_path.to_path_buf(),
+        rpath,
+    }])
+}
+
+pub fn patch_elf(elf: &Elf, reals_path: &Path, symlink_farm_path: &Path) -> Result<()> {
+    check_dependencies_in_farm(reals_path, symlink_farm_path, elf)?;
+    execute(&plan_patch_elf(elf, reals_path, symlink_farm_path)?)
 }
 
-pub fn patch_elf(elf: &Elf, reals_path: &PathBuf, symlink_farm_path: &PathBuf) -> Result<()> {
+pub fn plan_patch_elf(
+    elf: &Elf,
+    reals_path: &Path,
+    symlink_farm_path: &Path,
+) -> Result<Vec<ElfPatchOp>> {
     // TODO: add support to download patchelf if it does not exist
     // TODO: linux does not need a symlink farm, the reals path would simply be the libname
     // and we just add everything in the same folder, the final rpath would also simply be $ORIGIN
     // for now doing the mac structure
     // why this matters is that the rpath can be bigger than what is there originally in the binary
     // this might result in us not being able to patch it
+    let mut ops = Vec::new();
     if elf.dt_needed.len() == 0 {
         // has no dependency, no need to patch
-        return Ok(());
+        return Ok(ops);
     }
     if elf.all_dt_rpaths.len() + elf.all_dt_runpaths.len() > 0 {
         // only remove rpath if there is any
-        rm_rpath(reals_path).with_context(|| {
-            anyhow!(
-                "failed in removing RPATH for {}, all_dt_rpaths={:?} all_dt_runpaths={:?}",
-                reals_path.display(),
-                elf.all_dt_rpaths,
-                elf.all_dt_runpaths
-            )
-        })?;
+        ops.push(ElfPatchOp::RemoveRpath {
+            path: reals_path.to_path_buf(),
+        });
+    }
+    ops.push(ElfPatchOp::AddRpath {
+        path: reals_path.to_path_buf(),
+        rpath: get_new_rpath(reals_path, symlink_farm_path)?,
+    });
+    // sorted, the plan should not depend on hash map order
+    let mut needed: Vec<(&String, &PathBuf)> = elf.dt_needed.iter().collect();
+    needed.sort();
+    for (old, parent_path) in needed {
+        ops.push(ElfPatchOp::ReplaceNeeded {
+            path: reals_path.to_path_buf(),
+            old: old.clone(),
+            new: file_name_as_str(parent_path)?,
+        });
+    }
+    Ok(ops)
+}
+
+fn execute(ops: &[ElfPatchOp]) -> Result<()> {
+    for op in ops {
+        match op {
+            ElfPatchOp::RemoveRpath { path } => rm_rpath(path)
+                .with_context(|| anyhow!("failed in removing RPATH for {}", path.display()))?,
+            ElfPatchOp::AddRpath { path, rpath } => add_rpath(rpath, path)?,
+            ElfPatchOp::ReplaceNeeded { path, old, new } => modify_dt_needed(old, new, path)?,
+        }
     }
-    add_rpath(&get_new_rpath(reals_path, symlink_farm_path)?, reals_path)?;
-    modify_all_dt_needed(reals_path, symlink_farm_path, elf)?;
     Ok(())
 }
 
@@ -89,7 +151,7 @@ fn add_rpath(rpath: &str, path: &PathBuf) -> Result<()> {
 }
 
 // TODO: remove this duplication from mac
-fn get_new_rpath(real_path: &PathBuf, symlink_farm: &PathBuf) -> Result<String> {
+fn get_new_rpath(real_path: &Path, symlink_farm: &Path) -> Result<String> {
     let real_path_dir = real_path.parent().ok_or_else(|| {
         anyhow!(
             "failed in getting parent of real_path while patching it, path={}",
@@ -112,12 +174,12 @@ fn get_new_rpath(real_path: &PathBuf, symlink_farm: &PathBuf) -> Result<String>
     Ok(format!("$ORIGIN/{}/", rel_path))
 }
 
-fn modify_all_dt_needed(
-    reals_path: &PathBuf,
-    symlink_farm_path: &PathBuf,
+fn check_dependencies_in_farm(
+    reals_path: &Path,
+    symlink_farm_path: &Path,
     elf: &Elf,
 ) -> Result<()> {
-    for (old, parent_path) in &elf.dt_needed {
+    for parent_path in elf.dt_needed.values() {
         let lib_name = file_name_as_str(&parent_path)?;
         let lib_in_farm = symlink_farm_path.join(&lib_name);
         if !lib_in_farm.exists() {
@@ -128,7 +190,6 @@ fn modify_all_dt_needed(
                 lib_name
             );
         }
-        modify_dt_needed(&old, &lib_name, reals_path)?;
     }
     Ok(())
 }
@@ -161,5 +222,7 @@ fn modify_dt_needed(old: &str, new: &str, path: &PathBuf) -> Result<()> {
 
 fn patchelf() -> Result<String> {
     let p = patchelf_path()?;
-    p.to_str().map(|p| p.to_string()).ok_or(anyhow!("failed in converting patchelf path to string"))
-}
\ No newline at end of file
+    p.to_str()
+        .map(|p| p.to_string())
+        .ok_or(anyhow!("failed in converting patchelf path to string"))
+}
@@ -3,16 +3,64 @@ use crate::{parse::Macho, paths::file_name_as_str};
 // basically all install_name_tool operations
 
 use std::{
-    path::PathBuf,
+    path::{Path, PathBuf},
     process::{Command, Stdio},
 };
 
 use anyhow::{Result, anyhow, bail};
 use pathdiff::diff_paths;
+use serde::Serialize;
 
-pub fn patch_macho(mach: &Macho, reals_path: &PathBuf, symlink_farm_path: &PathBuf) -> Result<()> {
+/// a single install_name_tool (or codesign) invocation
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum MachoPatchOp {
+    DeleteRpath {
+        path: PathBuf,
+        rpath: String,
+    },
+    ChangeLoadCommand {
+        path: PathBuf,
+        old: String,
+        new: String,
+    },
+    AddRpath {
+        path: PathBuf,
+        rpath: String,
+    },
+    SetId {
+        path: PathBuf,
+        id: String,
+    },
+    Sign {
+        path: PathBuf,
+    },
+}
+
+pub fn patch_macho(mach: &Macho, reals_path: &Path, symlink_farm_path: &Path) -> Result<()> {
+    check_dependencies_in_farm(reals_path, symlink_farm_path, mach)?;
+    for op in plan_patch_macho(mach, reals_path, symlink_farm_path)? {
+        match op {
+            MachoPatchOp::DeleteRpath { path, rpath } => rm_rpath(&rpath, &path)?,
+            MachoPatchOp::ChangeLoadCommand { path, old, new } => {
+                modify_load_command(&old, &new, &path)?
+            }
+            MachoPatchOp::AddRpath { path, rpath } => add_rpath(&rpath, &path)?,
+            MachoPatchOp::SetId { path, id } => set_dylib_id(id, &path)?,
+            MachoPatchOp::Sign { path } => sign_dylib(&path)?,
+        }
+    }
+    Ok(())
+}
+
+pub fn plan_patch_macho(
+    mach: &Macho,
+    reals_path: &Path,
+    symlink_farm_path: &Path,
+) -> Result<Vec<MachoPatchOp>> {
+    let mut ops = Vec::new();
     if mach.load_cmds.len() == 0 {
-        return Ok(());
+        return Ok(ops);
     }
     // the order of these operations are important
     // for many dylibs, if you simply modify a load command, and if the size of the new load command is bigger than the older one
@@ -36,20 +84,43 @@ pub fn patch_macho(mach: &Macho, reals_path: &PathBuf, symlink_farm_path: &PathB
     // the first hope is to replace with rpaths, and then see what can be done
     // in any case, the operation is basically a map on load commands at a top level (which changes them to smaller variants) given an rpath
     for rpath in &mach.all_rpaths {
-        rm_rpath(rpath, reals_path)?;
+        ops.push(MachoPatchOp::DeleteRpath {
+            path: reals_path.to_path_buf(),
+            rpath: rpath.clone(),
+        });
     }
-    let lib_name = file_name_as_str(reals_path)?;
+    let lib_name = file_name_as_str(&reals_path.to_path_buf())?;
     let rpath = get_new_rpath(reals_path, symlink_farm_path)?;
-    modify_load_cmds(reals_path, symlink_farm_path, mach)?;
-    add_rpath(&rpath, reals_path)?;
-    set_dylib_id(dylib_id(&lib_name), &reals_path)?;
-    sign_dylib(&reals_path)?;
-
-    Ok(())
+    // sorted, the plan should not depend on hash map order
+    let mut load_cmds: Vec<(&String, &PathBuf)> = mach.load_cmds.iter().collect();
+    load_cmds.sort();
+    for (load_cmd, parent_path) in load_cmds {
+        ops.push(MachoPatchOp::ChangeLoadCommand {
+            path: reals_path.to_path_buf(),
+            old: load_cmd.clone(),
+            new: dylib_id(&file_name_as_str(parent_path)?),
+        });
+    }
+    ops.push(MachoPatchOp::AddRpath {
+        path: reals_path.to_path_buf(),
+        rpath,
+    });
+    ops.push(MachoPatchOp::SetId {
+        path: reals_path.to_path_buf(),
+        id: dylib_id(&lib_name),
+    });
+    ops.push(MachoPatchOp::Sign {
+        path: reals_path.to_path_buf(),
+    });
+    Ok(ops)
 }
 
-fn modify_load_cmds(reals_path: &PathBuf, symlink_farm_path: &PathBuf, mach: &Macho) -> Result<()> {
-    for (load_cmd, parent_path) in &mach.load_cmds {
+fn check_dependencies_in_farm(
+    reals_path: &Path,
+    symlink_farm_path: &Path,
+    mach: &Macho,
+) -> Result<()> {
+    for parent_path in mach.load_cmds.values() {
         let lib_name = file_name_as_str(&parent_path)?;
         let lib_in_farm = symlink_farm_path.join(&lib_name);
         if !lib_in_farm.exists() {
@@ -60,7 +131,6 @@ fn modify_load_cmds(reals_path: &PathBuf, symlink_farm_path: &PathBuf, mach: &Ma
                 lib_name
             );
         }
-        modify_load_command(&load_cmd, &dylib_id(&lib_name), reals_path)?;
     }
     Ok(())
 }
@@ -69,8 +139,7 @@ fn dylib_id(lib_name: &str) -> String {
     format!("@rpath/{}", lib_name)
 }
 
-
-fn get_new_rpath(real_path: &PathBuf, symlink_farm: &PathBuf) -> Result<String> {
+fn get_new_rpath(real_path: &Path, symlink_farm: &Path) -> Result<String> {
     let real_path_dir = real_path.parent().ok_or_else(|| {
         anyhow!(
             "failed in getting parent of real_path while patching it, path={}",
@@ -1,21 +1,50 @@
 // patching libraries to work with the new symlink tree
 // basically all install_name_tool operations
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use anyhow::Result;
 use log::info;
+use serde::Serialize;
 
-use crate::pkg::patch::elf::{patch_elf, patch_elf_for_destination};
+use crate::pkg::patch::elf::{
+    ElfPatchOp, patch_elf, patch_elf_for_destination, plan_patch_elf,
+    plan_patch_elf_for_destination,
+};
+use crate::pkg::patch::macho::{MachoPatchOp, plan_patch_macho};
 use crate::{node::deps::Deps, parse::Binary, pkg::patch::macho::patch_macho};
 
-mod macho;
 mod elf;
+mod macho;
+
+/// an operation done on a binary while patching it, only used to describe the patching (see `build --dry-run`)
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum PatchOp {
+    Elf(ElfPatchOp),
+    Macho(MachoPatchOp),
+}
 
 pub trait LibPatch {
     fn patch(&self, real_path: &PathBuf, symlink_farm_path: &PathBuf) -> Result<()>;
 
-    fn patch_for_destination(&self, dest_path: &PathBuf, real_path: &PathBuf, symlink_farm_path: &PathBuf) -> Result<()>;
+    fn patch_for_destination(
+        &self,
+        dest_path: &PathBuf,
+        real_path: &PathBuf,
+        symlink_farm_path: &PathBuf,
+    ) -> Result<()>;
+
+    /// the operations `patch` would do, nothing is touched
+    fn plan_patch(&self, real_path: &Path, symlink_farm_path: &Path) -> Result<Vec<PatchOp>>;
+
+    /// the operations `patch_for_destination` would do, nothing is touched
+    fn plan_patch_for_destination(
+        &self,
+        dest_path: &Path,
+        real_path: &Path,
+        symlink_farm_path: &Path,
+    ) -> Result<Vec<PatchOp>>;
 }
 
 impl LibPatch for Deps {
@@ -31,16 +60,20 @@ impl LibPatch for Deps {
         }
     }
 
-
-    fn patch_for_destination(&self, dest_path: &PathBuf, real_path: &PathBuf, symlink_farm_path: &PathBuf) -> Result<()> {
+    fn patch_for_destination(
+        &self,
+        dest_path: &PathBuf,
+        real_path: &PathBuf,
+        symlink_farm_path: &PathBuf,
+    ) -> Result<()> {
         match self {
             Deps::Plain => Ok(()),
             Deps::Binary(binary) => {
                 match binary {
-                    Binary::Elf(_) =>  {
+                    Binary::Elf(_) => {
                         patch_elf_for_destination(dest_path, real_path, symlink_farm_path)?;
-                    },
-                    _=> {}
+                    }
+                    _ => {}
                 };
                 Ok(())
             }
@@ -48,9 +81,46 @@ impl LibPatch for Deps {
             Deps::Mock { paths: _ } => Ok(()),
         }
     }
+
+    fn plan_patch(&self, real_path: &Path, symlink_farm_path: &Path) -> Result<Vec<PatchOp>> {
+        match self {
+            Deps::Binary(Binary::Elf(elf)) => {
+                Ok(plan_patch_elf(elf, real_path, symlink_farm_path)?
+                    .into_iter()
+                    .map(PatchOp::Elf)
+                    .collect())
+            }
+            Deps::Binary(Binary::Macho(mach)) => {
+                Ok(plan_patch_macho(mach, real_path, symlink_farm_path)?
+                    .into_iter()
+                    .map(PatchOp::Macho)
+                    .collect())
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    fn plan_patch_for_destination(
+        &self,
+        dest_path: &Path,
+        real_path: &Path,
+        symlink_farm_path: &Path,
+    ) -> Result<Vec<PatchOp>> {
+        match self {
+            Deps::Binary(Binary::Elf(_)) => {
+                Ok(
+                    plan_patch_elf_for_destination(dest_path, real_path, symlink_farm_path)?
+                        .into_iter()
+                        .map(PatchOp::Elf)
+                        .collect(),
+                )
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
 }
 
-pub fn patch_lib(reals_path: &PathBuf, binary: &Binary, symlink_farm_path: &PathBuf) -> Result<()> {
+pub fn patch_lib(reals_path: &Path, binary: &Binary, symlink_farm_path: &Path) -> Result<()> {
     // deps is a vector of shared library names, generated from the graph
     // im assuming that symlink farm location is hardcoded here
     // TODO: make this less hardcoded, we should simply find the relative path of symlink farm from reals
@@ -8,10 +8,12 @@
 use std::path::{Path, PathBuf};
 
 use log::error;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     manifest::Version,
-    node::{Node, Pkg}, paths::file_name_as_str,
+    node::{Node, Pkg},
+    paths::file_name_as_str,
 };
 
 pub trait ExportedFileTree {
@@ -63,7 +65,9 @@ impl ExportedFileTree for Pkg {
                 symlinks: _,
                 sha: _,
             } => path.file_name().map(|p| dist.join("lib").join("l").join(p)),
-            Pkg::BinaryInPath { sha: _ } | Pkg::PlainPyBinaryFile => path.file_name().map(|p| dist.join("bin").join("b").join(p)),
+            Pkg::BinaryInPath { sha: _ } | Pkg::PlainPyBinaryFile => {
+                path.file_name().map(|p| dist.join("bin").join("b").join(p))
+            }
             Pkg::Binary { sha: _ } => None,
             Pkg::Executable => None,
             Pkg::MainPyScript => Some(dist.join("main.py")),
@@ -81,7 +85,6 @@ impl ExportedFileTree for Pkg {
             | Pkg::PlainPyBinaryFile
             | Pkg::MainPyScript
             | Pkg::PrefixPlain(_) => None,
-            
 
             // HACK: currently `reals` actually just means that the directory `reals/r`
             // this is the wrong definition
@@ -99,9 +102,7 @@ impl ExportedFileTree for Pkg {
             }
             | Pkg::Binary { sha }
             | Pkg::BinaryInPath { sha }
-            | Pkg::BinaryInLDPath { symlinks: _, sha } => {
-                reals_path(&sha, &node.path, dist)
-            }
+            | Pkg::BinaryInLDPath { symlinks: _, sha } => reals_path(&sha, &node.path, dist),
             Pkg::PrefixBinary(pkg) | Pkg::ExecPrefixBinary(pkg) => {
                 reals_path(&pkg.sha, &node.path, dist)
             }
@@ -128,17 +129,57 @@ impl ExportedFileTree for Pkg {
             }
             | Pkg::Binary { sha }
             | Pkg::BinaryInPath { sha }
-            | Pkg::BinaryInLDPath {
-                symlinks: _,
-                sha,
-            } => symlink_farm_path(path, dist, sha),
+            | Pkg::BinaryInLDPath { symlinks: _, sha } => symlink_farm_path(path, dist, sha),
+
+            Pkg::ExecPrefixBinary(pkgs) | Pkg::PrefixBinary(pkgs) => {
+                symlink_farm_path(path, dist, &pkgs.sha)
+            }
+
+            Pkg::Executable => symlink_farm_path_from_file_name(path, dist),
+        }
+    }
+}
+
+/// all locations of a single node inside dist
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeLayout {
+    pub destination: Option<PathBuf>,
+    pub reals: Option<PathBuf>,
+    pub symlink_farm: Option<PathBuf>,
+}
 
-            | Pkg::ExecPrefixBinary(pkgs)
-            | Pkg::PrefixBinary(pkgs) => symlink_farm_path(path, dist, &pkgs.sha),
+impl NodeLayout {
+    pub fn of(node: &Node, dist: &PathBuf) -> NodeLayout {
+        NodeLayout {
+            destination: node.pkg.destination(&node.path, dist),
+            reals: node.pkg.reals(node, dist),
+            symlink_farm: node.pkg.symlink_farm(&node.path, dist),
+        }
+    }
 
-            | Pkg::Executable => symlink_farm_path_from_file_name(path, dist),
+    /// same as `of`, but every path is relative to dist
+    /// useful when the layout is stored or compared across builds (the dist can move)
+    pub fn relative(node: &Node, dist: &PathBuf) -> NodeLayout {
+        let strip = |p: Option<PathBuf>| {
+            p.map(|p| match p.strip_prefix(dist) {
+                Ok(rel) => rel.to_path_buf(),
+                Err(_) => p,
+            })
+        };
+        let layout = Self::of(node, dist);
+        NodeLayout {
+            destination: strip(layout.destination),
+            reals: strip(layout.reals),
+            symlink_farm: strip(layout.symlink_farm),
         }
     }
+
+    pub fn paths(&self) -> impl Iterator<Item = &PathBuf> {
+        self.destination
+            .iter()
+            .chain(self.reals.iter())
+            .chain(self.symlink_farm.iter())
+    }
 }
 
 fn site_pkgs_path_in_dist(alias: &str, rel_path: &PathBuf, dist: &PathBuf) -> PathBuf {
@@ -179,17 +220,14 @@ fn reals_path(sha: &str, path: &PathBuf, dist: &PathBuf) -> Option<PathBuf> {
 
 fn file_name_from_sha_and_original_path(path: &PathBuf, sha: &str) -> String {
     match path.extension().and_then(|ext| ext.to_str()) {
-        Some(ext) => {
-            match file_name_as_str(path) {
-                Ok(file_name) => format!("{}_{}", &sha[..4], file_name),
-                Err(_) => format!("{}.{}", &sha[..10], ext),
-            }
+        Some(ext) => match file_name_as_str(path) {
+            Ok(file_name) => format!("{}_{}", &sha[..4], file_name),
+            Err(_) => format!("{}.{}", &sha[..10], ext),
         },
         None => sha.to_string(),
     }
 }
 
-
 fn _reals_path_using_file_name(path: &Path, dist: &Path) -> Option<PathBuf> {
     let fname = match path.file_name() {
         Some(f) => f,
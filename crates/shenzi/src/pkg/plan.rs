@@ -0,0 +1,116 @@
+// the layout of a dist, computed without writing anything (`build --dry-run`)
+// every path inside the plan is relative to dist, the plan of the same environment is the same on every machine
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::PathBuf,
+};
+
+use anyhow::{Context, Result, anyhow};
+use serde::Serialize;
+
+use crate::{
+    gather::{NodeFactory, PythonPathComponent},
+    graph::FileGraph,
+    manifest::Version,
+    node::Node,
+    pkg::{
+        bootstrap::python_path_entries,
+        patch::{LibPatch, PatchOp},
+        paths::{ExportedFileTree, NodeLayout},
+    },
+    warnings::Warning,
+};
+
+#[derive(Debug, Serialize)]
+pub struct DistPlan {
+    // PYTHONPATH entries set by bootstrap.sh
+    pub python_path: Vec<String>,
+    // destination of the main script
+    pub main_script: Option<PathBuf>,
+    // sorted by source
+    pub files: Vec<FilePlan>,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FilePlan {
+    pub source: PathBuf,
+    pub kind: &'static str,
+    #[serde(flatten)]
+    pub layout: NodeLayout,
+    // file name inside the symlink farm -> the reals file of the dependency it points to
+    pub farm_links: BTreeMap<String, PathBuf>,
+    // empty for true copies after the first one, they share the patched reals file
+    pub patches: Vec<PatchOp>,
+}
+
+pub fn plan_dist(
+    graph: &FileGraph<NodeFactory>,
+    comps: &Vec<PythonPathComponent>,
+    version: &Version,
+    main_script_path: &PathBuf,
+    warnings: &[Warning],
+) -> Result<DistPlan> {
+    // an empty dist makes every computed path relative
+    let dist = PathBuf::new();
+    let mut nodes: Vec<&Node> = graph.iter_nodes().collect();
+    nodes.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let mut done_reals = HashSet::new();
+    let mut files = Vec::new();
+    for node in nodes {
+        let file = plan_node(node, graph, &dist, &mut done_reals)
+            .with_context(|| anyhow!("failed in planning export of {}", node.path.display()))?;
+        files.push(file);
+    }
+
+    let main_script = graph
+        .get_node_by_path(main_script_path)
+        .and_then(|n| n.pkg.destination(&n.path, &dist));
+    Ok(DistPlan {
+        python_path: python_path_entries(comps, version)?,
+        main_script,
+        files,
+        warnings: warnings.iter().map(|w| format!("{}", w)).collect(),
+    })
+}
+
+fn plan_node(
+    node: &Node,
+    graph: &FileGraph<NodeFactory>,
+    dist: &PathBuf,
+    done_reals: &mut HashSet<PathBuf>,
+) -> Result<FilePlan> {
+    let layout = NodeLayout::of(node, dist);
+
+    let mut farm_links = BTreeMap::new();
+    if layout.symlink_farm.is_some() {
+        for dep in graph.get_node_dependencies(node) {
+            if let (Some(file_name), Some(dep_reals)) =
+                (dep.path.file_name(), dep.pkg.reals(&dep, dist))
+            {
+                farm_links.insert(file_name.to_string_lossy().to_string(), dep_reals);
+            }
+        }
+    }
+
+    // same order as `move_nodes`: patch reals for its symlink farm, then for the destination
+    let mut patches = Vec::new();
+    if let (Some(reals), Some(farm)) = (&layout.reals, &layout.symlink_farm)
+        && done_reals.insert(reals.clone())
+    {
+        patches.extend(node.deps.plan_patch(reals, farm)?);
+        if let Some(dest) = &layout.destination {
+            patches.extend(node.deps.plan_patch_for_destination(dest, reals, farm)?);
+        }
+    }
+
+    Ok(FilePlan {
+        source: node.path.clone(),
+        kind: node.pkg.kind(),
+        layout,
+        farm_links,
+        patches,
+    })
+}
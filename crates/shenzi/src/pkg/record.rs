@@ -0,0 +1,129 @@
+// a record of every node that was exported to dist
+// it is written in dist after every build, incremental builds compare the new graph against it
+// every path inside dist is stored relative to dist, the dist can be moved around without invalidating the record
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, anyhow};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    digest::make_digest,
+    gather::NodeFactory,
+    graph::FileGraph,
+    node::Node,
+    pkg::{bytecode::BytecodeMode, paths::NodeLayout, strip::StripMode},
+};
+
+pub fn build_record_path(dist: &Path) -> PathBuf {
+    dist.join("build-record.json")
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeRecord {
+    // the original path of the file
+    pub path: PathBuf,
+    // blake3 digest of the original file
+    pub sha: String,
+    // size of the original file
+    pub size: u64,
+    // the `Pkg` variant of the node
+    pub kind: String,
+    pub layout: NodeLayout,
+    // original paths of all dependencies of this node, sorted
+    pub deps: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BuildRecord {
+    pub shenzi_version: String,
+    // `build --bytecode` of the build, sources of unchanged files are gone after a sourceless build
+    #[serde(default)]
+    pub bytecode: BytecodeMode,
+    // `build --zip-packages`, the zipped packages are not in dist as files anymore
+    #[serde(default)]
+    pub zip_packages: bool,
+    // `build --strip`, reals of unchanged nodes keep the strip mode of the build which wrote them
+    #[serde(default)]
+    pub strip: StripMode,
+    // `build --debug-archive`, the archive of an incremental build only has the debug info of the changed nodes
+    #[serde(default)]
+    pub debug_archive: bool,
+    // sorted by path
+    pub nodes: Vec<NodeRecord>,
+}
+
+impl BuildRecord {
+    pub fn from_graph(graph: &FileGraph<NodeFactory>, dist: &PathBuf) -> Result<BuildRecord> {
+        let nodes: Vec<&Node> = graph.iter_nodes().collect();
+        let mut records = nodes
+            .par_iter()
+            .map(|node| {
+                let mut deps: Vec<PathBuf> = graph
+                    .get_node_dependencies(node)
+                    .into_iter()
+                    .map(|n| n.path)
+                    .collect();
+                deps.sort();
+                node_record(node, dist, deps)
+            })
+            .collect::<Result<Vec<NodeRecord>>>()?;
+        records.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(BuildRecord {
+            shenzi_version: env!("CARGO_PKG_VERSION").to_string(),
+            bytecode: BytecodeMode::None,
+            zip_packages: false,
+            strip: StripMode::None,
+            debug_archive: false,
+            nodes: records,
+        })
+    }
+
+    pub fn by_path(&self) -> HashMap<&PathBuf, &NodeRecord> {
+        self.nodes.iter().map(|n| (&n.path, n)).collect()
+    }
+
+    /// every path inside dist (relative to dist) that this build wrote
+    pub fn dist_paths(&self) -> HashSet<&PathBuf> {
+        self.nodes.iter().flat_map(|n| n.layout.paths()).collect()
+    }
+
+    pub fn read(path: &Path) -> Result<BuildRecord> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| anyhow!("failed in reading build record at {}", path.display()))?;
+        serde_json::from_str(&contents)
+            .with_context(|| anyhow!("failed in parsing build record at {}", path.display()))
+    }
+
+    pub fn write(&self, dist: &Path) -> Result<PathBuf> {
+        let path = build_record_path(dist);
+        let contents = serde_json::to_string_pretty(self)?;
+        fs::write(&path, contents)
+            .with_context(|| anyhow!("failed in writing build record at {}", path.display()))?;
+        Ok(path)
+    }
+}
+
+fn node_record(node: &Node, dist: &PathBuf, deps: Vec<PathBuf>) -> Result<NodeRecord> {
+    let sha = match node.pkg.sha() {
+        Some(sha) => sha.to_string(),
+        None => make_digest(&node.path)
+            .with_context(|| anyhow!("failed in computing digest of {}", node.path.display()))?,
+    };
+    let size = fs::metadata(&node.path)
+        .with_context(|| anyhow!("failed in reading metadata of {}", node.path.display()))?
+        .len();
+    Ok(NodeRecord {
+        path: node.path.clone(),
+        sha,
+        size,
+        kind: node.pkg.kind().to_string(),
+        layout: NodeLayout::relative(node, dist),
+        deps,
+    })
+}
@@ -0,0 +1,209 @@
+// the dist is built inside a staging directory next to the final output directory
+// only when the build is complete, the staging directory replaces the output directory
+// if anything fails in between, the staging directory is dropped and the previous output stays as is
+
+use std::{
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use log::info;
+use tempfile::TempDir;
+
+use crate::paths::{absolute_path, marker_file_path};
+
+pub struct StagedDist {
+    target: PathBuf,
+    staging: TempDir,
+    force: bool,
+}
+
+impl StagedDist {
+    /// validates that `target` can be (re)built and creates an empty staging directory for it
+    /// the staging directory is created in the parent of `target`, so that the final swap is a rename on the same file system
+    pub fn new(target: &Path, force: bool) -> Result<Self> {
+        let target = absolute_path(target)?;
+        check_target(&target, force)?;
+        let parent = target.parent().ok_or_else(|| {
+            anyhow!(
+                "output directory does not have a parent, path={}",
+                target.display()
+            )
+        })?;
+        fs::create_dir_all(parent).context(anyhow!(
+            "failed in creating parent of output directory, path={}",
+            parent.display()
+        ))?;
+        // temporary directories are 0700, the dist should have the mode of a directory created with mkdir
+        let staging = tempfile::Builder::new()
+            .prefix(".shenzi-staging-")
+            .permissions(fs::Permissions::from_mode(0o755))
+            .tempdir_in(parent)
+            .context(anyhow!(
+                "failed in creating staging directory in {}",
+                parent.display()
+            ))?;
+        fs::File::create(marker_file_path(staging.path())).context(anyhow!(
+            "failed to create SHENZI marker file in staging directory"
+        ))?;
+        info!(
+            "building dist in staging directory={} target={}",
+            staging.path().display(),
+            target.display()
+        );
+        Ok(Self {
+            target,
+            staging,
+            force,
+        })
+    }
+
+    /// the directory where the dist should be written to
+    pub fn path(&self) -> PathBuf {
+        self.staging.path().to_path_buf()
+    }
+
+    /// replace the output directory with the staging directory, returns the final output directory
+    pub fn commit(self) -> Result<PathBuf> {
+        // the target might have been created while we were building
+        check_target(&self.target, self.force)?;
+        let parent = self
+            .target
+            .parent()
+            .expect("fatal: output directory was validated to have a parent");
+
+        // move the previous output out of the way first, it is deleted when `previous` is dropped
+        let previous = if self.target.exists() {
+            let previous = tempfile::Builder::new()
+                .prefix(".shenzi-previous-")
+                .tempdir_in(parent)
+                .context(anyhow!(
+                    "failed in creating directory for previous output in {}",
+                    parent.display()
+                ))?;
+            let previous_dist = previous.path().join("dist");
+            fs::rename(&self.target, &previous_dist).context(anyhow!(
+                "failed in moving previous output out of the way, path={}",
+                self.target.display()
+            ))?;
+            Some((previous, previous_dist))
+        } else {
+            None
+        };
+
+        let staging = self.staging.keep();
+        if let Err(e) = fs::rename(&staging, &self.target) {
+            if let Some((_, ref previous_dist)) = previous {
+                // best effort, put back what was there before
+                let _ = fs::rename(previous_dist, &self.target);
+            }
+            let _ = fs::remove_dir_all(&staging);
+            return Err(e).context(anyhow!(
+                "failed in moving staging directory to output directory, staging={} target={}",
+                staging.display(),
+                self.target.display()
+            ));
+        }
+        info!("dist written to {}", self.target.display());
+        Ok(self.target)
+    }
+}
+
+/// refuses to touch anything that shenzi did not create
+fn check_target(target: &Path, force: bool) -> Result<()> {
+    if !target.exists() {
+        return Ok(());
+    }
+    if !target.is_dir() {
+        bail!(
+            "output path {} already exists and is not a directory",
+            target.display()
+        );
+    }
+    if !marker_file_path(target).exists() {
+        bail!(
+            "refusing to overwrite {}, it was not created by shenzi (the SHENZI_MARKER file is missing inside it). Delete it yourself or choose another directory using --out",
+            target.display()
+        );
+    }
+    if !force {
+        bail!(
+            "the folder {} already exists, pass --force to replace it or delete it and call `shenzi` again",
+            target.display()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_commit_creates_target() {
+        let tmp = tempfile::tempdir().unwrap();
+        let target = tmp.path().join("dist");
+        let staged = StagedDist::new(&target, false).unwrap();
+        fs::write(staged.path().join("bootstrap.sh"), "").unwrap();
+        staged.commit().unwrap();
+
+        assert!(target.join("bootstrap.sh").exists());
+        assert!(marker_file_path(&target).exists());
+        // only the target should be left in the parent
+        assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_committed_target_is_0755_with_umask() {
+        let tmp = tempfile::tempdir().unwrap();
+        let target = tmp.path().join("dist");
+        StagedDist::new(&target, false).unwrap().commit().unwrap();
+        // a directory created with mkdir has 0777 masked by the umask
+        let plain = tmp.path().join("plain");
+        fs::create_dir(&plain).unwrap();
+        let umasked = fs::metadata(&plain).unwrap().permissions().mode() & 0o777;
+
+        let mode = fs::metadata(&target).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode, 0o755 & umasked);
+    }
+
+    #[test]
+    fn test_existing_target_needs_force() {
+        let tmp = tempfile::tempdir().unwrap();
+        let target = tmp.path().join("dist");
+        StagedDist::new(&target, false).unwrap().commit().unwrap();
+
+        assert!(StagedDist::new(&target, false).is_err());
+
+        let staged = StagedDist::new(&target, true).unwrap();
+        fs::write(staged.path().join("new"), "").unwrap();
+        staged.commit().unwrap();
+        assert!(target.join("new").exists());
+        assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_refuses_directory_without_marker() {
+        let tmp = tempfile::tempdir().unwrap();
+        let target = tmp.path().join("dist");
+        fs::create_dir_all(&target).unwrap();
+        fs::write(target.join("important"), "").unwrap();
+
+        assert!(StagedDist::new(&target, true).is_err());
+        assert!(target.join("important").exists());
+    }
+
+    #[test]
+    fn test_dropped_staging_is_cleaned_up() {
+        let tmp = tempfile::tempdir().unwrap();
+        let target = tmp.path().join("dist");
+        {
+            let staged = StagedDist::new(&target, false).unwrap();
+            fs::write(staged.path().join("partial"), "").unwrap();
+        }
+        assert!(!target.exists());
+        assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,277 @@
+// removing debug info from the copies of binaries in `reals/r`, done before they are patched
+// only the copy in dist is stripped, the original file is never touched
+// strip writes to a temporary file and replaces the copy only on success, a failure leaves the unstripped copy in place
+
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    node::{Node, deps::Deps},
+    parse::Binary,
+    pkg::paths::ExportedFileTree,
+};
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StripMode {
+    /// do not strip anything
+    #[default]
+    None,
+    /// remove debug sections only (`strip --strip-debug`, `strip -S` on macOS)
+    Default,
+    /// also remove symbols which are not needed for dynamic linking (`strip --strip-unneeded`, `strip -x` on macOS)
+    All,
+}
+
+/// bytes saved by stripping a single file
+#[derive(Debug, Clone, Serialize)]
+pub struct StrippedLibrary {
+    // relative to dist
+    pub path: PathBuf,
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+    pub bytes_saved: u64,
+}
+
+impl StrippedLibrary {
+    fn new(reals: &Path, dist: &Path, bytes_before: u64, bytes_after: u64) -> Self {
+        Self {
+            path: reals.strip_prefix(dist).unwrap_or(reals).to_path_buf(),
+            bytes_before,
+            bytes_after,
+            // strip can grow a file which has nothing to remove (alignment)
+            bytes_saved: bytes_before.saturating_sub(bytes_after),
+        }
+    }
+}
+
+/// strip the reals of every binary in `nodes`
+/// with `debug_dir`, the debug info of every ELF file is first copied to `<debug_dir>/<reals file name>.debug`
+pub fn strip_reals(
+    nodes: &[&Node],
+    dist: &Path,
+    mode: StripMode,
+    debug_dir: Option<&Path>,
+) -> Result<Vec<StrippedLibrary>> {
+    if mode == StripMode::None {
+        return Ok(Vec::new());
+    }
+    info!("Step: strip binaries in dist/reals, mode={:?}", mode);
+    if let Some(debug_dir) = debug_dir {
+        fs::create_dir_all(debug_dir)
+            .with_context(|| anyhow!("failed in creating {}", debug_dir.display()))?;
+    }
+    let dist = dist.to_path_buf();
+    let mut done_reals = HashSet::new();
+    let mut stripped = Vec::new();
+    for node in nodes {
+        let (Deps::Binary(binary), Some(reals)) = (&node.deps, node.pkg.reals(node, &dist)) else {
+            continue;
+        };
+        // true copies share their reals
+        if !done_reals.insert(reals.clone()) {
+            continue;
+        }
+        let bytes_before = file_size(&reals)?;
+        if let Some(debug_dir) = debug_dir
+            && let Binary::Elf(_) = binary
+        {
+            let file_name = reals
+                .file_name()
+                .expect("fatal: reals path does not have a file name")
+                .to_string_lossy();
+            let debug_file = debug_dir.join(format!("{}.debug", file_name));
+            if let Err(e) = keep_debug(&reals, &debug_file) {
+                warn!(
+                    "could not collect debug info of {}: {:#}",
+                    node.path.display(),
+                    e
+                );
+            }
+        }
+        if let Err(e) = strip(binary, mode, &reals) {
+            warn!(
+                "could not strip {}, it is kept as is: {:#}",
+                node.path.display(),
+                e
+            );
+            continue;
+        }
+        let bytes_after = file_size(&reals)?;
+        stripped.push(StrippedLibrary::new(
+            &reals,
+            &dist,
+            bytes_before,
+            bytes_after,
+        ));
+    }
+    let saved: u64 = stripped.iter().map(|s| s.bytes_saved).sum();
+    info!(
+        "stripped {} binaries, saved {} bytes",
+        stripped.len(),
+        saved
+    );
+    Ok(stripped)
+}
+
+fn strip(binary: &Binary, mode: StripMode, path: &Path) -> Result<()> {
+    let arg = match (binary, mode) {
+        (_, StripMode::None) => return Ok(()),
+        (Binary::Elf(_), StripMode::Default) => "--strip-debug",
+        (Binary::Elf(_), StripMode::All) => "--strip-unneeded",
+        (Binary::Macho(_), StripMode::Default) => "-S",
+        (Binary::Macho(_), StripMode::All) => "-x",
+    };
+    run_strip(arg, path)
+}
+
+/// strip `path` into a temporary file next to it, which replaces `path` only if strip succeeded
+fn run_strip(arg: &str, path: &Path) -> Result<()> {
+    let dir = path
+        .parent()
+        .ok_or_else(|| anyhow!("path to strip does not have a parent {}", path.display()))?;
+    let tmp = tempfile::Builder::new()
+        .prefix(".shenzi-strip-")
+        .tempfile_in(dir)
+        .with_context(|| anyhow!("failed in creating temporary file in {}", dir.display()))?;
+    let output = Command::new("strip")
+        .arg(arg)
+        .arg("-o")
+        .arg(tmp.path())
+        .arg(path)
+        .output()
+        .context("failed in running strip, is it installed?")?;
+    if !output.status.success() {
+        bail!(
+            "failed in running strip {} path={} status={:?} stderr={}",
+            arg,
+            path.display(),
+            output.status,
+            String::from_utf8_lossy(&output.stderr),
+        );
+    }
+    let permissions = fs::metadata(path)
+        .with_context(|| anyhow!("failed in reading metadata of {}", path.display()))?
+        .permissions();
+    fs::set_permissions(tmp.path(), permissions)
+        .with_context(|| anyhow!("failed in setting permissions of {}", tmp.path().display()))?;
+    tmp.persist(path).with_context(|| {
+        anyhow!(
+            "failed in replacing {} with its stripped copy",
+            path.display()
+        )
+    })?;
+    Ok(())
+}
+
+fn keep_debug(path: &Path, debug_file: &Path) -> Result<()> {
+    let output = Command::new("objcopy")
+        .arg("--only-keep-debug")
+        .arg(path)
+        .arg(debug_file)
+        .output()
+        .context("failed in running objcopy, is binutils installed?")?;
+    if !output.status.success() {
+        bail!(
+            "failed in running objcopy --only-keep-debug path={} status={:?} stderr={}",
+            path.display(),
+            output.status,
+            String::from_utf8_lossy(&output.stderr),
+        );
+    }
+    Ok(())
+}
+
+fn file_size(path: &Path) -> Result<u64> {
+    Ok(fs::metadata(path)
+        .with_context(|| anyhow!("failed in reading metadata of {}", path.display()))?
+        .len())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_strip_reals_none_does_nothing() {
+        let tmp = tempfile::tempdir().unwrap();
+        let debug_dir = tmp.path().join("debug");
+        let stripped = strip_reals(&[], tmp.path(), StripMode::None, Some(&debug_dir)).unwrap();
+        assert!(stripped.is_empty());
+        assert!(!debug_dir.exists());
+    }
+
+    #[test]
+    fn test_stripped_library_bytes() {
+        let dist = Path::new("/dist");
+        let reals = dist.join("reals").join("r").join("abcd_libfoo.so");
+        let lib = StrippedLibrary::new(&reals, dist, 1000, 400);
+        assert_eq!(lib.path, PathBuf::from("reals/r/abcd_libfoo.so"));
+        assert_eq!(lib.bytes_saved, 600);
+        // a file which grew saved nothing
+        let lib = StrippedLibrary::new(&reals, dist, 400, 408);
+        assert_eq!(lib.bytes_saved, 0);
+    }
+
+    #[test]
+    fn test_failed_strip_keeps_the_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("abcd_libfoo.so");
+        fs::write(&path, "not an object file").unwrap();
+        assert!(run_strip("--strip-debug", &path).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "not an object file");
+        // no temporary file is left behind
+        assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_strip_elf_keeps_mode_and_hardlinks() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // needs a C compiler and binutils
+        if ["cc", "strip", "objcopy"]
+            .iter()
+            .any(|tool| Command::new(tool).arg("--version").output().is_err())
+        {
+            return;
+        }
+        let tmp = tempfile::tempdir().unwrap();
+        let source = tmp.path().join("hello.c");
+        fs::write(&source, "int hello(void) { return 42; }\n").unwrap();
+        // the original file in the environment
+        let original = tmp.path().join("libhello.so");
+        let status = Command::new("cc")
+            .args(["-g", "-shared", "-fPIC", "-o"])
+            .arg(&original)
+            .arg(&source)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        fs::set_permissions(&original, fs::Permissions::from_mode(0o751)).unwrap();
+        let original_contents = fs::read(&original).unwrap();
+
+        // copied to dist as a hardlink (`--copy-mode hardlink`)
+        let reals = tmp.path().join("abcd_libhello.so");
+        fs::hard_link(&original, &reals).unwrap();
+        let debug_file = tmp.path().join("abcd_libhello.so.debug");
+        keep_debug(&reals, &debug_file).unwrap();
+        run_strip("--strip-debug", &reals).unwrap();
+
+        assert!(debug_file.exists());
+        assert!(file_size(&reals).unwrap() < original_contents.len() as u64);
+        assert_eq!(
+            fs::metadata(&reals).unwrap().permissions().mode() & 0o7777,
+            0o751
+        );
+        // the original behind the hardlink is untouched
+        assert_eq!(fs::read(&original).unwrap(), original_contents);
+    }
+}
@@ -0,0 +1,278 @@
+// packing the pure python packages of every site-package into `site_packages/<alias>.zip`, imported by python with zipimport
+// thousands of small files become a single file, which is faster to copy, scan and extract
+// a top level entry (`requests`, `six.py`, `requests-2.31.0.dist-info`) stays a directory if it contains shared libraries,
+// a nested site-package or symlinks, or if it is marked unzippable in the workspace
+
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::{OsStr, OsString},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, anyhow};
+use log::info;
+use walkdir::WalkDir;
+use zip::{CompressionMethod, DateTime, ZipWriter, write::FileOptions};
+
+use crate::{
+    gather::{NodeFactory, PythonPathComponent},
+    graph::FileGraph,
+    node::{Pkg, deps::Deps},
+    pkg::paths::site_pkgs_relative_path,
+    site_pkgs::normalize_package_name,
+};
+
+/// `site_packages/<alias>.zip`
+pub fn zip_relative_path(alias: &str) -> PathBuf {
+    PathBuf::from("site_packages").join(format!("{}.zip", alias))
+}
+
+/// zip the pure python packages of every top level site-package in `dist`
+/// returns `comps` with the zips which were written, each right after its site-package
+pub fn zip_site_packages(
+    graph: &FileGraph<NodeFactory>,
+    comps: &[PythonPathComponent],
+    dist: &Path,
+    unzippable: &[String],
+) -> Result<Vec<PythonPathComponent>> {
+    info!("Step: zip pure python packages in site-packages");
+    // alias -> top level entries which stay directories
+    let mut keep: HashMap<&str, HashSet<&OsStr>> = HashMap::new();
+    for node in graph.iter_nodes() {
+        if let (
+            Pkg::SitePackagesBinary {
+                alias, rel_path, ..
+            },
+            Deps::Binary(_),
+        ) = (&node.pkg, &node.deps)
+            && let Some(top) = rel_path.components().next()
+        {
+            keep.entry(alias).or_default().insert(top.as_os_str());
+        }
+    }
+    // python path entries inside a package would disappear with it
+    for comp in comps {
+        if let PythonPathComponent::RelativeToSitePkg {
+            top_level_alias,
+            rel_path,
+        } = comp
+            && let Some(top) = rel_path.components().next()
+        {
+            keep.entry(top_level_alias)
+                .or_default()
+                .insert(top.as_os_str());
+        }
+    }
+    let unzippable: HashSet<String> = unzippable
+        .iter()
+        .map(|p| normalize_package_name(p))
+        .collect();
+
+    let mut zipped = HashSet::new();
+    for comp in comps {
+        let PythonPathComponent::TopLevel { alias } = comp else {
+            continue;
+        };
+        let dir = dist.join(site_pkgs_relative_path(alias));
+        if !dir.exists() {
+            continue;
+        }
+        let kept = keep.get(alias.as_str());
+        let out = dist.join(zip_relative_path(alias));
+        let packed = pack_dir(&dir, &out, |name| {
+            !kept.is_some_and(|k| k.contains(name)) && !unzippable.contains(&package_name(name))
+        })
+        .with_context(|| anyhow!("failed in zipping site-packages {}", dir.display()))?;
+        info!(
+            "zipped {} entries of {} into {}",
+            packed,
+            dir.display(),
+            out.display()
+        );
+        if packed > 0 {
+            zipped.insert(alias.as_str());
+        }
+    }
+    Ok(with_zips(comps, &zipped))
+}
+
+/// `comps` with a `SitePkgZip` after the `TopLevel` of every alias in `zipped`
+/// the zip keeps the place of its site-package on the python path, a later site-package can't shadow it
+pub fn with_zips(
+    comps: &[PythonPathComponent],
+    zipped: &HashSet<&str>,
+) -> Vec<PythonPathComponent> {
+    let mut res = Vec::with_capacity(comps.len() + zipped.len());
+    for comp in comps {
+        res.push(comp.clone());
+        if let PythonPathComponent::TopLevel { alias } = comp
+            && zipped.contains(alias.as_str())
+        {
+            res.push(PythonPathComponent::SitePkgZip {
+                alias: alias.clone(),
+            });
+        }
+    }
+    res
+}
+
+/// `requests` -> `requests`, `six.py` -> `six`
+fn package_name(entry: &OsStr) -> String {
+    let entry = entry.to_string_lossy();
+    normalize_package_name(entry.split('.').next().unwrap_or(&entry))
+}
+
+/// move every top level entry of `dir` accepted by `should_pack` into a zip at `out`
+/// only directories and python files are packed, no zip is written if there is nothing to pack
+/// `__pycache__` directories are left out, zipimport never reads them
+/// returns the number of packed top level entries
+fn pack_dir(dir: &Path, out: &Path, should_pack: impl Fn(&OsStr) -> bool) -> Result<usize> {
+    let mut entries: Vec<(OsString, PathBuf)> = Vec::new();
+    for entry in
+        fs::read_dir(dir).with_context(|| anyhow!("failed in reading {}", dir.display()))?
+    {
+        let entry = entry.context("failed in reading a directory entry")?;
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        let is_python_file = path.extension().is_some_and(|e| e == "py" || e == "pyc");
+        if !(file_type.is_dir() || (file_type.is_file() && is_python_file))
+            || is_pycache(&entry.file_name())
+        {
+            continue;
+        }
+        if should_pack(&entry.file_name()) && !contains_symlink(&path)? {
+            entries.push((entry.file_name(), path));
+        }
+    }
+    if entries.is_empty() {
+        return Ok(0);
+    }
+    entries.sort();
+
+    let file = fs::File::create(out)
+        .with_context(|| anyhow!("failed in creating zip at {}", out.display()))?;
+    let mut writer = ZipWriter::new(file);
+    // fixed timestamps, the same packages give the same zip
+    let options = FileOptions::default()
+        .compression_method(CompressionMethod::Deflated)
+        .last_modified_time(DateTime::default());
+    for (_, path) in &entries {
+        let walk = WalkDir::new(path)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(|e| !is_pycache(e.file_name()));
+        for entry in walk {
+            let entry = entry.with_context(|| anyhow!("failed in walking {}", path.display()))?;
+            let name = entry
+                .path()
+                .strip_prefix(dir)
+                .expect("fatal: walked path is not inside dir")
+                .to_string_lossy()
+                .to_string();
+            if entry.file_type().is_dir() {
+                writer.add_directory(name, options.unix_permissions(0o755))?;
+            } else {
+                writer.start_file(name, options.unix_permissions(0o644))?;
+                let mut f = fs::File::open(entry.path())
+                    .with_context(|| anyhow!("failed in opening {}", entry.path().display()))?;
+                io::copy(&mut f, &mut writer)
+                    .with_context(|| anyhow!("failed in zipping {}", entry.path().display()))?;
+            }
+        }
+    }
+    writer
+        .finish()
+        .with_context(|| anyhow!("failed in finishing zip at {}", out.display()))?;
+
+    for (_, path) in &entries {
+        if path.is_dir() {
+            fs::remove_dir_all(path)
+        } else {
+            fs::remove_file(path)
+        }
+        .with_context(|| anyhow!("failed in removing zipped {}", path.display()))?;
+    }
+    Ok(entries.len())
+}
+
+fn is_pycache(name: &OsStr) -> bool {
+    name == "__pycache__"
+}
+
+fn contains_symlink(path: &Path) -> Result<bool> {
+    for entry in WalkDir::new(path) {
+        let entry = entry.with_context(|| anyhow!("failed in walking {}", path.display()))?;
+        if entry.path_is_symlink() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pack_dir() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("sp_0");
+        fs::create_dir_all(dir.join("pure").join("sub")).unwrap();
+        fs::write(dir.join("pure").join("__init__.py"), "").unwrap();
+        fs::write(dir.join("pure").join("sub").join("data.json"), "{}").unwrap();
+        fs::create_dir_all(dir.join("pure").join("__pycache__")).unwrap();
+        fs::write(
+            dir.join("pure")
+                .join("__pycache__")
+                .join("__init__.cpython-312.pyc"),
+            "",
+        )
+        .unwrap();
+        fs::create_dir_all(dir.join("__pycache__")).unwrap();
+        fs::write(dir.join("__pycache__").join("six.cpython-312.pyc"), "").unwrap();
+        fs::write(dir.join("six.py"), "six").unwrap();
+        fs::write(dir.join("distutils-precedence.pth"), "").unwrap();
+        fs::create_dir_all(dir.join("native")).unwrap();
+        fs::write(dir.join("native").join("__init__.py"), "").unwrap();
+        fs::create_dir_all(dir.join("linked")).unwrap();
+        std::os::unix::fs::symlink("../six.py", dir.join("linked").join("six.py")).unwrap();
+
+        let out = tmp.path().join("sp_0.zip");
+        let packed = pack_dir(&dir, &out, |name| name != "native").unwrap();
+        assert_eq!(packed, 2);
+
+        let mut archive = zip::ZipArchive::new(fs::File::open(&out).unwrap()).unwrap();
+        let mut names: Vec<String> = archive.file_names().map(|n| n.to_string()).collect();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                "pure/",
+                "pure/__init__.py",
+                "pure/sub/",
+                "pure/sub/data.json",
+                "six.py"
+            ]
+        );
+        let mut six = String::new();
+        io::Read::read_to_string(&mut archive.by_name("six.py").unwrap(), &mut six).unwrap();
+        assert_eq!(six, "six");
+
+        assert!(!dir.join("pure").exists());
+        assert!(!dir.join("six.py").exists());
+        assert!(dir.join("native").exists());
+        assert!(dir.join("linked").exists());
+        assert!(dir.join("distutils-precedence.pth").exists());
+        assert!(dir.join("__pycache__").exists());
+    }
+
+    #[test]
+    fn test_package_name() {
+        assert_eq!(package_name(OsStr::new("six.py")), "six");
+        assert_eq!(
+            package_name(OsStr::new("Typing_Extensions")),
+            "typing_extensions"
+        );
+    }
+}
@@ -0,0 +1,153 @@
+// build-report.json, a machine readable summary of a build written into the dist
+// meant for CI dashboards: how long every phase took, what was packaged and how big it is
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{Component, Path, PathBuf},
+    time::Instant,
+};
+
+use anyhow::{Context, Result, anyhow};
+use log::info;
+use serde::Serialize;
+use walkdir::WalkDir;
+
+use crate::{
+    gather::filter::ExcludedFile,
+    pkg::{dedup::DuplicateGroup, record::BuildRecord, strip::StrippedLibrary},
+    warnings::Warning,
+};
+
+pub fn build_report_path(dist: &Path) -> PathBuf {
+    dist.join("build-report.json")
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PhaseTiming {
+    pub phase: String,
+    pub seconds: f64,
+}
+
+/// wall clock time of every phase of a build, in the order the phases ran
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(transparent)]
+pub struct Timings {
+    phases: Vec<PhaseTiming>,
+}
+
+impl Timings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// record the time elapsed since `start` as the duration of `phase`
+    pub fn record_since(&mut self, phase: &str, start: Instant) {
+        let seconds = start.elapsed().as_secs_f64();
+        info!("phase {} took {:.2}s", phase, seconds);
+        self.phases.push(PhaseTiming {
+            phase: phase.to_string(),
+            seconds,
+        });
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BuildReport<'a> {
+    pub shenzi_version: &'static str,
+    pub timings: &'a Timings,
+    // number of nodes per `Pkg` variant
+    pub node_counts: BTreeMap<String, usize>,
+    // total size of the files of every top level entry of site-packages (`numpy`, `numpy.libs`, ...)
+    pub site_packages_bytes: BTreeMap<String, u64>,
+    // size of all regular files in dist (symlinks are not counted, hardlinked files are counted once)
+    pub dist_bytes: u64,
+    // bytes saved by `--strip` for every stripped file, empty without `--strip`
+    pub strip_bytes_saved: u64,
+    pub stripped: &'a [StrippedLibrary],
+    // files left out by the `[files]` rules of the workspace, with the rule which excluded them
+    pub excluded: &'a [ExcludedFile],
+    // bytes saved by `--dedup`, identical plain files are stored once
+    pub dedup_bytes_saved: u64,
+    pub deduplicated: &'a [DuplicateGroup],
+    pub warnings: &'a [Warning],
+}
+
+impl<'a> BuildReport<'a> {
+    pub fn new(
+        record: &BuildRecord,
+        timings: &'a Timings,
+        warnings: &'a [Warning],
+        stripped: &'a [StrippedLibrary],
+        excluded: &'a [ExcludedFile],
+        deduplicated: &'a [DuplicateGroup],
+        dist: &Path,
+    ) -> Result<Self> {
+        let mut node_counts = BTreeMap::new();
+        let mut site_packages_bytes = BTreeMap::new();
+        for node in &record.nodes {
+            *node_counts.entry(node.kind.clone()).or_insert(0) += 1;
+            if let Some(top) = node
+                .layout
+                .destination
+                .as_ref()
+                .and_then(|d| site_packages_top_level(d))
+            {
+                *site_packages_bytes.entry(top).or_insert(0) += node.size;
+            }
+        }
+        Ok(Self {
+            shenzi_version: env!("CARGO_PKG_VERSION"),
+            timings,
+            node_counts,
+            site_packages_bytes,
+            dist_bytes: dir_size(dist)?,
+            strip_bytes_saved: stripped.iter().map(|s| s.bytes_saved).sum(),
+            stripped,
+            excluded,
+            dedup_bytes_saved: deduplicated.iter().map(|g| g.bytes_saved).sum(),
+            deduplicated,
+            warnings,
+        })
+    }
+
+    pub fn write(&self, dist: &Path) -> Result<PathBuf> {
+        let path = build_report_path(dist);
+        let contents = serde_json::to_string_pretty(self)?;
+        fs::write(&path, contents)
+            .with_context(|| anyhow!("failed in writing build report at {}", path.display()))?;
+        Ok(path)
+    }
+}
+
+/// `site_packages/<alias>/<top>/...` -> `<top>`
+fn site_packages_top_level(destination: &Path) -> Option<String> {
+    let mut components = destination.components().filter_map(|c| match c {
+        Component::Normal(c) => Some(c),
+        _ => None,
+    });
+    match (components.next(), components.next(), components.next()) {
+        (Some(first), Some(_alias), Some(top)) if first == "site_packages" => {
+            Some(top.to_string_lossy().to_string())
+        }
+        _ => None,
+    }
+}
+
+fn dir_size(dir: &Path) -> Result<u64> {
+    let mut total = 0;
+    let mut seen = HashSet::new();
+    for entry in WalkDir::new(dir) {
+        let entry = entry.with_context(|| anyhow!("failed in walking {}", dir.display()))?;
+        if entry.file_type().is_file() {
+            let meta = entry.metadata().with_context(|| {
+                anyhow!("failed in reading metadata of {}", entry.path().display())
+            })?;
+            if seen.insert((meta.dev(), meta.ino())) {
+                total += meta.len();
+            }
+        }
+    }
+    Ok(total)
+}
@@ -0,0 +1,206 @@
+// a single executable file containing the whole dist: a bash stub followed by the dist as a tar.gz
+// on the first run the stub extracts the payload into a cache directory keyed by the digest of the payload
+// and execs bootstrap.sh from there, later runs reuse the extracted copy
+
+use std::{
+    fs,
+    io::{self, Write},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, anyhow};
+use log::info;
+
+use crate::{
+    archive::{ArchiveFormat, write_archive},
+    digest::make_digest,
+};
+
+// the stub only needs bash, tar and gzip on the target machine
+// `{{PAYLOAD_LINE}}` is the line where the payload starts, it is always the line after `__SHENZI_PAYLOAD__`
+const STUB_TEMPLATE: &str = r#"#!/usr/bin/env bash
+# self-extracting application generated by shenzi
+set -euo pipefail
+SHENZI_NAME="{{NAME}}"
+SHENZI_DIGEST="{{DIGEST}}"
+CACHE_ROOT="${SHENZI_CACHE_DIR:-${XDG_CACHE_HOME:-$HOME/.cache}/shenzi}"
+TARGET="$CACHE_ROOT/$SHENZI_NAME-$SHENZI_DIGEST"
+# the marker (the digest of the payload) is written before the extracted directory is moved into place, a directory without it is a failed extraction
+# cleaners of old files in the cache can remove a part of an extraction, it is extracted again if bootstrap.sh is gone
+MARKER="$TARGET/.shenzi-extracted"
+intact() {
+    [ -f "$MARKER" ] && [ "$(cat "$MARKER")" = "$SHENZI_DIGEST" ] && [ -f "$TARGET/bootstrap.sh" ]
+}
+if ! intact; then
+    mkdir -p "$CACHE_ROOT"
+    TMP="$(mktemp -d "$CACHE_ROOT/.extract.XXXXXX")"
+    trap 'rm -rf "$TMP"' EXIT
+    tail -n +{{PAYLOAD_LINE}} "$0" | tar -xzf - -C "$TMP"
+    echo "$SHENZI_DIGEST" > "$TMP/$SHENZI_NAME/.shenzi-extracted"
+    if [ -d "$TARGET" ] && ! intact; then
+        rm -rf "$TARGET"
+    fi
+    # another run could have extracted it in the meantime, keep its copy
+    # `-T` never moves into a $TARGET which another run moved into place after the check
+    if mv -T "$TMP/$SHENZI_NAME" "$TARGET" 2>/dev/null || intact; then
+        :
+    elif [ ! -d "$TARGET" ]; then
+        # mv without -T (BSD)
+        mv "$TMP/$SHENZI_NAME" "$TARGET" || intact
+    fi
+    rm -rf "$TMP"
+    trap - EXIT
+fi
+exec bash "$TARGET/bootstrap.sh" "$@"
+__SHENZI_PAYLOAD__
+"#;
+
+/// `dist.run` next to `dist`
+pub fn self_extracting_path(dist: &Path) -> PathBuf {
+    let name = dist
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| "dist".to_string());
+    dist.with_file_name(format!("{}.run", name))
+}
+
+/// write `dist` as a single self extracting executable at `out`
+pub fn write_self_extracting(dist: &Path, out: &Path) -> Result<()> {
+    info!(
+        "writing self extracting executable of {} to {}",
+        dist.display(),
+        out.display()
+    );
+    let name = dist
+        .file_name()
+        .ok_or_else(|| anyhow!("dist path {} does not have a file name", dist.display()))?
+        .to_string_lossy()
+        .to_string();
+
+    let parent = out.parent().unwrap_or(Path::new("."));
+    let payload = tempfile::NamedTempFile::new_in(parent)
+        .context("failed in creating temporary file for the payload")?;
+    write_archive(dist, payload.path(), ArchiveFormat::TarGz)?;
+    let digest = make_digest(&payload.path().to_path_buf())?;
+
+    let stub = stub(&name, &digest[..16]);
+    let mut file =
+        fs::File::create(out).with_context(|| anyhow!("failed in creating {}", out.display()))?;
+    file.write_all(stub.as_bytes())?;
+    io::copy(&mut fs::File::open(payload.path())?, &mut file)
+        .with_context(|| anyhow!("failed in writing payload to {}", out.display()))?;
+    fs::set_permissions(out, fs::Permissions::from_mode(0o755))
+        .with_context(|| anyhow!("failed in making {} executable", out.display()))?;
+    Ok(())
+}
+
+fn stub(name: &str, digest: &str) -> String {
+    let payload_line = STUB_TEMPLATE.lines().count() + 1;
+    STUB_TEMPLATE
+        .replace("{{NAME}}", name)
+        .replace("{{DIGEST}}", digest)
+        .replace("{{PAYLOAD_LINE}}", &payload_line.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::process::Command;
+
+    #[test]
+    fn test_self_extracting_runs_bootstrap() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dist = tmp.path().join("app");
+        fs::create_dir_all(&dist).unwrap();
+        fs::write(dist.join("bootstrap.sh"), "echo \"hello $1\"\n").unwrap();
+
+        let out = self_extracting_path(&dist);
+        assert_eq!(out, tmp.path().join("app.run"));
+        write_self_extracting(&dist, &out).unwrap();
+
+        let cache = tmp.path().join("cache");
+        for _ in 0..2 {
+            let output = Command::new(&out)
+                .arg("world")
+                .env("SHENZI_CACHE_DIR", &cache)
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "{:?}", output);
+            assert_eq!(String::from_utf8_lossy(&output.stdout), "hello world\n");
+        }
+        let extracted: Vec<_> = fs::read_dir(&cache).unwrap().collect();
+        assert_eq!(extracted.len(), 1);
+    }
+
+    #[test]
+    fn test_broken_extraction_is_extracted_again() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dist = tmp.path().join("app");
+        fs::create_dir_all(&dist).unwrap();
+        fs::write(dist.join("bootstrap.sh"), "echo hello\n").unwrap();
+        let out = self_extracting_path(&dist);
+        write_self_extracting(&dist, &out).unwrap();
+
+        let cache = tmp.path().join("cache");
+        let run = || {
+            let output = Command::new(&out)
+                .env("SHENZI_CACHE_DIR", &cache)
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "{:?}", output);
+            assert_eq!(String::from_utf8_lossy(&output.stdout), "hello\n");
+        };
+        run();
+        let extracted = fs::read_dir(&cache)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+
+        // a cleaner of old files removed a part of it, the marker is still there
+        fs::remove_file(extracted.join("bootstrap.sh")).unwrap();
+        run();
+        // the marker of another payload
+        fs::write(extracted.join(".shenzi-extracted"), "0000\n").unwrap();
+        run();
+        assert_ne!(
+            fs::read_to_string(extracted.join(".shenzi-extracted")).unwrap(),
+            "0000\n"
+        );
+    }
+
+    #[test]
+    fn test_concurrent_first_runs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dist = tmp.path().join("app");
+        fs::create_dir_all(&dist).unwrap();
+        fs::write(dist.join("bootstrap.sh"), "echo hello\n").unwrap();
+        let out = self_extracting_path(&dist);
+        write_self_extracting(&dist, &out).unwrap();
+
+        let cache = tmp.path().join("cache");
+        let children: Vec<_> = (0..8)
+            .map(|_| {
+                Command::new(&out)
+                    .env("SHENZI_CACHE_DIR", &cache)
+                    .stdout(std::process::Stdio::piped())
+                    .spawn()
+                    .unwrap()
+            })
+            .collect();
+        for child in children {
+            let output = child.wait_with_output().unwrap();
+            assert!(output.status.success(), "{:?}", output);
+            assert_eq!(String::from_utf8_lossy(&output.stdout), "hello\n");
+        }
+        let extracted: Vec<PathBuf> = fs::read_dir(&cache)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect();
+        assert_eq!(extracted.len(), 1);
+        // no run moved its copy into the directory of another
+        assert!(!extracted[0].join("app").exists());
+    }
+}
@@ -15,6 +15,10 @@ pub enum PythonPathComponent {
         top_level_alias: String,
         rel_path: PathBuf,
     },
+    // `site_packages/<alias>.zip`, the zipped pure python packages of a top level site-package
+    SitePkgZip {
+        alias: String,
+    },
 }
 
 pub fn get_python_path_mapping(
@@ -29,9 +33,9 @@ pub fn get_python_path_mapping(
     let mut res = Vec::new();
     for site_pkg in all_site_pkgs {
         if site_pkg == lib_dynload {
-            continue
+            continue;
         } else if site_pkg == stdlib {
-            continue
+            continue;
         }
         let maybe_alias = site_pkg_by_alias.get(site_pkg);
         match maybe_alias {
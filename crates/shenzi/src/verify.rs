@@ -0,0 +1,332 @@
+// offline check of a finished dist
+// every ELF in the dist is parsed again and the dynamic loader is simulated for each of its DT_NEEDED entries
+// the search order is the same as ld.so: RPATH (only if there is no RUNPATH), LD_LIBRARY_PATH set by bootstrap.sh, RUNPATH
+// a dependency which is not found, or found outside the dist, would break the dist on a machine which does not have it
+// only linux dists can be verified, a dist with Mach-O files is rejected instead of passing without checks
+
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use log::info;
+use walkdir::WalkDir;
+
+use crate::{parse::read_elf_dynamic, paths::is_sys_lib_linux};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Resolution {
+    InDist(PathBuf),
+    OutsideDist(PathBuf),
+    NotFound,
+}
+
+#[derive(Debug)]
+pub struct Problem {
+    // the ELF file, relative to dist
+    pub object: PathBuf,
+    // the path the loader opens the object with (a symlink to it or the object itself), relative to dist
+    pub loaded_from: PathBuf,
+    // the DT_NEEDED entry
+    pub needed: String,
+    pub resolution: Resolution,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.resolution {
+            Resolution::NotFound => write!(f, "not found: ")?,
+            Resolution::OutsideDist(p) => write!(f, "outside dist ({}): ", p.display())?,
+            Resolution::InDist(_) => write!(f, "ok: ")?,
+        };
+        write!(f, "'{}' <- '{}'", self.needed, self.object.display())?;
+        if self.loaded_from != self.object {
+            write!(f, " (loaded from '{}')", self.loaded_from.display())?;
+        }
+        Ok(())
+    }
+}
+
+/// directories the loader falls back to, a dependency found here is not shipped with the dist
+/// the multiarch directories (`/usr/lib/aarch64-linux-gnu`) are the ones of the architecture shenzi was built for
+fn system_lib_dirs() -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = ["/lib64", "/lib", "/usr/lib64", "/usr/lib"]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+    if let Some(triplet) = multiarch_triplet(std::env::consts::ARCH) {
+        dirs.push(Path::new("/lib").join(triplet));
+        dirs.push(Path::new("/usr/lib").join(triplet));
+    }
+    dirs
+}
+
+/// debian multiarch name of `arch` (`std::env::consts::ARCH`)
+fn multiarch_triplet(arch: &str) -> Option<&'static str> {
+    match arch {
+        "x86_64" => Some("x86_64-linux-gnu"),
+        "aarch64" => Some("aarch64-linux-gnu"),
+        "x86" => Some("i386-linux-gnu"),
+        "arm" => Some("arm-linux-gnueabihf"),
+        "powerpc64" => Some("powerpc64le-linux-gnu"),
+        "riscv64" => Some("riscv64-linux-gnu"),
+        "s390x" => Some("s390x-linux-gnu"),
+        _ => None,
+    }
+}
+
+/// returns every DT_NEEDED in the dist which does not resolve inside the dist
+pub fn verify_dist(dist: &Path) -> Result<Vec<Problem>> {
+    let dist = fs::canonicalize(dist)
+        .with_context(|| anyhow!("failed in finding dist at {}", dist.display()))?;
+    let ld_library_path = [dist.join("lib").join("l")];
+
+    // every ELF, along with all paths it can be loaded from
+    let mut loaded_from: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+    let mut symlinks = Vec::new();
+    for entry in WalkDir::new(&dist) {
+        let entry = entry.with_context(|| anyhow!("failed in walking dist {}", dist.display()))?;
+        if entry.path_is_symlink() {
+            symlinks.push(entry.path().to_path_buf());
+        } else if entry.file_type().is_file() {
+            match object_kind(entry.path())? {
+                Some(ObjectKind::Elf) => {
+                    loaded_from
+                        .insert(entry.path().to_path_buf(), vec![entry.path().to_path_buf()]);
+                }
+                Some(ObjectKind::Macho) => bail!(
+                    "only linux dists can be verified, found a Mach-O file {}",
+                    relative_to(entry.path(), &dist).display()
+                ),
+                None => {}
+            }
+        }
+    }
+    for link in symlinks {
+        // dangling links are not our concern here, only links which end at an ELF in the dist
+        if let Ok(target) = fs::canonicalize(&link)
+            && let Some(paths) = loaded_from.get_mut(&target)
+        {
+            paths.push(link);
+        }
+    }
+    info!(
+        "verify: checking {} ELF files in {}",
+        loaded_from.len(),
+        dist.display()
+    );
+
+    let mut objects: Vec<&PathBuf> = loaded_from.keys().collect();
+    objects.sort();
+    let mut problems = Vec::new();
+    for object in objects {
+        let dynamic = match read_elf_dynamic(object)? {
+            Some(dynamic) => dynamic,
+            None => continue,
+        };
+        for path in &loaded_from[object] {
+            let (rpaths, runpaths) = dynamic
+                .search_dirs(path)
+                .with_context(|| anyhow!("failed in resolving rpaths of {}", path.display()))?;
+            let mut dirs = Vec::new();
+            if runpaths.is_empty() {
+                dirs.extend(rpaths);
+            }
+            dirs.extend(ld_library_path.iter().cloned());
+            dirs.extend(runpaths);
+
+            for needed in &dynamic.needed {
+                if is_sys_lib_linux(needed) {
+                    continue;
+                }
+                let resolution = resolve(needed, &dirs, &dist);
+                if let Resolution::InDist(_) = resolution {
+                    continue;
+                }
+                problems.push(Problem {
+                    object: relative_to(object, &dist),
+                    loaded_from: relative_to(path, &dist),
+                    needed: needed.clone(),
+                    resolution,
+                });
+            }
+        }
+    }
+    Ok(problems)
+}
+
+/// search `name` in `dirs` in order, then in the system directories
+pub fn resolve(name: &str, dirs: &[PathBuf], dist: &Path) -> Resolution {
+    let found = if name.contains('/') {
+        Some(PathBuf::from(name)).filter(|p| p.exists())
+    } else {
+        dirs.iter().map(|d| d.join(name)).find(|p| p.exists())
+    };
+    match found {
+        Some(p) => {
+            let p = fs::canonicalize(&p).unwrap_or(p);
+            if p.starts_with(dist) {
+                Resolution::InDist(p)
+            } else {
+                Resolution::OutsideDist(p)
+            }
+        }
+        None => system_lib_dirs()
+            .iter()
+            .map(|d| d.join(name))
+            .find(|p| p.exists())
+            .map(Resolution::OutsideDist)
+            .unwrap_or(Resolution::NotFound),
+    }
+}
+
+/// copy the dist to `dest`, symlinks are copied as symlinks
+pub fn copy_dist(dist: &Path, dest: &Path) -> Result<()> {
+    info!("verify: copying {} to {}", dist.display(), dest.display());
+    for entry in WalkDir::new(dist) {
+        let entry = entry.with_context(|| anyhow!("failed in walking dist {}", dist.display()))?;
+        let rel_path = entry
+            .path()
+            .strip_prefix(dist)
+            .expect("fatal: walked path is not inside dist");
+        let target = dest.join(rel_path);
+        if entry.path_is_symlink() {
+            let link = fs::read_link(entry.path())?;
+            std::os::unix::fs::symlink(&link, &target)
+        } else if entry.file_type().is_dir() {
+            fs::create_dir_all(&target)
+        } else {
+            fs::copy(entry.path(), &target).map(|_| ())
+        }
+        .with_context(|| {
+            anyhow!(
+                "failed in copying {} to {}",
+                entry.path().display(),
+                target.display()
+            )
+        })?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum ObjectKind {
+    Elf,
+    Macho,
+}
+
+fn object_kind(path: &Path) -> Result<Option<ObjectKind>> {
+    let mut magic = [0u8; 8];
+    let mut file =
+        fs::File::open(path).with_context(|| anyhow!("failed in opening {}", path.display()))?;
+    if file.read_exact(&mut magic).is_err() {
+        return Ok(None);
+    }
+    let kind = match magic[..4] {
+        [0x7f, b'E', b'L', b'F'] => Some(ObjectKind::Elf),
+        // 32 and 64 bit, both byte orders
+        [0xfe, 0xed, 0xfa, 0xce | 0xcf] | [0xce | 0xcf, 0xfa, 0xed, 0xfe] => {
+            Some(ObjectKind::Macho)
+        }
+        // universal binaries share their magic with java class files, a class file has its version (>= 45) where a universal binary has its number of architectures
+        [0xca, 0xfe, 0xba, 0xbe]
+            if u32::from_be_bytes([magic[4], magic[5], magic[6], magic[7]]) < 45 =>
+        {
+            Some(ObjectKind::Macho)
+        }
+        _ => None,
+    };
+    Ok(kind)
+}
+
+fn relative_to(path: &Path, dist: &Path) -> PathBuf {
+    path.strip_prefix(dist).unwrap_or(path).to_path_buf()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_resolve_in_search_order() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dist = fs::canonicalize(tmp.path()).unwrap().join("dist");
+        let outside = fs::canonicalize(tmp.path()).unwrap().join("outside");
+        let farm = dist.join("symlinks").join("libfoo.so");
+        fs::create_dir_all(dist.join("reals").join("r")).unwrap();
+        fs::create_dir_all(&farm).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(dist.join("reals").join("r").join("abcd_libbar.so"), "").unwrap();
+        std::os::unix::fs::symlink("../../reals/r/abcd_libbar.so", farm.join("libbar.so")).unwrap();
+        fs::write(outside.join("libbaz.so"), "").unwrap();
+
+        let dirs = vec![farm.clone(), outside.clone()];
+        assert_eq!(
+            resolve("libbar.so", &dirs, &dist),
+            Resolution::InDist(dist.join("reals").join("r").join("abcd_libbar.so"))
+        );
+        assert_eq!(
+            resolve("libbaz.so", &dirs, &dist),
+            Resolution::OutsideDist(outside.join("libbaz.so"))
+        );
+        assert_eq!(
+            resolve("libshenzi-does-not-exist.so", &dirs, &dist),
+            Resolution::NotFound
+        );
+    }
+
+    #[test]
+    fn test_copy_dist_keeps_symlinks() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dist = tmp.path().join("dist");
+        fs::create_dir_all(dist.join("reals").join("r")).unwrap();
+        fs::write(dist.join("reals").join("r").join("lib.so"), "x").unwrap();
+        std::os::unix::fs::symlink("reals/r/lib.so", dist.join("link.so")).unwrap();
+
+        let dest = tmp.path().join("moved");
+        copy_dist(&dist, &dest).unwrap();
+        assert_eq!(
+            fs::read_link(dest.join("link.so")).unwrap(),
+            PathBuf::from("reals/r/lib.so")
+        );
+        assert_eq!(fs::read_to_string(dest.join("link.so")).unwrap(), "x");
+    }
+
+    #[test]
+    fn test_system_lib_dirs_of_arch() {
+        assert_eq!(multiarch_triplet("aarch64"), Some("aarch64-linux-gnu"));
+        assert_eq!(multiarch_triplet("wasm32"), None);
+        let dirs = system_lib_dirs();
+        if let Some(triplet) = multiarch_triplet(std::env::consts::ARCH) {
+            assert!(dirs.contains(&Path::new("/usr/lib").join(triplet)));
+        }
+        assert!(dirs.contains(&PathBuf::from("/usr/lib")));
+    }
+
+    #[test]
+    fn test_rejects_macho_dist() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dist = tmp.path().join("dist");
+        fs::create_dir_all(dist.join("reals").join("r")).unwrap();
+        let mut macho = vec![0xcf, 0xfa, 0xed, 0xfe];
+        macho.extend([0u8; 28]);
+        fs::write(
+            dist.join("reals").join("r").join("abcd_libfoo.dylib"),
+            &macho,
+        )
+        .unwrap();
+        // a java class file is not a universal binary
+        fs::write(
+            dist.join("Main.class"),
+            [0xca, 0xfe, 0xba, 0xbe, 0, 0, 0, 52],
+        )
+        .unwrap();
+        assert_eq!(object_kind(&dist.join("Main.class")).unwrap(), None);
+
+        let err = verify_dist(&dist).unwrap_err();
+        assert!(err.to_string().contains("Mach-O"), "{}", err);
+    }
+}
@@ -1,6 +1,10 @@
-use std::{collections::HashSet, path::{Path, PathBuf}};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
 
-use anyhow::{Result};
+use anyhow::Result;
+use serde::Serialize;
 use walkdir::WalkDir;
 
 use crate::{
@@ -8,6 +12,8 @@ use crate::{
     paths::{get_root_dirs, is_maybe_object_file},
 };
 
+#[derive(Debug, Serialize)]
+#[serde(tag = "code")]
 pub enum Warning {
     // the dependency `dependency` for shared library at path `path` was not found
     W001DependencyNotFound { dependency: String, path: PathBuf },
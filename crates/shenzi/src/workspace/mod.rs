@@ -1,12 +1,12 @@
-use std::path::{Path, PathBuf};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
 
 use anyhow::{Result, anyhow, bail};
 use serde::{Deserialize, Serialize};
 
-use crate::{
-    paths::normalize_path,
-    workspace::packaging::Packaging,
-};
+use crate::{paths::normalize_path, workspace::packaging::Packaging};
 
 mod packaging;
 mod pylock;
@@ -27,6 +27,28 @@ mod pylock;
             "package": ["graphviz"],
             "shared_libraries": ["libhello.so.2"],
         },
+        // packages which stay directories with `build --zip-packages` (they read their own files from disk)
+        "unzippable": ["certifi"],
+        "metadata": {
+            "name": "hello",
+            "version": "1.0.0",
+            "maintainer": "Jane Doe <jane@example.com>",
+            "description": "says hello",
+        },
+        "files": {
+            // globs relative to each site-packages directory (and the stdlib)
+            "exclude": ["*.pyi", "*.pyc"],
+            // wins over exclude
+            "include": ["six.pyi"],
+        },
+        // absolute paths in the manifest under a key are rewritten to the same path under its value (`build --path-map OLD=NEW`)
+        "path_map": {
+            "/builds/ci/.venv": "/app/.venv",
+        },
+        // environment variables of the manifest which are kept, besides PATH, LD_LIBRARY_PATH, LD_PRELOAD and DYLD_*
+        "env": {
+            "allow": ["CUDA_HOME", "CONDA_*"],
+        },
         // not added right now, will be added later
         "binaries": [
             // all binaries we need
@@ -41,6 +63,19 @@ pub struct ShenziWorkspace {
     pub packaging: Packaging,
     pub execution: Execution,
     pub binaries: Vec<String>,
+    // packages which are never zipped by `build --zip-packages`
+    #[serde(default)]
+    pub unzippable: Vec<String>,
+    // used for packages (`build --format deb`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Metadata>,
+    #[serde(default)]
+    pub files: FileRules,
+    // rewrites paths of the manifest, the environment moved after discovery
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub path_map: BTreeMap<PathBuf, PathBuf>,
+    #[serde(default)]
+    pub env: EnvRules,
 
     #[serde(skip)]
     pub workspace_file: PathBuf,
@@ -95,6 +130,30 @@ impl InitializedShenziWorkspace {
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Metadata {
+    pub name: String,
+    pub version: String,
+    pub maintainer: Option<String>,
+    pub description: Option<String>,
+}
+
+/// files in site-packages and the stdlib matching `exclude` are left out of the dist, unless they match `include`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct FileRules {
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    #[serde(default)]
+    pub include: Vec<String>,
+}
+
+/// environment variables of the manifest which are kept, globs like `CONDA_*`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct EnvRules {
+    #[serde(default)]
+    pub allow: Vec<String>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Execution {
     pub main: String,
@@ -123,6 +182,11 @@ pub fn init_workspace() -> Result<()> {
         execution: Execution { main: main_file },
         workspace_file: file_path,
         binaries: binaries.split(",").map(|s| s.to_string()).collect(),
+        unzippable: Vec::new(),
+        metadata: None,
+        files: FileRules::default(),
+        path_map: BTreeMap::new(),
+        env: EnvRules::default(),
     };
 
     let content = toml::to_string(&workspace)?;
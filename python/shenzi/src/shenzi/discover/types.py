@@ -4,6 +4,9 @@ from typing import Any, Literal, Optional
 
 LoadKind = Literal["extension", "dlopen"]
 
+# version of shenzi.json, has to match `MANIFEST_VERSION` in crates/shenzi/src/manifest.rs
+MANIFEST_VERSION = 2
+
 @dataclass(frozen=True)
 class LocalLoad:
     kind: LoadKind
@@ -105,6 +108,7 @@ class ShenziDiscovery:
     
     def to_dict(self) -> dict[str, Any]:
         return {
+            "version": MANIFEST_VERSION,
             "loads": [load.to_dict() for load in self.loads],
             "libs": [lib.to_dict() for lib in self.libs],
             "bins": [bin.to_dict() for bin in self.bins],